@@ -0,0 +1,620 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{Instruction, START_ADDR};
+
+/// Constants can refer to other constants up to this depth, deeper
+/// definitions are considered recursive.
+const MAX_CONST_DEPTH: usize = 32;
+
+/// Names that are operands of the instructions and can't be labels.
+const RESERVED: [&str; 10] = ["I", "DT", "ST", "K", "F", "HF", "B", "R", "PITCH", "LONG"];
+
+/// An error in the source, `line` starts at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// An assembled program: the ROM and where every source line went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub rom: Vec<u8>,
+    /// The address of every source line that emits bytes, in order.
+    pub lines: Vec<(usize, u16)>,
+}
+
+impl Program {
+    /// The address of the bytes emitted by the source line.
+    pub fn addr_of_line(&self, line: usize) -> Option<u16> {
+        self.lines
+            .iter()
+            .find(|(src_line, _)| *src_line == line)
+            .map(|(_, addr)| *addr)
+    }
+
+    /// The source line that emitted the byte at `addr`.
+    pub fn line_of_addr(&self, addr: u16) -> Option<usize> {
+        if addr as usize >= START_ADDR + self.rom.len() {
+            return None;
+        }
+        self.lines
+            .iter()
+            .take_while(|(_, line_addr)| *line_addr <= addr)
+            .last()
+            .map(|(line, _)| *line)
+    }
+}
+
+/// Assemble a program in the Cowgod syntax produced by the disassembler
+/// into a ROM loaded at `START_ADDR`.
+///
+/// Besides the instructions the source can hold:
+/// - labels, `name:` alone or before an instruction
+/// - constants, `NAME = expr` or `NAME EQU expr`
+/// - data, `DB` for bytes and `DW` for big endian words
+/// - sprite literals in `DB`, like `"#..##..#"`: 8 or 16 pixels where
+///   `#` or `X` are lit
+///
+/// Numbers are decimal, `0x` hexadecimal or `0b` binary, and expressions
+/// add or subtract them with labels and constants. Comments start with `;`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_program(source).map(|program| program.rom)
+}
+
+/// Like `assemble`, also mapping source lines to addresses.
+pub fn assemble_program(source: &str) -> Result<Program, AsmError> {
+    let mut asm = Assembler::default();
+
+    // first pass: find the statements and the address of every label
+    let mut addr = START_ADDR;
+    for (idx, text) in source.lines().enumerate() {
+        let line = idx + 1;
+        let Some(statement) = asm.parse_line(line, text, addr)? else {
+            continue;
+        };
+        addr += statement.size(line)?;
+        if addr > 0x10000 {
+            return Err(error(line, "program doesn't fit in memory"));
+        }
+        asm.statements.push(statement);
+    }
+
+    // second pass: emit the bytes, now that every symbol is known
+    let mut program = Program {
+        rom: Vec::new(),
+        lines: Vec::new(),
+    };
+    for statement in &asm.statements {
+        program.lines.push((statement.line, statement.addr));
+        asm.emit(statement, &mut program.rom)?;
+    }
+    Ok(program)
+}
+
+fn error(line: usize, message: impl Into<String>) -> AsmError {
+    AsmError {
+        line,
+        message: message.into(),
+    }
+}
+
+enum Symbol {
+    Label(u16),
+    /// The expression of a constant and the line it's defined at.
+    Const(String, usize),
+}
+
+struct Statement {
+    line: usize,
+    addr: u16,
+    mnemonic: String,
+    operands: Vec<String>,
+}
+
+impl Statement {
+    /// How many bytes the statement emits.
+    fn size(&self, line: usize) -> Result<usize, AsmError> {
+        match self.mnemonic.as_str() {
+            "DB" => self
+                .operands
+                .iter()
+                .map(|op| match sprite_literal(op) {
+                    Some(pixels) if pixels.len() == 8 || pixels.len() == 16 => Ok(pixels.len() / 8),
+                    Some(_) => Err(error(line, "sprite literals are 8 or 16 pixels wide")),
+                    None => Ok(1),
+                })
+                .sum(),
+            "DW" => Ok(2 * self.operands.len()),
+            "LD" if self.operands.len() == 2 && long_operand(&self.operands[1]).is_some() => Ok(4),
+            _ => Ok(2),
+        }
+    }
+}
+
+enum Operand<'a> {
+    Reg(u8),
+    I,
+    /// `[I]`, the memory at I
+    Mem,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Pitch,
+    Long(&'a str),
+    Expr(&'a str),
+}
+
+#[derive(Default)]
+struct Assembler {
+    symbols: HashMap<String, Symbol>,
+    statements: Vec<Statement>,
+}
+
+impl Assembler {
+    /// Record the label and constant definitions of a line and return
+    /// the statement it holds, if any.
+    fn parse_line(
+        &mut self,
+        line: usize,
+        text: &str,
+        addr: usize,
+    ) -> Result<Option<Statement>, AsmError> {
+        let mut text = strip_comment(text).trim();
+        // nothing can start after the last byte of memory
+        let past_end = || error(line, "program doesn't fit in memory");
+
+        if let Some((name, rest)) = text.split_once(':') {
+            let name = name.trim();
+            if is_name(name) {
+                let addr = u16::try_from(addr).map_err(|_| past_end())?;
+                self.define(line, name, Symbol::Label(addr))?;
+                text = rest.trim();
+            }
+        }
+        if text.is_empty() {
+            return Ok(None);
+        }
+
+        let (first, rest) = split_first(text);
+        if let Some(expr) = rest.strip_prefix('=') {
+            self.define(line, first, Symbol::Const(expr.trim().to_string(), line))?;
+            return Ok(None);
+        }
+        let (second, expr) = split_first(rest);
+        if second.eq_ignore_ascii_case("EQU") {
+            self.define(line, first, Symbol::Const(expr.to_string(), line))?;
+            return Ok(None);
+        }
+
+        let operands = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(|op| op.trim().to_string()).collect()
+        };
+        Ok(Some(Statement {
+            line,
+            addr: u16::try_from(addr).map_err(|_| past_end())?,
+            mnemonic: first.to_ascii_uppercase(),
+            operands,
+        }))
+    }
+
+    fn define(&mut self, line: usize, name: &str, symbol: Symbol) -> Result<(), AsmError> {
+        if !is_name(name)
+            || register(name).is_some()
+            || RESERVED.iter().any(|r| r.eq_ignore_ascii_case(name))
+        {
+            return Err(error(line, format!("invalid name `{name}`")));
+        }
+        if self.symbols.insert(name.to_string(), symbol).is_some() {
+            return Err(error(line, format!("`{name}` is already defined")));
+        }
+        Ok(())
+    }
+
+    fn emit(&self, statement: &Statement, rom: &mut Vec<u8>) -> Result<(), AsmError> {
+        let line = statement.line;
+        match statement.mnemonic.as_str() {
+            "DB" => {
+                for op in &statement.operands {
+                    match sprite_literal(op) {
+                        Some(pixels) => {
+                            for row in pixels.chunks(8) {
+                                rom.push(row.iter().fold(0, |byte, lit| byte << 1 | *lit as u8));
+                            }
+                        }
+                        None => rom.push(self.byte(line, op)?),
+                    }
+                }
+            }
+            "DW" => {
+                for op in &statement.operands {
+                    let word = self.value(line, op, 0xFFFF)?;
+                    rom.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            _ => {
+                let operands: Vec<Operand> =
+                    statement.operands.iter().map(|op| operand(op)).collect();
+                let instruction = self.instruction(statement, &operands)?;
+                let op_code = instruction
+                    .encode()
+                    .map_err(|err| error(line, err.to_string()))?;
+                rom.extend_from_slice(&op_code.to_be_bytes());
+
+                if let [Operand::I, Operand::Long(expr)] = operands.as_slice() {
+                    let addr = self.value(line, expr, 0xFFFF)?;
+                    rom.extend_from_slice(&addr.to_be_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn instruction(
+        &self,
+        statement: &Statement,
+        operands: &[Operand],
+    ) -> Result<Instruction, AsmError> {
+        use Instruction::*;
+        use Operand::{Expr, Reg};
+
+        let line = statement.line;
+        let addr = |expr| self.value(line, expr, 0xFFF);
+        let nibble = |expr| self.value(line, expr, 0xF).map(|n| n as u8);
+        let byte = |expr| self.byte(line, expr);
+
+        let instruction = match (statement.mnemonic.as_str(), operands) {
+            ("NOP", []) => Nop,
+            ("SCD", [Expr(n)]) => Scd(nibble(n)?),
+            ("CLS", []) => Cls,
+            ("RET", []) => Ret,
+            ("SCR", []) => Scr,
+            ("SCL", []) => Scl,
+            ("EXIT", []) => Exit,
+            ("LOW", []) => Low,
+            ("HIGH", []) => High,
+            ("JP", [Expr(nnn)]) => Jp(addr(nnn)?),
+            ("JP", [Reg(0), Expr(nnn)]) => JpV0(addr(nnn)?),
+            ("CALL", [Expr(nnn)]) => Call(addr(nnn)?),
+            ("SE", [Reg(x), Reg(y)]) => SeReg { x: *x, y: *y },
+            ("SE", [Reg(x), Expr(nn)]) => SeByte {
+                x: *x,
+                nn: byte(nn)?,
+            },
+            ("SNE", [Reg(x), Reg(y)]) => SneReg { x: *x, y: *y },
+            ("SNE", [Reg(x), Expr(nn)]) => SneByte {
+                x: *x,
+                nn: byte(nn)?,
+            },
+            ("SAVE", [Reg(x), Reg(y)]) => SaveRange { x: *x, y: *y },
+            ("LOAD", [Reg(x), Reg(y)]) => LoadRange { x: *x, y: *y },
+            ("LD", [Reg(x), Reg(y)]) => LdReg { x: *x, y: *y },
+            ("LD", [Reg(x), Operand::Dt]) => LdVxDt(*x),
+            ("LD", [Reg(x), Operand::K]) => LdKey(*x),
+            ("LD", [Reg(x), Operand::Mem]) => Load(*x),
+            ("LD", [Reg(x), Operand::R]) => LoadFlags(*x),
+            ("LD", [Reg(x), Expr(nn)]) => LdByte {
+                x: *x,
+                nn: byte(nn)?,
+            },
+            ("LD", [Operand::I, Operand::Long(_)]) => LdILong,
+            ("LD", [Operand::I, Expr(nnn)]) => LdI(addr(nnn)?),
+            ("LD", [Operand::Dt, Reg(x)]) => LdDt(*x),
+            ("LD", [Operand::St, Reg(x)]) => LdSt(*x),
+            ("LD", [Operand::F, Reg(x)]) => LdF(*x),
+            ("LD", [Operand::Hf, Reg(x)]) => LdHf(*x),
+            ("LD", [Operand::B, Reg(x)]) => Bcd(*x),
+            ("LD", [Operand::Pitch, Reg(x)]) => Pitch(*x),
+            ("LD", [Operand::Mem, Reg(x)]) => Store(*x),
+            ("LD", [Operand::R, Reg(x)]) => SaveFlags(*x),
+            ("ADD", [Reg(x), Reg(y)]) => AddReg { x: *x, y: *y },
+            ("ADD", [Reg(x), Expr(nn)]) => AddByte {
+                x: *x,
+                nn: byte(nn)?,
+            },
+            ("ADD", [Operand::I, Reg(x)]) => AddI(*x),
+            ("OR", [Reg(x), Reg(y)]) => Or { x: *x, y: *y },
+            ("AND", [Reg(x), Reg(y)]) => And { x: *x, y: *y },
+            ("XOR", [Reg(x), Reg(y)]) => Xor { x: *x, y: *y },
+            ("SUB", [Reg(x), Reg(y)]) => Sub { x: *x, y: *y },
+            ("SUBN", [Reg(x), Reg(y)]) => Subn { x: *x, y: *y },
+            ("SHR", [Reg(x)]) => Shr { x: *x, y: *x },
+            ("SHR", [Reg(x), Reg(y)]) => Shr { x: *x, y: *y },
+            ("SHL", [Reg(x)]) => Shl { x: *x, y: *x },
+            ("SHL", [Reg(x), Reg(y)]) => Shl { x: *x, y: *y },
+            ("RND", [Reg(x), Expr(nn)]) => Rnd {
+                x: *x,
+                nn: byte(nn)?,
+            },
+            ("RNDSCR", []) => RandomScreen,
+            ("DRW", [Reg(x), Reg(y), Expr(n)]) => Drw {
+                x: *x,
+                y: *y,
+                n: nibble(n)?,
+            },
+            ("SKP", [Reg(x)]) => Skp(*x),
+            ("SKNP", [Reg(x)]) => Sknp(*x),
+            ("PLANE", [Expr(n)]) => Plane(self.value(line, n, 3)? as u8),
+            ("AUDIO", []) => Audio,
+            (mnemonic, _) => {
+                let message = if KNOWN.contains(&mnemonic) {
+                    format!("invalid operands for {mnemonic}")
+                } else {
+                    format!("unknown instruction `{mnemonic}`")
+                };
+                return Err(error(line, message));
+            }
+        };
+        Ok(instruction)
+    }
+
+    /// A byte, negative values down to -128 are two's complement.
+    fn byte(&self, line: usize, expr: &str) -> Result<u8, AsmError> {
+        let val = self.eval(line, expr, 0)?;
+        if !(-128..=0xFF).contains(&val) {
+            return Err(error(line, format!("{val} doesn't fit in a byte")));
+        }
+        Ok(val as u8)
+    }
+
+    fn value(&self, line: usize, expr: &str, max: u16) -> Result<u16, AsmError> {
+        let val = self.eval(line, expr, 0)?;
+        if !(0..=max as i64).contains(&val) {
+            return Err(error(
+                line,
+                format!("{val} is out of range (0 to 0x{max:X})"),
+            ));
+        }
+        Ok(val as u16)
+    }
+
+    /// Evaluate a sum of numbers and symbols.
+    fn eval(&self, line: usize, expr: &str, depth: usize) -> Result<i64, AsmError> {
+        let expr = expr.trim();
+        if expr.is_empty() {
+            return Err(error(line, "missing value"));
+        }
+
+        let (mut sign, mut rest) = match expr.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, expr),
+        };
+        let mut total: i64 = 0;
+        loop {
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            total = self
+                .term(line, rest[..end].trim(), depth)?
+                .checked_mul(sign)
+                .and_then(|val| total.checked_add(val))
+                .ok_or_else(|| error(line, "value out of range"))?;
+
+            let Some(op) = rest[end..].chars().next() else {
+                return Ok(total);
+            };
+            sign = if op == '-' { -1 } else { 1 };
+            rest = &rest[end + 1..];
+        }
+    }
+
+    fn term(&self, line: usize, term: &str, depth: usize) -> Result<i64, AsmError> {
+        if let Some(val) = number(term) {
+            return Ok(val);
+        }
+        match self.symbols.get(term) {
+            Some(Symbol::Label(addr)) => Ok(*addr as i64),
+            Some(Symbol::Const(expr, const_line)) => {
+                if depth >= MAX_CONST_DEPTH {
+                    return Err(error(line, format!("`{term}` is defined recursively")));
+                }
+                // errors in the definition are reported at its own line
+                self.eval(*const_line, expr, depth + 1)
+            }
+            None if is_name(term) => Err(error(line, format!("undefined symbol `{term}`"))),
+            None => Err(error(line, format!("invalid value `{term}`"))),
+        }
+    }
+}
+
+/// Every mnemonic, to tell unknown ones from bad operands.
+const KNOWN: [&str; 31] = [
+    "NOP", "SCD", "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE",
+    "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "RNDSCR",
+    "DRW", "SKP", "SKNP", "PLANE", "AUDIO",
+];
+
+fn strip_comment(text: &str) -> &str {
+    // sprite literals could hold a `;`
+    let mut quoted = false;
+    for (idx, chr) in text.char_indices() {
+        match chr {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..idx],
+            _ => (),
+        }
+    }
+    text
+}
+
+fn split_first(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((first, rest)) => (first, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|chr| chr.is_ascii_alphabetic() || chr == '_')
+        && chars.all(|chr| chr.is_ascii_alphanumeric() || chr == '_' || chr == '.')
+}
+
+fn number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else if text.starts_with(|chr: char| chr.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['V', 'v'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn long_operand(text: &str) -> Option<&str> {
+    let (first, rest) = split_first(text);
+    first.eq_ignore_ascii_case("LONG").then_some(rest)
+}
+
+fn sprite_literal(text: &str) -> Option<Vec<bool>> {
+    let pixels = text.strip_prefix('"')?.strip_suffix('"')?;
+    Some(
+        pixels
+            .chars()
+            .map(|chr| matches!(chr, '#' | 'X' | 'x'))
+            .collect(),
+    )
+}
+
+fn operand(text: &str) -> Operand<'_> {
+    if let Some(x) = register(text) {
+        return Operand::Reg(x);
+    }
+    if let Some(expr) = long_operand(text) {
+        return Operand::Long(expr);
+    }
+    match text.to_ascii_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::Mem,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "HF" => Operand::Hf,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        "PITCH" => Operand::Pitch,
+        _ => Operand::Expr(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{disassemble, Syntax};
+
+    #[test]
+    fn assemble_program_with_labels() {
+        let source = "
+            SPEED = 2
+            LIMIT EQU SPEED + 0x3E   ; constants can use constants
+
+            start:
+                LD I, ball
+                LD V0, 0
+            loop:   DRW V0, V1, 2
+                ADD V0, SPEED
+                SE V0, LIMIT
+                JP loop
+                JP start
+            ball:
+                DB \"#..##..#\", 0b11110000
+                DW 0x1234
+        ";
+        let program = assemble_program(source).unwrap();
+
+        assert_eq!(
+            vec![
+                0xA2, 0x0E, 0x60, 0x00, 0xD0, 0x12, 0x70, 0x02, 0x30, 0x40, 0x12, 0x04, 0x12, 0x00,
+                0x99, 0xF0, 0x12, 0x34,
+            ],
+            program.rom
+        );
+        assert_eq!(Some(0x204), program.addr_of_line(8));
+        assert_eq!(Some(8), program.line_of_addr(0x205));
+        assert_eq!(None, program.line_of_addr(0x212));
+    }
+
+    #[test]
+    fn line_numbered_errors() {
+        let err = |source| assemble(source).unwrap_err();
+
+        assert_eq!(error(2, "unknown instruction `FOO`"), err("CLS\nFOO V0\n"));
+        assert_eq!(error(1, "invalid operands for LD"), err("LD V0, DT, 1"));
+        assert_eq!(
+            error(3, "undefined symbol `nowhere`"),
+            err("\n\nJP nowhere")
+        );
+        assert_eq!(error(1, "256 doesn't fit in a byte"), err("LD V0, 256"));
+        assert_eq!(error(2, "`a` is already defined"), err("a: CLS\na: CLS"));
+        assert_eq!(
+            error(1, "`A` is defined recursively"),
+            err("A = A\nLD V0, A")
+        );
+        assert_eq!(
+            error(1, "value out of range"),
+            err("LD V0, 0x7FFFFFFFFFFFFFFF + 1")
+        );
+    }
+
+    #[test]
+    fn end_of_memory() {
+        // words up to the last byte of the 64 KiB
+        let full = "DW 0\n".repeat(0x7F00);
+        assert_eq!(0xFE00, assemble(&full).unwrap().len());
+
+        let line = 0x7F01;
+        for after in ["CLS", "end:", "end: CLS"] {
+            let err = assemble(&format!("{full}{after}")).unwrap_err();
+            assert_eq!(error(line, "program doesn't fit in memory"), err);
+        }
+        // constants don't take any memory
+        assert!(assemble(&format!("{full}END = 1")).is_ok());
+
+        let program = assemble_program(&full).unwrap();
+        assert_eq!(Some(0x7F00), program.line_of_addr(0xFFFF));
+        assert_eq!(Some(1), program.line_of_addr(0x200));
+    }
+
+    #[test]
+    fn round_trip_with_disassembler() {
+        let rom = [
+            0x00, 0xFF, // HIGH
+            0xF0, 0x00, 0x02, 0x12, // LD I, LONG data
+            0x22, 0x0C, // CALL sub
+            0x3A, 0x01, // SE VA, 1
+            0x12, 0x00, // JP start
+            0x8A, 0xB6, // sub: SHR VA, VB
+            0xF2, 0x01, // PLANE 2
+            0x00, 0xEE, // RET
+            0x3C, 0x7E, 0x42, // data
+        ];
+
+        let listing = disassemble(&rom, Syntax::Cowgod).to_string();
+        assert_eq!(rom.to_vec(), assemble(&listing).unwrap());
+    }
+}
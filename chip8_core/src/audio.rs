@@ -0,0 +1,179 @@
+use std::mem;
+
+use crate::{C8Emulator, AUDIO_PATTERN_SIZE, DEFAULT_PITCH, TIMER_HZ};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+/// The tone of the beeper outside of the XO-CHIP mode.
+pub const BEEP_HZ: f64 = 440.0;
+/// Loudness of the samples, leaving some headroom to the frontend.
+const AMPLITUDE: f32 = 0.25;
+const PATTERN_BITS: f64 = (AUDIO_PATTERN_SIZE * 8) as f64;
+
+/// Where the sound is in its waveform.
+pub(crate) struct Audio {
+    sample_rate: u32,
+    /// In periods of the beeper, or in bits of the XO-CHIP pattern.
+    phase: f64,
+    /// Samples of the frames run since the last `take_audio`.
+    capture: Option<Vec<f32>>,
+    /// What's left of the sample rate after splitting it in frames.
+    frame_rest: u32,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            phase: 0.0,
+            capture: None,
+            frame_rest: 0,
+        }
+    }
+}
+
+impl C8Emulator {
+    pub fn sample_rate(&self) -> u32 {
+        self.audio.sample_rate
+    }
+
+    /// The rate of the samples produced by `fill_audio`, in Hz.
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.audio.sample_rate = rate.max(1);
+    }
+
+    /// Render the audio of every frame when its timers tick, to be taken
+    /// with `take_audio`. Unlike `fill_audio` the samples follow the
+    /// emulated time, as when recording to a file.
+    pub fn capture_audio(&mut self, enabled: bool) {
+        self.audio.capture = enabled.then(Vec::new);
+        self.audio.frame_rest = 0;
+    }
+
+    /// The samples captured since the last call.
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        self.audio
+            .capture
+            .as_mut()
+            .map(mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Capture the samples of the frame ending, before its timers tick.
+    pub(crate) fn capture_frame(&mut self) {
+        let Some(mut samples) = self.audio.capture.take() else {
+            return;
+        };
+        // rates that aren't a multiple of 60 Hz spread the remainder
+        let total = self.audio.sample_rate + self.audio.frame_rest;
+        self.audio.frame_rest = total % TIMER_HZ;
+        let start = samples.len();
+        samples.resize(start + (total / TIMER_HZ) as usize, 0.0);
+        self.fill_audio(&mut samples[start..]);
+        self.audio.capture = Some(samples);
+    }
+
+    /// Fill `buf` with the next mono samples, in -1.0..=1.0.
+    ///
+    /// While the sound timer is active this is a square wave, or in the
+    /// XO-CHIP mode the audio pattern played at the rate of the pitch
+    /// register. Otherwise it's silence. Frontends call it whenever their
+    /// audio sink needs more samples, the sound follows the timer as it
+    /// is at that time.
+    pub fn fill_audio(&mut self, buf: &mut [f32]) {
+        let audio = &mut self.audio;
+        if self.sound_t == 0 {
+            // the next sound starts at the beginning of its waveform
+            audio.phase = 0.0;
+            buf.fill(0.0);
+            return;
+        }
+
+        let sample_rate = audio.sample_rate as f64;
+        if self.xo_chip {
+            let step = pattern_rate(self.pitch) / sample_rate;
+            for sample in buf {
+                let bit = audio.phase as usize;
+                let on = self.audio_pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                *sample = if on { AMPLITUDE } else { -AMPLITUDE };
+                audio.phase = (audio.phase + step) % PATTERN_BITS;
+            }
+        } else {
+            let step = BEEP_HZ / sample_rate;
+            for sample in buf {
+                *sample = if audio.phase < 0.5 {
+                    AMPLITUDE
+                } else {
+                    -AMPLITUDE
+                };
+                audio.phase = (audio.phase + step) % 1.0;
+            }
+        }
+    }
+}
+
+/// Bits per second of the XO-CHIP pattern for a pitch register value.
+fn pattern_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - DEFAULT_PITCH as f64) / 48.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn beeper() {
+        let mut c8 = C8Emulator::new();
+        c8.set_sample_rate(7040);
+        let mut buf = [1.0; 32];
+
+        c8.fill_audio(&mut buf);
+        assert!(buf.iter().all(|sample| *sample == 0.0));
+
+        c8.sound_t = 2;
+        c8.fill_audio(&mut buf);
+        // 440 Hz at 7040 Hz is 8 samples high then 8 low
+        assert!(buf[..8].iter().all(|sample| *sample == AMPLITUDE));
+        assert!(buf[8..16].iter().all(|sample| *sample == -AMPLITUDE));
+        assert_eq!(buf[..16], buf[16..]);
+    }
+
+    #[test]
+    fn xo_chip_pattern() {
+        let mut c8 = C8Emulator::new();
+        c8.set_xo_chip(true);
+        c8.set_sample_rate(8000);
+        c8.audio_pattern = [0xF0; AUDIO_PATTERN_SIZE];
+        c8.sound_t = 1;
+
+        // 4000 bits per second at the default pitch, 2 samples per bit
+        let mut buf = [0.0; 32];
+        c8.fill_audio(&mut buf);
+        assert!(buf[..8].iter().all(|sample| *sample == AMPLITUDE));
+        assert!(buf[8..16].iter().all(|sample| *sample == -AMPLITUDE));
+        assert_eq!(buf[..16], buf[16..]);
+
+        // an octave higher, a sample per bit
+        c8.pitch = DEFAULT_PITCH + 48;
+        c8.fill_audio(&mut buf);
+        assert_eq!([AMPLITUDE; 4], [buf[0], buf[1], buf[2], buf[3]], "{buf:?}");
+        assert_eq!(-AMPLITUDE, buf[4]);
+    }
+
+    #[test]
+    fn capture_follows_the_frames() {
+        let mut c8 = C8Emulator::new();
+        c8.set_sample_rate(1000);
+        c8.capture_audio(true);
+        c8.sound_t = 2;
+
+        for _ in 0..4 {
+            c8.frame_cycle();
+        }
+        let samples = c8.take_audio();
+        // 16 or 17 samples a frame, only the first 2 frames sound
+        assert_eq!(16 + 17 + 17 + 16, samples.len());
+        assert!(samples[..33].iter().all(|sample| *sample != 0.0));
+        assert!(samples[33..].iter().all(|sample| *sample == 0.0));
+        assert!(c8.take_audio().is_empty());
+    }
+}
@@ -0,0 +1,453 @@
+/// What the CPU sees of the machine around it.
+pub(crate) trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+    /// OUT 1-7, the byte comes from memory at R(X).
+    fn output(&mut self, port: u8, val: u8);
+    /// INP 1-7, the byte goes to memory at R(X) and to D.
+    fn input(&mut self, port: u8) -> u8;
+    /// Whether the external flag EF1-EF4 is asserted.
+    fn flag(&self, n: u8) -> bool;
+}
+
+/// The RCA CDP1802 CPU.
+///
+/// Every instruction takes 2 machine cycles of 8 clock cycles, the long
+/// branches and skips 3. Interrupts and DMA are requested by the machine
+/// between instructions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Cdp1802 {
+    /// The 16 scratchpad registers, any of them can be the program
+    /// counter (selected by P) or the data pointer (selected by X).
+    pub(crate) r: [u16; 16],
+    pub(crate) p: u8,
+    pub(crate) x: u8,
+    pub(crate) d: u8,
+    pub(crate) df: bool,
+    /// X and P saved by an interrupt.
+    pub(crate) t: u8,
+    /// Interrupts enabled.
+    pub(crate) ie: bool,
+    /// The Q output flip-flop.
+    pub(crate) q: bool,
+    /// Stopped by IDL until an interrupt or a DMA cycle.
+    pub(crate) idle: bool,
+}
+
+impl Cdp1802 {
+    pub(crate) fn new() -> Self {
+        let mut cpu = Self {
+            r: [0; 16],
+            p: 0,
+            x: 0,
+            d: 0,
+            df: false,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        };
+        cpu.reset();
+        cpu
+    }
+
+    /// The CLEAR state: the program starts at 0000 with R0 as the
+    /// program counter. The other registers keep their values.
+    pub(crate) fn reset(&mut self) {
+        self.r[0] = 0;
+        self.p = 0;
+        self.x = 0;
+        self.q = false;
+        self.ie = true;
+        self.idle = false;
+    }
+
+    /// Respond to an interrupt request, returning the machine cycles it
+    /// took, 0 if interrupts are disabled. The routine runs with R1 as
+    /// the program counter and R2 as the data pointer.
+    pub(crate) fn interrupt(&mut self) -> u32 {
+        if !self.ie {
+            return 0;
+        }
+        self.t = self.x << 4 | self.p;
+        self.p = 1;
+        self.x = 2;
+        self.ie = false;
+        self.idle = false;
+        1
+    }
+
+    /// A DMA output cycle: the byte at R0 goes to the device.
+    pub(crate) fn dma_out(&mut self, bus: &mut impl Bus) -> u8 {
+        let val = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        val
+    }
+
+    /// Execute an instruction and return the machine cycles it took.
+    pub(crate) fn step(&mut self, bus: &mut impl Bus) -> u32 {
+        if self.idle {
+            return 1;
+        }
+
+        let op_code = self.immediate(bus);
+        let n = op_code & 0xF;
+        match op_code >> 4 {
+            0x0 if n == 0 => self.idle = true,            // IDL
+            0x0 => self.d = bus.read(self.r[n as usize]), // LDN
+            0x1 => self.r[n as usize] = self.r[n as usize].wrapping_add(1), // INC
+            0x2 => self.r[n as usize] = self.r[n as usize].wrapping_sub(1), // DEC
+            0x3 => {
+                // short branches, the second half branches on the opposite
+                let cond = match n & 7 {
+                    0 => true,
+                    1 => self.q,
+                    2 => self.d == 0,
+                    3 => self.df,
+                    ef => bus.flag(ef - 3),
+                };
+                self.short_branch(bus, cond != (n & 8 != 0));
+            }
+            0x4 => self.d = self.load_advance(bus, n), // LDA
+            0x5 => bus.write(self.r[n as usize], self.d), // STR
+            0x6 if n == 0 => self.advance(self.x),     // IRX
+            0x6 if n < 8 => {
+                // OUT
+                let val = self.load_advance(bus, self.x);
+                bus.output(n, val);
+            }
+            0x6 if n == 8 => (), // not an 1802 instruction
+            0x6 => {
+                // INP
+                self.d = bus.input(n - 8);
+                bus.write(self.r[self.x as usize], self.d);
+            }
+            0x7 => self.execute_7(bus, n),
+            0x8 => self.d = self.r[n as usize] as u8, // GLO
+            0x9 => self.d = (self.r[n as usize] >> 8) as u8, // GHI
+            0xA => self.r[n as usize] = self.r[n as usize] & 0xFF00 | self.d as u16, // PLO
+            0xB => self.r[n as usize] = self.r[n as usize] & 0xFF | (self.d as u16) << 8, // PHI
+            0xC => {
+                self.execute_long(bus, n);
+                return 3;
+            }
+            0xD => self.p = n, // SEP
+            0xE => self.x = n, // SEX
+            _ => self.execute_f(bus, n),
+        }
+        2
+    }
+
+    fn execute_7(&mut self, bus: &mut impl Bus, n: u8) {
+        let rx = self.r[self.x as usize];
+        match n {
+            0x0 | 0x1 => {
+                // RET and DIS
+                let val = self.load_advance(bus, self.x);
+                self.x = val >> 4;
+                self.p = val & 0xF;
+                self.ie = n == 0;
+            }
+            0x2 => self.d = self.load_advance(bus, self.x), // LDXA
+            0x3 => {
+                // STXD
+                bus.write(rx, self.d);
+                self.r[self.x as usize] = rx.wrapping_sub(1);
+            }
+            0x4 => self.add(bus.read(rx), self.d, self.df), // ADC
+            0x5 => self.subtract(bus.read(rx), self.d, self.df), // SDB
+            0x6 => {
+                // SHRC
+                let carry = self.df;
+                self.df = self.d & 1 != 0;
+                self.d = self.d >> 1 | (carry as u8) << 7;
+            }
+            0x7 => self.subtract(self.d, bus.read(rx), self.df), // SMB
+            0x8 => bus.write(rx, self.t),                        // SAV
+            0x9 => {
+                // MARK
+                self.t = self.x << 4 | self.p;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            0xA => self.q = false, // REQ
+            0xB => self.q = true,  // SEQ
+            0xC => {
+                // ADCI
+                let val = self.immediate(bus);
+                self.add(val, self.d, self.df);
+            }
+            0xD => {
+                // SDBI
+                let val = self.immediate(bus);
+                self.subtract(val, self.d, self.df);
+            }
+            0xE => {
+                // SHLC
+                let carry = self.df;
+                self.df = self.d & 0x80 != 0;
+                self.d = self.d << 1 | carry as u8;
+            }
+            _ => {
+                // SMBI
+                let val = self.immediate(bus);
+                self.subtract(self.d, val, self.df);
+            }
+        }
+    }
+
+    /// Long branches and skips.
+    fn execute_long(&mut self, bus: &mut impl Bus, n: u8) {
+        let cond = match n & 3 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            _ => self.df,
+        };
+        match n {
+            // NOP
+            0x4 => (),
+            // LBR, LBQ, LBZ, LBDF and the opposite LBNQ, LBNZ, LBNF
+            0x0..=0x3 | 0x9..=0xB => {
+                if cond != (n & 8 != 0) {
+                    let pc = self.r[self.p as usize];
+                    let hi = bus.read(pc);
+                    let lo = bus.read(pc.wrapping_add(1));
+                    self.r[self.p as usize] = u16::from_be_bytes([hi, lo]);
+                } else {
+                    self.skip(2);
+                }
+            }
+            // LSNQ, LSNZ, LSNF
+            0x5..=0x7 => {
+                if !cond {
+                    self.skip(2);
+                }
+            }
+            // LSKP
+            0x8 => self.skip(2),
+            // LSIE
+            0xC => {
+                if self.ie {
+                    self.skip(2);
+                }
+            }
+            // LSQ, LSZ, LSDF
+            _ => {
+                if cond {
+                    self.skip(2);
+                }
+            }
+        }
+    }
+
+    fn execute_f(&mut self, bus: &mut impl Bus, n: u8) {
+        // the immediate forms take the operand after the opcode
+        let val = if n >= 8 && n != 0xE {
+            self.immediate(bus)
+        } else {
+            bus.read(self.r[self.x as usize])
+        };
+        match n & 7 {
+            0 => self.d = val,                     // LDX, LDI
+            1 => self.d |= val,                    // OR, ORI
+            2 => self.d &= val,                    // AND, ANI
+            3 => self.d ^= val,                    // XOR, XRI
+            4 => self.add(val, self.d, false),     // ADD, ADI
+            5 => self.subtract(val, self.d, true), // SD, SDI
+            7 => self.subtract(self.d, val, true), // SM, SMI
+            _ if n == 6 => {
+                // SHR
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            }
+            _ => {
+                // SHL
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+        }
+    }
+
+    /// D = `a` + `b` + `carry`, DF is the carry out.
+    fn add(&mut self, a: u8, b: u8, carry: bool) {
+        let sum = a as u16 + b as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    /// D = `a` - `b` - borrow, where `no_borrow` is DF before and after.
+    fn subtract(&mut self, a: u8, b: u8, no_borrow: bool) {
+        let diff = a as i16 - b as i16 - !no_borrow as i16;
+        self.d = diff as u8;
+        self.df = diff >= 0;
+    }
+
+    fn short_branch(&mut self, bus: &mut impl Bus, cond: bool) {
+        if cond {
+            let pc = self.r[self.p as usize];
+            self.r[self.p as usize] = pc & 0xFF00 | bus.read(pc) as u16;
+        } else {
+            self.skip(1);
+        }
+    }
+
+    fn skip(&mut self, bytes: u16) {
+        self.r[self.p as usize] = self.r[self.p as usize].wrapping_add(bytes);
+    }
+
+    fn advance(&mut self, n: u8) {
+        self.r[n as usize] = self.r[n as usize].wrapping_add(1);
+    }
+
+    /// The byte at R(`n`), then R(`n`) moves to the next one.
+    fn load_advance(&mut self, bus: &mut impl Bus, n: u8) -> u8 {
+        let val = bus.read(self.r[n as usize]);
+        self.advance(n);
+        val
+    }
+
+    fn immediate(&mut self, bus: &mut impl Bus) -> u8 {
+        self.load_advance(bus, self.p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 64 KiB of memory and a log of the outputs.
+    struct TestBus {
+        mem: Vec<u8>,
+        out: Vec<(u8, u8)>,
+        ef: [bool; 4],
+    }
+
+    impl Bus for TestBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.mem[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            self.mem[addr as usize] = val;
+        }
+
+        fn output(&mut self, port: u8, val: u8) {
+            self.out.push((port, val));
+        }
+
+        fn input(&mut self, port: u8) -> u8 {
+            0x40 | port
+        }
+
+        fn flag(&self, n: u8) -> bool {
+            self.ef[n as usize - 1]
+        }
+    }
+
+    fn run(program: &[u8], steps: usize) -> (Cdp1802, TestBus) {
+        let mut bus = TestBus {
+            mem: vec![0; 0x10000],
+            out: Vec::new(),
+            ef: [false, false, true, false],
+        };
+        bus.mem[..program.len()].copy_from_slice(program);
+        let mut cpu = Cdp1802::new();
+        for _ in 0..steps {
+            cpu.step(&mut bus);
+        }
+        (cpu, bus)
+    }
+
+    #[test]
+    fn arithmetic() {
+        let (cpu, _) = run(
+            &[
+                0xF8, 0xF0, // LDI F0
+                0xFC, 0x20, // ADI 20: D = 10, DF = 1
+                0x7C, 0x01, // ADCI 01: D = 12, DF = 0
+                0xFF, 0x13, // SMI 13: D = FF, DF = 0 (borrow)
+                0x7F, 0x00, // SMBI 00: D = FE, DF = 1
+                0xFE, // SHL: D = FC, DF = 1
+                0x76, // SHRC: D = FE, DF = 0
+            ],
+            7,
+        );
+        assert_eq!((0xFE, false), (cpu.d, cpu.df));
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let (cpu, bus) = run(
+            &[
+                0xF8, 0x01, // LDI 01
+                0xB3, // PHI R3
+                0xF8, 0x00, // LDI 00
+                0xA3, // PLO R3: R3 = 0100
+                0xE3, // SEX R3
+                0xF8, 0x5A, // LDI 5A
+                0x73, // STXD: M(0100) = 5A, R3 = 00FF
+                0x13, // INC R3
+                0x61, // OUT 1: 5A, R3 = 0101
+                0x6A, // INP 2: D = M(0101) = 42
+            ],
+            10,
+        );
+        assert_eq!(0x5A, bus.mem[0x100]);
+        assert_eq!(vec![(1, 0x5A)], bus.out);
+        assert_eq!((0x42, 0x42), (cpu.d, bus.mem[0x101]));
+        assert_eq!(0x0101, cpu.r[3]);
+    }
+
+    #[test]
+    fn branches() {
+        let (cpu, _) = run(
+            &[
+                0x36, 0x04, // 00: B3 04, EF3 is asserted
+                0x00, 0x00, // 02: IDL
+                0x3A, 0x00, // 04: BNZ 00, D is 0
+                0xC2, 0x00, 0x10, // 06: LBZ 0010
+            ],
+            3,
+        );
+        assert_eq!(0x10, cpu.r[0]);
+    }
+
+    #[test]
+    fn subroutines_and_interrupts() {
+        let mut program = vec![0; 0x30];
+        program[..6].copy_from_slice(&[
+            0xF8, 0x20, // 00: LDI 20
+            0xA1, // 02: PLO R1, the interrupt routine
+            0xF8, 0x2F, // 03: LDI 2F
+            0xA2, // 05: PLO R2, the stack
+        ]);
+        program[0x20..0x23].copy_from_slice(&[
+            0x7B, // 20: SEQ
+            0x72, // 21: LDXA, R2 = 30
+            0x70, // 22: RET to X=0 P=0, from M(30)
+        ]);
+        let mut bus = TestBus {
+            mem: vec![0; 0x10000],
+            out: Vec::new(),
+            ef: [false; 4],
+        };
+        bus.mem[..program.len()].copy_from_slice(&program);
+        let mut cpu = Cdp1802::new();
+        for _ in 0..4 {
+            cpu.step(&mut bus);
+        }
+
+        // X=0 P=0 are saved in T and the routine is at R1
+        assert_eq!(1, cpu.interrupt());
+        assert_eq!((0x00, 1, 2, false), (cpu.t, cpu.p, cpu.x, cpu.ie));
+        for _ in 0..3 {
+            cpu.step(&mut bus);
+        }
+        assert!(cpu.q);
+        assert_eq!((0, 0, true), (cpu.p, cpu.x, cpu.ie));
+        assert_eq!(0x06, cpu.r[0]);
+    }
+}
@@ -0,0 +1,707 @@
+use std::{collections::HashMap, fmt, fs, path::Path, str::FromStr};
+
+use crate::{golden, C8Emulator, InputScript, Quirks};
+
+/// The manifest of a directory of test ROMs.
+pub const MANIFEST_NAME: &str = "conformance.txt";
+
+/// Every profile, when a ROM doesn't choose.
+const PROFILES: [&str; 4] = ["vip", "chip48", "schip", "xochip"];
+const DEFAULT_FRAMES: u64 = 120;
+/// Test ROMs don't need real speed, only to finish in their frames.
+const DEFAULT_CYCLES: u32 = 500;
+
+/// An error in the manifest, `line` starts at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+/// A picture the test ROMs draw, a tick or a cross, lit pixels are `#`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Glyph {
+    rows: Vec<Vec<bool>>,
+}
+
+impl Glyph {
+    fn parse(pattern: &str) -> Result<Self, String> {
+        let rows: Vec<Vec<bool>> = pattern
+            .split('/')
+            .map(|row| row.trim().chars().map(|chr| chr == '#').collect())
+            .collect();
+        let width = rows[0].len();
+        if width == 0 || rows.iter().any(|row| row.len() != width) {
+            return Err(format!("the rows of {pattern} aren't all as wide"));
+        }
+        Ok(Self { rows })
+    }
+
+    fn width(&self) -> usize {
+        self.rows[0].len()
+    }
+
+    fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Whether the screen shows the glyph with its top left at `x`, `y`.
+    fn is_at(&self, c8: &C8Emulator, x: usize, y: usize) -> bool {
+        let screen = c8.get_screen();
+        self.rows.iter().enumerate().all(|(row, pixels)| {
+            pixels.iter().enumerate().all(|(col, lit)| {
+                let (x, y) = (x + col, y + row);
+                x < screen.width() && y < screen.height() && screen.pixel(x, y) == *lit
+            })
+        })
+    }
+
+    /// Whether the screen shows the glyph anywhere in the area.
+    fn is_in(&self, c8: &C8Emulator, area: &Area) -> bool {
+        let cols = (area.width + 1).saturating_sub(self.width());
+        let rows = (area.height + 1).saturating_sub(self.height());
+        (0..rows).any(|row| (0..cols).any(|col| self.is_at(c8, area.x + col, area.y + row)))
+    }
+}
+
+/// Where a check looks for the glyphs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Area {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Check {
+    name: String,
+    /// Without an area the glyphs must be at `x`, `y`.
+    area: Option<Area>,
+    x: usize,
+    y: usize,
+}
+
+/// A test ROM and where it shows its results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RomSpec {
+    name: String,
+    /// The line of the section, for errors found after parsing it.
+    line: usize,
+    file: String,
+    frames: u64,
+    cycles: u32,
+    profiles: Vec<String>,
+    input: Option<String>,
+    /// Bytes written before running, for the ROMs that read a choice
+    /// from memory. A profile can add its own.
+    pokes: Vec<(Option<String>, u16, u8)>,
+    pass: String,
+    fail: Option<String>,
+    checks: Vec<Check>,
+    /// `(profile, check)` known to fail.
+    expected_failures: Vec<(String, String)>,
+}
+
+/// What's in the manifest of a directory of test ROMs, `conformance.txt`
+/// by default:
+///
+/// ```text
+/// # the pictures of the results
+/// [glyph tick]
+/// pattern = ..# / .#. / #..
+/// [glyph cross]
+/// pattern = #.# / .#. / #.#
+///
+/// [rom flags]
+/// file = 4-flags.ch8
+/// frames = 200
+/// profiles = vip schip xochip
+/// pass = tick
+/// fail = cross
+/// check.carry = 8 2
+/// check.borrow = 8 7
+/// expect-fail = vip:borrow
+/// ```
+///
+/// A ROM runs `frames` frames (120 by default) of `cycles` instructions
+/// (500) under every profile in `profiles` (all of vip, chip48, schip and
+/// xochip by default). Then every check looks for the `pass` or `fail`
+/// glyph with its top left at the column and row given, or anywhere in
+/// the area of `check.<name> = <x> <y> <width> <height>`, a `fail` glyph
+/// in it failing the check. Optionally
+/// `input` names an `InputScript` file, and `poke = <addr> <byte>` or
+/// `poke.<profile> = <addr> <byte>`, in hexadecimal, write to memory
+/// before running. Lines starting with `#` are comments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    glyphs: HashMap<String, Glyph>,
+    roms: Vec<RomSpec>,
+}
+
+impl Manifest {
+    pub fn parse(source: &str) -> Result<Self, ManifestError> {
+        let mut manifest = Manifest::default();
+        // the glyph being defined, or else the ROM is the last one
+        let mut glyph: Option<String> = None;
+
+        for (idx, line) in source.lines().enumerate() {
+            let error = |message: String| ManifestError {
+                line: idx + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                match header.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["glyph", name] => glyph = Some(name.to_string()),
+                    ["rom", name] => {
+                        glyph = None;
+                        manifest.roms.push(RomSpec::new(name, idx + 1));
+                    }
+                    _ => return Err(error(format!("unknown section {header}"))),
+                }
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("expected <key> = <value>".to_string()));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if let Some(name) = &glyph {
+                if key != "pattern" {
+                    return Err(error(format!("unknown glyph key {key}")));
+                }
+                let pattern = Glyph::parse(value).map_err(error)?;
+                manifest.glyphs.insert(name.clone(), pattern);
+            } else {
+                let rom = manifest
+                    .roms
+                    .last_mut()
+                    .ok_or_else(|| error(format!("{key} outside of a section")))?;
+                rom.set(key, value).map_err(error)?;
+            }
+        }
+
+        for rom in &manifest.roms {
+            let glyphs = std::iter::once(&rom.pass).chain(&rom.fail);
+            if let Some(name) = glyphs
+                .into_iter()
+                .find(|name| !manifest.glyphs.contains_key(*name))
+            {
+                return Err(ManifestError {
+                    line: rom.line,
+                    message: format!("unknown glyph {name} in rom {}", rom.name),
+                });
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Run every ROM of the manifest, with the files in `dir`.
+    pub fn run(&self, dir: &Path) -> ConformanceReport {
+        let mut results = Vec::new();
+        for rom in &self.roms {
+            for profile in &rom.profiles {
+                let outcomes = self.run_rom(dir, rom, profile);
+                for (idx, check) in rom.checks.iter().enumerate() {
+                    let expected_failure = rom
+                        .expected_failures
+                        .iter()
+                        .any(|(p, c)| p == profile && *c == check.name);
+                    results.push(CheckResult {
+                        rom: rom.name.clone(),
+                        profile: profile.clone(),
+                        check: check.name.clone(),
+                        outcome: match &outcomes {
+                            Ok(outcomes) => outcomes[idx].clone(),
+                            Err(err) => Outcome::Error(err.clone()),
+                        },
+                        expected: if expected_failure {
+                            Outcome::Fail
+                        } else {
+                            Outcome::Pass
+                        },
+                    });
+                }
+            }
+        }
+        ConformanceReport { results }
+    }
+
+    /// The outcome of every check of the ROM under the profile.
+    fn run_rom(&self, dir: &Path, rom: &RomSpec, profile: &str) -> Result<Vec<Outcome>, String> {
+        let quirks = Quirks::preset(profile).ok_or(format!("unknown profile {profile}"))?;
+        let bytes = fs::read(dir.join(&rom.file)).map_err(|err| format!("{}: {err}", rom.file))?;
+        let input = match &rom.input {
+            Some(file) => {
+                let source =
+                    fs::read_to_string(dir.join(file)).map_err(|err| format!("{file}: {err}"))?;
+                InputScript::parse(&source).map_err(|err| format!("{file}: {err}"))?
+            }
+            None => InputScript::default(),
+        };
+
+        let mut c8 = C8Emulator::with_quirks(quirks);
+        c8.set_xo_chip(profile == "xochip");
+        c8.load(&bytes).map_err(|err| err.to_string())?;
+        for (only, addr, byte) in &rom.pokes {
+            if only.as_deref().is_none_or(|only| only == profile) {
+                c8.write_ram(*addr as usize, *byte)
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+        golden::run_frames(&mut c8, rom.frames, rom.cycles, &input)
+            .map_err(|(frame, err)| format!("frame {frame}: {err}"))?;
+
+        let pass = &self.glyphs[&rom.pass];
+        let fail = rom.fail.as_ref().map(|name| &self.glyphs[name]);
+        Ok(rom
+            .checks
+            .iter()
+            .map(|check| {
+                let shows = |glyph: &Glyph| match &check.area {
+                    Some(area) => glyph.is_in(&c8, area),
+                    None => glyph.is_at(&c8, check.x, check.y),
+                };
+                // a single failure in an area fails it
+                if fail.is_some_and(shows) {
+                    Outcome::Fail
+                } else if shows(pass) {
+                    Outcome::Pass
+                } else {
+                    Outcome::Unknown
+                }
+            })
+            .collect())
+    }
+}
+
+impl RomSpec {
+    fn new(name: &str, line: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            line,
+            file: format!("{name}.ch8"),
+            frames: DEFAULT_FRAMES,
+            cycles: DEFAULT_CYCLES,
+            profiles: PROFILES.iter().map(|name| name.to_string()).collect(),
+            input: None,
+            pokes: Vec::new(),
+            pass: "pass".to_string(),
+            fail: None,
+            checks: Vec::new(),
+            expected_failures: Vec::new(),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn number<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+            value.parse().map_err(|_| format!("invalid {key} {value}"))
+        }
+        let hex = |value: &str| {
+            u16::from_str_radix(value.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("invalid {key} {value}"))
+        };
+
+        match key {
+            "file" => self.file = value.to_string(),
+            "frames" => self.frames = number(key, value)?,
+            "cycles" => self.cycles = number(key, value)?,
+            "profiles" => {
+                self.profiles = value.split_whitespace().map(str::to_string).collect();
+                if let Some(name) = self
+                    .profiles
+                    .iter()
+                    .find(|name| Quirks::preset(name).is_none())
+                {
+                    return Err(format!("unknown profile {name}"));
+                }
+            }
+            "input" => self.input = Some(value.to_string()),
+            "pass" => self.pass = value.to_string(),
+            "fail" => self.fail = Some(value.to_string()),
+            "expect-fail" => {
+                for item in value.split_whitespace() {
+                    let (profile, check) = item
+                        .split_once(':')
+                        .ok_or(format!("expected <profile>:<check>, got {item}"))?;
+                    self.expected_failures
+                        .push((profile.to_string(), check.to_string()));
+                }
+            }
+            _ => {
+                if let Some(name) = key.strip_prefix("check.") {
+                    let numbers = value
+                        .split_whitespace()
+                        .map(|val| number(key, val))
+                        .collect::<Result<Vec<usize>, _>>()?;
+                    let (x, y, area) = match numbers[..] {
+                        [x, y] => (x, y, None),
+                        [x, y, width, height] => (
+                            x,
+                            y,
+                            Some(Area {
+                                x,
+                                y,
+                                width,
+                                height,
+                            }),
+                        ),
+                        _ => return Err(format!("expected <x> <y> [<width> <height>] for {key}")),
+                    };
+                    self.checks.push(Check {
+                        name: name.to_string(),
+                        area,
+                        x,
+                        y,
+                    });
+                } else if key == "poke" || key.starts_with("poke.") {
+                    let only = key.strip_prefix("poke.").map(str::to_string);
+                    let [addr, byte] = value.split_whitespace().collect::<Vec<_>>()[..] else {
+                        return Err(format!("expected <addr> <byte> for {key}"));
+                    };
+                    let byte =
+                        u8::try_from(hex(byte)?).map_err(|_| format!("invalid {key} {byte}"))?;
+                    self.pokes.push((only, hex(addr)?, byte));
+                } else {
+                    return Err(format!("unknown key {key}"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a check found on the screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Fail,
+    /// Neither glyph is there.
+    Unknown,
+    /// The ROM couldn't run: missing files, or a fault.
+    Error(String),
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Pass => "pass",
+            Outcome::Fail => "fail",
+            Outcome::Unknown => "unknown",
+            Outcome::Error(_) => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub rom: String,
+    pub profile: String,
+    pub check: String,
+    pub outcome: Outcome,
+    /// `Pass`, or `Fail` for the known failures.
+    pub expected: Outcome,
+}
+
+impl CheckResult {
+    /// A check expected to pass that doesn't.
+    pub fn is_regression(&self) -> bool {
+        self.expected == Outcome::Pass && self.outcome != Outcome::Pass
+    }
+}
+
+/// The result of every check of every ROM under every profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceReport {
+    pub results: Vec<CheckResult>,
+}
+
+impl ConformanceReport {
+    pub fn regressions(&self) -> impl Iterator<Item = &CheckResult> {
+        self.results.iter().filter(|result| result.is_regression())
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.regressions().next().is_none()
+    }
+
+    /// A line per check, then the totals.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for result in &self.results {
+            let mut outcome = result.outcome.name().to_string();
+            if let Outcome::Error(err) = &result.outcome {
+                outcome = format!("error: {err}");
+            }
+            let note = match (result.is_regression(), &result.outcome) {
+                (true, _) => "  REGRESSION",
+                (false, Outcome::Pass) if result.expected == Outcome::Fail => "  (fixed?)",
+                (false, Outcome::Pass) => "",
+                (false, _) => "  (expected)",
+            };
+            text.push_str(&format!(
+                "{:<12} {:<7} {:<16} {outcome}{note}\n",
+                result.rom, result.profile, result.check
+            ));
+        }
+
+        let count = |outcome: &str| {
+            self.results
+                .iter()
+                .filter(|result| result.outcome.name() == outcome)
+                .count()
+        };
+        text.push_str(&format!(
+            "{} passed, {} failed, {} unknown, {} errors, {} regressions\n",
+            count("pass"),
+            count("fail"),
+            count("unknown"),
+            count("error"),
+            self.regressions().count()
+        ));
+        text
+    }
+
+    /// `{"results": [...], "regressions": n}`, a result has the `rom`,
+    /// `profile`, `check`, `outcome`, `expected` and `regression` keys,
+    /// plus `error` for errors.
+    pub fn to_json(&self) -> String {
+        let results: Vec<String> = self
+            .results
+            .iter()
+            .map(|result| {
+                let error = match &result.outcome {
+                    Outcome::Error(err) => format!(",\"error\":{}", json_string(err)),
+                    _ => String::new(),
+                };
+                format!(
+                    "{{\"rom\":{},\"profile\":{},\"check\":{},\"outcome\":\"{}\",\"expected\":\"{}\",\"regression\":{}{error}}}",
+                    json_string(&result.rom),
+                    json_string(&result.profile),
+                    json_string(&result.check),
+                    result.outcome.name(),
+                    result.expected.name(),
+                    result.is_regression()
+                )
+            })
+            .collect();
+        format!(
+            "{{\"results\":[{}],\"regressions\":{}}}\n",
+            results.join(","),
+            self.regressions().count()
+        )
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for chr in text.chars() {
+        match chr {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            chr if (chr as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", chr as u32)),
+            chr => json.push(chr),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf};
+
+    use super::*;
+
+    /// Draws a tick at 0,0 when 8XY6 shifts VX in place, a cross when it
+    /// shifts VY, as on the VIP.
+    const SHIFT: [u8; 30] = [
+        0x60, 0x03, // 6003 - V0 = 3
+        0x61, 0x07, // 6107 - V1 = 7
+        0x80, 0x16, // 8016 - V0 = V0 or V1 >> 1
+        0x30, 0x01, // 3001 - skip if V0 == 1
+        0x12, 0x10, // 1210 - jump to the cross
+        0xA2, 0x18, // A218 - I = tick
+        0xD2, 0x23, // D223 - draw at V2, V2
+        0x12, 0x0E, // 120E - loop
+        0xA2, 0x1B, // A21B - I = cross
+        0xD2, 0x23, // D223 - draw at V2, V2
+        0x12, 0x14, // 1214 - loop
+        0x00, 0x00, //
+        0x20, 0x40, 0x80, // 218: tick
+        0xA0, 0x40, 0xA0, // 21B: cross
+    ];
+
+    const MANIFEST: &str = "\
+# pictures
+[glyph tick]
+pattern = ..# / .#. / #..
+[glyph cross]
+pattern = #.# / .#. / #.#
+
+[rom shift]
+frames = 2
+profiles = vip schip
+pass = tick
+fail = cross
+check.shift = 0 0
+check.nothing = 10 10
+expect-fail = schip:nothing
+
+[rom missing]
+profiles = vip
+pass = tick
+check.any = 0 0
+";
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chip8-conformance-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("shift.ch8"), SHIFT).unwrap();
+        dir
+    }
+
+    #[test]
+    fn report() {
+        let dir = test_dir("report");
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+        let report = manifest.run(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let outcomes: Vec<(&str, &str, &Outcome)> = report
+            .results
+            .iter()
+            .map(|result| {
+                (
+                    result.profile.as_str(),
+                    result.check.as_str(),
+                    &result.outcome,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("vip", "shift", &Outcome::Fail),
+                ("vip", "nothing", &Outcome::Unknown),
+                ("schip", "shift", &Outcome::Pass),
+                ("schip", "nothing", &Outcome::Unknown),
+            ],
+            outcomes[..4]
+        );
+        assert!(
+            matches!(&report.results[4].outcome, Outcome::Error(err) if err.starts_with("missing.ch8"))
+        );
+
+        let regressions: Vec<(&str, &str)> = report
+            .regressions()
+            .map(|result| (result.profile.as_str(), result.check.as_str()))
+            .collect();
+        assert_eq!(
+            vec![("vip", "shift"), ("vip", "nothing"), ("vip", "any")],
+            regressions
+        );
+        assert!(!report.is_success());
+
+        let text = report.to_text();
+        assert!(
+            text.contains("schip   nothing          unknown  (expected)"),
+            "{text}"
+        );
+        assert!(text.ends_with("1 passed, 1 failed, 2 unknown, 1 errors, 3 regressions\n"));
+        let json = report.to_json();
+        assert!(json.starts_with("{\"results\":[{\"rom\":\"shift\",\"profile\":\"vip\",\"check\":\"shift\",\"outcome\":\"fail\",\"expected\":\"pass\",\"regression\":true}"), "{json}");
+        assert!(json.ends_with("\"regressions\":3}\n"));
+    }
+
+    #[test]
+    fn pokes_per_profile() {
+        // overwriting the first row of the tick only spoils the schip run
+        let dir = test_dir("pokes");
+        let manifest = MANIFEST.replace("expect-fail", "poke.schip = 218 E0\n#");
+        let manifest = Manifest::parse(&manifest).unwrap();
+        let report = manifest.run(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let outcomes: Vec<&Outcome> = report
+            .results
+            .iter()
+            .map(|result| &result.outcome)
+            .collect();
+        assert_eq!(
+            vec![&Outcome::Fail, &Outcome::Unknown, &Outcome::Unknown],
+            outcomes[..3]
+        );
+    }
+
+    #[test]
+    fn manifest_errors() {
+        let error = Manifest::parse("[rom a]\nframes = lots\n").unwrap_err();
+        assert_eq!("line 2: invalid frames lots", error.to_string());
+        assert!(Manifest::parse("frames = 1\n").is_err());
+        assert!(Manifest::parse("[rom a]\nprofiles = vip gameboy\n").is_err());
+        assert!(Manifest::parse("[glyph a]\npattern = ## / #\n").is_err());
+        // the default pass glyph isn't defined
+        let error = Manifest::parse("[rom a]\ncheck.x = 1 2\n[rom b]\n").unwrap_err();
+        assert_eq!(1, error.line);
+        let error = Manifest::parse("[rom a]\ncycles = 4294967296\n").unwrap_err();
+        assert_eq!("line 2: invalid cycles 4294967296", error.to_string());
+        let error = Manifest::parse("[rom a]\ncheck.x = 1 2 3\n").unwrap_err();
+        assert_eq!(
+            "line 2: expected <x> <y> [<width> <height>] for check.x",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn shipped_manifest() {
+        let manifest =
+            Manifest::parse(include_str!("../tests/conformance/conformance.txt")).unwrap();
+        let names: Vec<&str> = manifest.roms.iter().map(|rom| rom.name.as_str()).collect();
+        assert_eq!(vec!["corax", "flags", "quirks", "keypad"], names);
+        let source = include_str!("../tests/conformance/keypad.txt");
+        assert!(InputScript::parse(source).is_ok());
+    }
+
+    #[test]
+    fn area_checks() {
+        let dir = test_dir("areas");
+        let manifest = MANIFEST.replace(
+            "check.nothing = 10 10",
+            "check.around = 0 0 8 5\ncheck.narrow = 1 0 2 3",
+        );
+        let manifest = Manifest::parse(&manifest).unwrap();
+        let report = manifest.run(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let outcomes: Vec<(&str, &Outcome)> = report
+            .results
+            .iter()
+            .map(|result| (result.check.as_str(), &result.outcome))
+            .collect();
+        assert_eq!(
+            vec![
+                ("shift", &Outcome::Fail),
+                ("around", &Outcome::Fail),
+                ("narrow", &Outcome::Unknown),
+                ("shift", &Outcome::Pass),
+                ("around", &Outcome::Pass),
+                ("narrow", &Outcome::Unknown),
+            ],
+            outcomes[..6]
+        );
+    }
+}
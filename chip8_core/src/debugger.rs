@@ -0,0 +1,304 @@
+use std::collections::BTreeSet;
+
+use crate::{C8Emulator, C8Error, Instruction, WatchHit};
+
+/// Why the debugger paused the emulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// A step, or a step over a call, completed.
+    Step,
+    /// The program counter reached a breakpoint, not yet executed.
+    Breakpoint(u16),
+    /// The current subroutine is about to return, for `run_until_return`.
+    AtReturn,
+    /// The current subroutine returned, for `step_out`.
+    Returned,
+    /// An instruction, or the ROM loading, touched watched memory.
+    Watchpoint(WatchHit),
+    /// The instruction at the program counter faulted.
+    Fault(C8Error),
+    /// The program executed 00FD.
+    Halted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Paused,
+    Running,
+    Step,
+    /// Run until the stack is back to this depth.
+    StepOver(usize),
+    /// Run until the stack is below this depth.
+    StepOut(usize),
+    /// Run until a return at this depth is the next instruction.
+    UntilReturn(usize),
+}
+
+/// Breakpoints and stepping around a `C8Emulator`.
+///
+/// Commands only select what to do, the frontend keeps driving the
+/// emulator with `run` and `frame_cycle` so that timers and the display
+/// go on while stepping over long subroutines.
+pub struct Debugger {
+    emulator: C8Emulator,
+    breakpoints: BTreeSet<u16>,
+    mode: Mode,
+    /// Where the last command started, its breakpoint is not hit again.
+    resumed_at: Option<u16>,
+}
+
+impl Debugger {
+    /// Wrap the emulator, paused.
+    pub fn new(emulator: C8Emulator) -> Self {
+        Self {
+            emulator,
+            breakpoints: BTreeSet::new(),
+            mode: Mode::Paused,
+            resumed_at: None,
+        }
+    }
+
+    pub fn emulator(&self) -> &C8Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut C8Emulator {
+        &mut self.emulator
+    }
+
+    pub fn into_emulator(self) -> C8Emulator {
+        self.emulator
+    }
+
+    /// Return false if there was already a breakpoint at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    /// Return false if there was no breakpoint at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused;
+    }
+
+    /// Run until a breakpoint or a fault.
+    pub fn resume(&mut self) {
+        self.start(Mode::Running);
+    }
+
+    /// Execute a single instruction.
+    pub fn step(&mut self) {
+        self.start(Mode::Step);
+    }
+
+    /// Like `step`, but a call runs until its subroutine returns.
+    pub fn step_over(&mut self) {
+        match self.emulator.current_instruction() {
+            Ok(Instruction::Call(_)) => self.start(Mode::StepOver(self.depth())),
+            _ => self.start(Mode::Step),
+        }
+    }
+
+    /// Run until the current subroutine returned to its caller.
+    pub fn step_out(&mut self) {
+        self.start(Mode::StepOut(self.depth()));
+    }
+
+    /// Run until the current subroutine is about to return.
+    pub fn run_until_return(&mut self) {
+        self.start(Mode::UntilReturn(self.depth()));
+    }
+
+    fn start(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.resumed_at = Some(self.emulator.pc());
+    }
+
+    fn depth(&self) -> usize {
+        self.emulator.stack().len()
+    }
+
+    /// Execute up to `cycles` CPU cycles for the current command and
+    /// return why it stopped, if it did. Nothing runs while paused, and
+    /// cycles stop early when a draw waits for the end of the frame.
+    pub fn run(&mut self, cycles: usize) -> Option<StopReason> {
+        for _ in 0..cycles {
+            if self.mode == Mode::Paused || self.emulator.is_waiting_vblank() {
+                return None;
+            }
+            if let Some(reason) = self.cycle() {
+                self.mode = Mode::Paused;
+                return Some(reason);
+            }
+        }
+        None
+    }
+
+    fn cycle(&mut self) -> Option<StopReason> {
+        if let Some(hit) = self.emulator.take_watch_hit() {
+            return Some(StopReason::Watchpoint(hit));
+        }
+        if self.emulator.is_halted() {
+            return Some(StopReason::Halted);
+        }
+
+        let pc = self.emulator.pc();
+        let resumed = self.resumed_at.take() == Some(pc);
+        if !resumed && self.breakpoints.contains(&pc) {
+            return Some(StopReason::Breakpoint(pc));
+        }
+        if let Mode::UntilReturn(depth) = self.mode {
+            if self.depth() == depth && self.emulator.current_instruction() == Ok(Instruction::Ret)
+            {
+                return Some(StopReason::AtReturn);
+            }
+        }
+
+        if let Err(err) = self.emulator.cpu_cycle() {
+            return Some(StopReason::Fault(err));
+        }
+        if let Some(hit) = self.emulator.take_watch_hit() {
+            return Some(StopReason::Watchpoint(hit));
+        }
+
+        match self.mode {
+            Mode::Step => Some(StopReason::Step),
+            Mode::StepOver(depth) if self.depth() <= depth => Some(StopReason::Step),
+            Mode::StepOut(depth) if self.depth() < depth => Some(StopReason::Returned),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: [u8; 12] = [
+        0x22, 0x06, // 200: CALL 206
+        0x70, 0x01, // 202: ADD V0, 1
+        0x12, 0x00, // 204: JP 200
+        0x71, 0x01, // 206: ADD V1, 1
+        0x72, 0x01, // 208: ADD V2, 1
+        0x00, 0xEE, // 20A: RET
+    ];
+
+    fn debugger() -> Debugger {
+        let mut c8 = C8Emulator::new();
+        c8.load(&PROGRAM).unwrap();
+        Debugger::new(c8)
+    }
+
+    #[test]
+    fn paused_until_asked() {
+        let mut dbg = debugger();
+        assert_eq!(None, dbg.run(100));
+        assert_eq!(0x200, dbg.emulator().pc());
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut dbg = debugger();
+        dbg.add_breakpoint(0x202);
+
+        dbg.resume();
+        assert_eq!(Some(StopReason::Breakpoint(0x202)), dbg.run(100));
+        assert_eq!(1, dbg.emulator().v_regs()[1]);
+        assert_eq!(0, dbg.emulator().v_regs()[0]);
+
+        // resuming from the breakpoint doesn't hit it again right away
+        dbg.resume();
+        assert_eq!(Some(StopReason::Breakpoint(0x202)), dbg.run(100));
+        assert_eq!(1, dbg.emulator().v_regs()[0]);
+
+        assert!(dbg.remove_breakpoint(0x202));
+        dbg.resume();
+        assert_eq!(None, dbg.run(100));
+    }
+
+    #[test]
+    fn step_into_and_over() {
+        let mut dbg = debugger();
+
+        dbg.step();
+        assert_eq!(Some(StopReason::Step), dbg.run(100));
+        assert_eq!(0x206, dbg.emulator().pc());
+        assert_eq!(&[0x202], dbg.emulator().stack());
+
+        let mut dbg = debugger();
+        dbg.step_over();
+        assert_eq!(Some(StopReason::Step), dbg.run(100));
+        assert_eq!(0x202, dbg.emulator().pc());
+        assert_eq!(1, dbg.emulator().v_regs()[2]);
+    }
+
+    #[test]
+    fn step_out_and_until_return() {
+        let mut dbg = debugger();
+        dbg.step();
+        dbg.run(1);
+
+        dbg.run_until_return();
+        assert_eq!(Some(StopReason::AtReturn), dbg.run(100));
+        assert_eq!(0x20A, dbg.emulator().pc());
+
+        dbg.step_out();
+        assert_eq!(Some(StopReason::Returned), dbg.run(100));
+        assert_eq!(0x202, dbg.emulator().pc());
+        assert!(dbg.emulator().stack().is_empty());
+    }
+
+    #[test]
+    fn stop_on_watchpoint() {
+        let mut dbg = debugger();
+        dbg.emulator_mut()
+            .add_watchpoint(0x200..=0x20B, crate::WatchKind::Read);
+        // fetching the instructions is not a read
+        dbg.resume();
+        assert_eq!(None, dbg.run(100));
+
+        let mut c8 = C8Emulator::new();
+        c8.add_watchpoint(0x300..=0x300, crate::WatchKind::Write);
+        c8.load(&[0xA3, 0x00, 0xF0, 0x55, 0x12, 0x04]).unwrap();
+        let mut dbg = Debugger::new(c8);
+
+        dbg.resume();
+        let Some(StopReason::Watchpoint(hit)) = dbg.run(10) else {
+            panic!("no watchpoint hit");
+        };
+        assert_eq!(
+            (0x202, Some(Instruction::Store(0))),
+            (hit.pc, hit.instruction)
+        );
+        assert_eq!(0x204, dbg.emulator().pc());
+    }
+
+    #[test]
+    fn stop_on_fault() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&[0x00, 0xEE]).unwrap();
+        let mut dbg = Debugger::new(c8);
+
+        dbg.resume();
+        assert_eq!(
+            Some(StopReason::Fault(C8Error::StackUnderflow)),
+            dbg.run(10)
+        );
+        assert!(dbg.is_paused());
+    }
+}
@@ -0,0 +1,490 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use crate::{Instruction, START_ADDR};
+
+/// Data bytes are grouped in lines of at most this many bytes.
+const DATA_PER_LINE: usize = 8;
+/// The end of the largest memory, the 64 KiB of XO-CHIP.
+const MEMORY_END: usize = 0x10000;
+
+/// Mnemonic syntax of the listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// The high level syntax of the Octo assembler.
+    Octo,
+    /// The syntax of Cowgod's technical reference, extended with the
+    /// SUPER-CHIP and XO-CHIP instructions. This is the syntax read by
+    /// the assembler.
+    Cowgod,
+}
+
+/// What a line of the listing holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    /// An instruction reached by following the program flow.
+    Code(Instruction),
+    /// Bytes never reached as code, like sprites.
+    Data,
+}
+
+/// A line of the listing: the bytes at `addr` and how they read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    /// Auto label of the address, if anything refers to it.
+    pub label: Option<String>,
+    pub kind: LineKind,
+    pub text: String,
+}
+
+/// The disassembly of a ROM, printed as source that includes the
+/// address and the bytes of every line in a comment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub syntax: Syntax,
+    pub lines: Vec<Line>,
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comment = match self.syntax {
+            Syntax::Octo => '#',
+            Syntax::Cowgod => ';',
+        };
+
+        for line in &self.lines {
+            if let Some(label) = &line.label {
+                match self.syntax {
+                    Syntax::Octo => writeln!(f, ": {label}")?,
+                    Syntax::Cowgod => writeln!(f, "{label}:")?,
+                }
+            }
+
+            let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{b:02X}")).collect();
+            writeln!(
+                f,
+                "    {:<28} {comment} {:03X}: {}",
+                line.text,
+                line.addr,
+                bytes.join(" ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassemble a ROM loaded at `START_ADDR`.
+///
+/// Code is told apart from data by following the program flow from the
+/// first instruction through jumps, calls and skips. Jumps and calls
+/// targets get a label, as well as the addresses loaded into I. Bytes
+/// past the end of the 64 KiB aren't addressable and are left out.
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> Listing {
+    let rom = &rom[..rom.len().min(MEMORY_END - START_ADDR)];
+    let code = trace_code(rom);
+
+    let mut labels = HashMap::new();
+    for instruction in code.values() {
+        let (prefix, addr) = match *instruction {
+            Instruction::Call(addr) => ("sub", addr),
+            Instruction::Jp(addr) => ("label", addr),
+            Instruction::LdI(addr) => ("data", addr),
+            _ => continue,
+        };
+        // labels in the middle of an instruction couldn't be printed
+        let inside_code = code
+            .range(..addr)
+            .next_back()
+            .is_some_and(|(start, code)| start + code.size() > addr);
+        if rom_offset(rom, addr).is_some() && !inside_code {
+            // calls win over jumps, and jumps over data
+            let label = labels.entry(addr).or_insert(prefix);
+            if rank(prefix) < rank(label) {
+                *label = prefix;
+            }
+        }
+    }
+    let labels: HashMap<u16, String> = labels
+        .into_iter()
+        .map(|(addr, prefix)| (addr, format!("{prefix}_{addr:03X}")))
+        .collect();
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = (START_ADDR + offset) as u16;
+        let label = labels.get(&addr).cloned();
+
+        if let Some(instruction) = code.get(&addr) {
+            let size = instruction.size() as usize;
+            let bytes = rom[offset..offset + size].to_vec();
+            let long_addr = (size == 4).then(|| u16::from_be_bytes([bytes[2], bytes[3]]));
+            let text = format_instruction(instruction, syntax, long_addr, &|addr| {
+                labels.get(&addr).cloned()
+            });
+
+            lines.push(Line {
+                addr,
+                bytes,
+                label,
+                kind: LineKind::Code(*instruction),
+                text,
+            });
+            offset += size;
+        } else {
+            // data runs until the next code, label or the line is full
+            let mut end = offset + 1;
+            while end < rom.len() && end - offset < DATA_PER_LINE {
+                let next = (START_ADDR + end) as u16;
+                if code.contains_key(&next) || labels.contains_key(&next) {
+                    break;
+                }
+                end += 1;
+            }
+
+            let bytes = rom[offset..end].to_vec();
+            let text = format_data(&bytes, syntax);
+            lines.push(Line {
+                addr,
+                bytes,
+                label,
+                kind: LineKind::Data,
+                text,
+            });
+            offset = end;
+        }
+    }
+
+    Listing { syntax, lines }
+}
+
+fn rank(prefix: &str) -> u8 {
+    match prefix {
+        "sub" => 0,
+        "label" => 1,
+        _ => 2,
+    }
+}
+
+fn rom_offset(rom: &[u8], addr: u16) -> Option<usize> {
+    let offset = (addr as usize).checked_sub(START_ADDR)?;
+    (offset < rom.len()).then_some(offset)
+}
+
+/// Follow the program flow from the start of the ROM and return the
+/// instructions reached, by address.
+fn trace_code(rom: &[u8]) -> BTreeMap<u16, Instruction> {
+    let mut code = BTreeMap::new();
+    // bytes already decoded as part of an instruction
+    let mut claimed = vec![false; rom.len()];
+    let mut pending = vec![START_ADDR as u16];
+
+    while let Some(addr) = pending.pop() {
+        if code.contains_key(&addr) {
+            continue;
+        }
+        let Some(offset) = rom_offset(rom, addr) else {
+            continue;
+        };
+        if offset + 1 >= rom.len() {
+            continue;
+        }
+
+        let op_code = u16::from_be_bytes([rom[offset], rom[offset + 1]]);
+        let Ok(instruction) = Instruction::decode(op_code) else {
+            continue;
+        };
+        let size = instruction.size() as usize;
+        if offset + size > rom.len() || claimed[offset..offset + size].iter().any(|c| *c) {
+            // a jump in the middle of another instruction
+            continue;
+        }
+        claimed[offset..offset + size].fill(true);
+        code.insert(addr, instruction);
+
+        // the flow ends at the end of memory
+        let Some(next) = addr.checked_add(size as u16) else {
+            if let Instruction::Jp(target) | Instruction::Call(target) = instruction {
+                pending.push(target);
+            }
+            continue;
+        };
+        match instruction {
+            Instruction::Jp(target) => pending.push(target),
+            Instruction::Call(target) => {
+                pending.push(target);
+                pending.push(next);
+            }
+            // jump tables and exits end the flow
+            Instruction::Ret | Instruction::Exit | Instruction::JpV0(_) => (),
+            Instruction::SeByte { .. }
+            | Instruction::SneByte { .. }
+            | Instruction::SeReg { .. }
+            | Instruction::SneReg { .. }
+            | Instruction::Skp(_)
+            | Instruction::Sknp(_) => {
+                pending.push(next);
+                // the skipped instruction may be the long I load
+                let long = rom_offset(rom, next)
+                    .is_some_and(|offset| rom[offset..].starts_with(&[0xF0, 0x00]));
+                pending.extend(next.checked_add(if long { 4 } else { 2 }));
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    code
+}
+
+fn format_data(bytes: &[u8], syntax: Syntax) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{b:02X}")).collect();
+    match syntax {
+        Syntax::Octo => bytes.join(" "),
+        Syntax::Cowgod => format!("DB {}", bytes.join(", ")),
+    }
+}
+
+/// Format an instruction, `long_addr` is the address following F000
+/// and `label` names the addresses that have one.
+fn format_instruction(
+    instruction: &Instruction,
+    syntax: Syntax,
+    long_addr: Option<u16>,
+    label: &dyn Fn(u16) -> Option<String>,
+) -> String {
+    match syntax {
+        Syntax::Octo => format_octo(instruction, long_addr, label),
+        Syntax::Cowgod => format_cowgod(instruction, long_addr, label),
+    }
+}
+
+fn format_cowgod(
+    instruction: &Instruction,
+    long_addr: Option<u16>,
+    label: &dyn Fn(u16) -> Option<String>,
+) -> String {
+    use Instruction::*;
+
+    let addr = |addr: u16| label(addr).unwrap_or_else(|| format!("0x{addr:03X}"));
+
+    match *instruction {
+        Nop => "NOP".to_string(),
+        Scd(n) => format!("SCD {n}"),
+        Cls => "CLS".to_string(),
+        Ret => "RET".to_string(),
+        Scr => "SCR".to_string(),
+        Scl => "SCL".to_string(),
+        Exit => "EXIT".to_string(),
+        Low => "LOW".to_string(),
+        High => "HIGH".to_string(),
+        Jp(nnn) => format!("JP {}", addr(nnn)),
+        Call(nnn) => format!("CALL {}", addr(nnn)),
+        SeByte { x, nn } => format!("SE V{x:X}, 0x{nn:02X}"),
+        SneByte { x, nn } => format!("SNE V{x:X}, 0x{nn:02X}"),
+        SeReg { x, y } => format!("SE V{x:X}, V{y:X}"),
+        SaveRange { x, y } => format!("SAVE V{x:X}, V{y:X}"),
+        LoadRange { x, y } => format!("LOAD V{x:X}, V{y:X}"),
+        LdByte { x, nn } => format!("LD V{x:X}, 0x{nn:02X}"),
+        AddByte { x, nn } => format!("ADD V{x:X}, 0x{nn:02X}"),
+        LdReg { x, y } => format!("LD V{x:X}, V{y:X}"),
+        Or { x, y } => format!("OR V{x:X}, V{y:X}"),
+        And { x, y } => format!("AND V{x:X}, V{y:X}"),
+        Xor { x, y } => format!("XOR V{x:X}, V{y:X}"),
+        AddReg { x, y } => format!("ADD V{x:X}, V{y:X}"),
+        Sub { x, y } => format!("SUB V{x:X}, V{y:X}"),
+        Shr { x, y } => format!("SHR V{x:X}, V{y:X}"),
+        Subn { x, y } => format!("SUBN V{x:X}, V{y:X}"),
+        Shl { x, y } => format!("SHL V{x:X}, V{y:X}"),
+        SneReg { x, y } => format!("SNE V{x:X}, V{y:X}"),
+        LdI(nnn) => format!("LD I, {}", addr(nnn)),
+        JpV0(nnn) => format!("JP V0, 0x{nnn:03X}"),
+        Rnd { x, nn } => format!("RND V{x:X}, 0x{nn:02X}"),
+        RandomScreen => "RNDSCR".to_string(),
+        Drw { x, y, n } => format!("DRW V{x:X}, V{y:X}, {n}"),
+        Skp(x) => format!("SKP V{x:X}"),
+        Sknp(x) => format!("SKNP V{x:X}"),
+        LdILong => match long_addr {
+            Some(nnnn) => format!("LD I, LONG {}", long(nnnn, label)),
+            None => "LD I, LONG".to_string(),
+        },
+        Plane(n) => format!("PLANE {n}"),
+        Audio => "AUDIO".to_string(),
+        LdVxDt(x) => format!("LD V{x:X}, DT"),
+        LdKey(x) => format!("LD V{x:X}, K"),
+        LdDt(x) => format!("LD DT, V{x:X}"),
+        LdSt(x) => format!("LD ST, V{x:X}"),
+        AddI(x) => format!("ADD I, V{x:X}"),
+        LdF(x) => format!("LD F, V{x:X}"),
+        LdHf(x) => format!("LD HF, V{x:X}"),
+        Bcd(x) => format!("LD B, V{x:X}"),
+        Pitch(x) => format!("LD PITCH, V{x:X}"),
+        Store(x) => format!("LD [I], V{x:X}"),
+        Load(x) => format!("LD V{x:X}, [I]"),
+        SaveFlags(x) => format!("LD R, V{x:X}"),
+        LoadFlags(x) => format!("LD V{x:X}, R"),
+    }
+}
+
+fn format_octo(
+    instruction: &Instruction,
+    long_addr: Option<u16>,
+    label: &dyn Fn(u16) -> Option<String>,
+) -> String {
+    use Instruction::*;
+
+    let addr = |addr: u16| label(addr).unwrap_or_else(|| format!("0x{addr:03X}"));
+
+    match *instruction {
+        Nop => "0x00 0x00".to_string(),
+        Scd(n) => format!("scroll-down {n}"),
+        Cls => "clear".to_string(),
+        Ret => "return".to_string(),
+        Scr => "scroll-right".to_string(),
+        Scl => "scroll-left".to_string(),
+        Exit => "exit".to_string(),
+        Low => "lores".to_string(),
+        High => "hires".to_string(),
+        Jp(nnn) => format!("jump {}", addr(nnn)),
+        Call(nnn) => format!(":call {}", addr(nnn)),
+        // Octo conditions tell when the next instruction is executed
+        SeByte { x, nn } => format!("if v{x:x} != 0x{nn:02X} then"),
+        SneByte { x, nn } => format!("if v{x:x} == 0x{nn:02X} then"),
+        SeReg { x, y } => format!("if v{x:x} != v{y:x} then"),
+        SaveRange { x, y } => format!("save v{x:x} - v{y:x}"),
+        LoadRange { x, y } => format!("load v{x:x} - v{y:x}"),
+        LdByte { x, nn } => format!("v{x:x} := 0x{nn:02X}"),
+        AddByte { x, nn } => format!("v{x:x} += 0x{nn:02X}"),
+        LdReg { x, y } => format!("v{x:x} := v{y:x}"),
+        Or { x, y } => format!("v{x:x} |= v{y:x}"),
+        And { x, y } => format!("v{x:x} &= v{y:x}"),
+        Xor { x, y } => format!("v{x:x} ^= v{y:x}"),
+        AddReg { x, y } => format!("v{x:x} += v{y:x}"),
+        Sub { x, y } => format!("v{x:x} -= v{y:x}"),
+        Shr { x, y } => format!("v{x:x} >>= v{y:x}"),
+        Subn { x, y } => format!("v{x:x} =- v{y:x}"),
+        Shl { x, y } => format!("v{x:x} <<= v{y:x}"),
+        SneReg { x, y } => format!("if v{x:x} == v{y:x} then"),
+        LdI(nnn) => format!("i := {}", addr(nnn)),
+        JpV0(nnn) => format!("jump0 0x{nnn:03X}"),
+        Rnd { x, nn } => format!("v{x:x} := random 0x{nn:02X}"),
+        RandomScreen => "0xDD 0xDD".to_string(),
+        Drw { x, y, n } => format!("sprite v{x:x} v{y:x} {n}"),
+        Skp(x) => format!("if v{x:x} -key then"),
+        Sknp(x) => format!("if v{x:x} key then"),
+        LdILong => match long_addr {
+            Some(nnnn) => format!("i := long {}", long(nnnn, label)),
+            None => "0xF0 0x00".to_string(),
+        },
+        Plane(n) => format!("plane {n}"),
+        Audio => "audio".to_string(),
+        LdVxDt(x) => format!("v{x:x} := delay"),
+        LdKey(x) => format!("v{x:x} := key"),
+        LdDt(x) => format!("delay := v{x:x}"),
+        LdSt(x) => format!("buzzer := v{x:x}"),
+        AddI(x) => format!("i += v{x:x}"),
+        LdF(x) => format!("i := hex v{x:x}"),
+        LdHf(x) => format!("i := bighex v{x:x}"),
+        Bcd(x) => format!("bcd v{x:x}"),
+        Pitch(x) => format!("pitch := v{x:x}"),
+        Store(x) => format!("save v{x:x}"),
+        Load(x) => format!("load v{x:x}"),
+        SaveFlags(x) => format!("saveflags v{x:x}"),
+        LoadFlags(x) => format!("loadflags v{x:x}"),
+    }
+}
+
+fn long(addr: u16, label: &dyn Fn(u16) -> Option<String>) -> String {
+    label(addr).unwrap_or_else(|| format!("0x{addr:04X}"))
+}
+
+impl fmt::Display for Instruction {
+    /// The Cowgod mnemonic, without labels.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&format_cowgod(self, None, &|_| None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 14] = [
+        0x22, 0x06, // 200: CALL sub_206
+        0xA2, 0x0C, // 202: LD I, data_20C
+        0x12, 0x02, // 204: JP label_202
+        0x30, 0x01, // 206: SE V0, 0x01
+        0x00, 0xE0, // 208: CLS
+        0x00, 0xEE, // 20A: RET
+        0xF0, 0x90, // 20C: sprite data
+    ];
+
+    #[test]
+    fn follow_flow_and_label() {
+        let listing = disassemble(&ROM, Syntax::Cowgod);
+        let texts: Vec<&str> = listing.lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(
+            vec![
+                "CALL sub_206",
+                "LD I, data_20C",
+                "JP label_202",
+                "SE V0, 0x01",
+                "CLS",
+                "RET",
+                "DB 0xF0, 0x90",
+            ],
+            texts
+        );
+
+        assert_eq!(Some("label_202".to_string()), listing.lines[1].label);
+        assert_eq!(LineKind::Data, listing.lines[6].kind);
+        assert_eq!(vec![0xF0, 0x90], listing.lines[6].bytes);
+    }
+
+    #[test]
+    fn octo_syntax() {
+        let listing = disassemble(&ROM, Syntax::Octo);
+        let text = listing.to_string();
+
+        assert!(text.contains(": sub_206\n"));
+        assert!(text.contains("if v0 != 0x01 then"));
+        assert!(text.contains("0xF0 0x90"));
+        assert!(text.contains("# 20C: F0 90"));
+    }
+
+    #[test]
+    fn unreached_bytes_are_data() {
+        // jump over an invalid opcode
+        let rom = [0x12, 0x04, 0xFF, 0xFF, 0x12, 0x04];
+        let listing = disassemble(&rom, Syntax::Cowgod);
+
+        assert_eq!(LineKind::Data, listing.lines[1].kind);
+        assert_eq!("DB 0xFF, 0xFF", listing.lines[1].text);
+        assert_eq!(
+            LineKind::Code(Instruction::Jp(0x204)),
+            listing.lines[2].kind
+        );
+    }
+
+    #[test]
+    fn end_of_memory() {
+        // 6000 up to the last word of the 64 KiB, then a skip of it
+        let mut rom: Vec<u8> = [0x60, 0x00].repeat(0xFE00 / 2);
+        rom[0xFDFC..0xFDFE].copy_from_slice(&[0x30, 0x00]);
+        let listing = disassemble(&rom, Syntax::Cowgod);
+
+        let last = listing.lines.last().unwrap();
+        assert_eq!(0xFFFE, last.addr);
+        assert_eq!(
+            LineKind::Code(Instruction::LdByte { x: 0, nn: 0 }),
+            last.kind
+        );
+
+        // the bytes that don't fit aren't listed
+        rom.extend_from_slice(&[0x12, 0x00]);
+        assert_eq!(listing, disassemble(&rom, Syntax::Cowgod));
+    }
+}
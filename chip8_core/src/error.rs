@@ -0,0 +1,42 @@
+use std::fmt;
+
+/// Faults that can stop the emulator while loading or running a ROM.
+///
+/// A fault leaves the emulator in a consistent state: frontends can show
+/// it to the user and then `reset` or load another ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum C8Error {
+    /// The opcode fetched at `addr` doesn't match any instruction.
+    UnknownOpcode { op_code: u16, addr: u16 },
+    /// A subroutine was called with every stack slot already in use.
+    StackOverflow,
+    /// A return was executed outside of any subroutine.
+    StackUnderflow,
+    /// An instruction tried to read or write past the end of RAM.
+    MemoryFault { addr: usize },
+    /// The ROM doesn't fit in the memory available to programs.
+    RomTooLarge { size: usize, max: usize },
+    /// The key index is not one of the 16 keys of the keypad.
+    InvalidKey(usize),
+}
+
+impl fmt::Display for C8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            C8Error::UnknownOpcode { op_code, addr } => {
+                write!(f, "unknown opcode 0x{op_code:04X} at 0x{addr:03X}")
+            }
+            C8Error::StackOverflow => write!(f, "stack overflow"),
+            C8Error::StackUnderflow => write!(f, "stack underflow"),
+            C8Error::MemoryFault { addr } => {
+                write!(f, "memory access out of bounds at 0x{addr:04X}")
+            }
+            C8Error::RomTooLarge { size, max } => {
+                write!(f, "rom too large: {size} bytes (max {max})")
+            }
+            C8Error::InvalidKey(idx) => write!(f, "invalid key index {idx}"),
+        }
+    }
+}
+
+impl std::error::Error for C8Error {}
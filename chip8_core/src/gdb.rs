@@ -0,0 +1,444 @@
+use crate::{C8Emulator, C8Error, Debugger, StopReason, WatchKind, STACK_SIZE, V_REGS_NUM};
+
+/// Target description of the custom chip8 architecture, the `g` packet
+/// holds the registers in this order.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>chip8</architecture>
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Register numbers after the V registers.
+const REG_I: usize = V_REGS_NUM;
+const REG_PC: usize = REG_I + 1;
+const REG_SP: usize = REG_PC + 1;
+const REG_DT: usize = REG_SP + 1;
+const REG_ST: usize = REG_DT + 1;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// What the transport does after a packet.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GdbAction {
+    Reply(String),
+    /// The target runs, the stop reply is sent when it stops.
+    Resume,
+    Detach,
+}
+
+/// The packet handling of the GDB remote serial protocol, without the
+/// transport: the frontend reads and frames the packets, and sends the
+/// replies.
+pub struct GdbStub {
+    debugger: Debugger,
+}
+
+impl GdbStub {
+    /// The target starts stopped, as GDB expects when it connects.
+    pub fn new(emulator: C8Emulator) -> Self {
+        Self {
+            debugger: Debugger::new(emulator),
+        }
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    pub fn into_emulator(self) -> C8Emulator {
+        self.debugger.into_emulator()
+    }
+
+    /// Answer a packet, given without its `$` and checksum.
+    pub fn handle(&mut self, packet: &str) -> GdbAction {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => "S05".to_string(),
+            Some(b'g') => self.read_registers(),
+            Some(b'G') => self.write_registers(&packet[1..]),
+            Some(b'p') => self.read_register(&packet[1..]),
+            Some(b'P') => self.write_register(&packet[1..]),
+            Some(b'm') => self.read_memory(&packet[1..]),
+            Some(b'M') => self.write_memory(&packet[1..]),
+            Some(b'Z') => self.breakpoint(&packet[1..], true),
+            Some(b'z') => self.breakpoint(&packet[1..], false),
+            Some(b'c') => return self.resume(false),
+            Some(b's') => return self.resume(true),
+            Some(b'D') | Some(b'k') => return GdbAction::Detach,
+            Some(b'H') => "OK".to_string(),
+            _ => self.query(packet),
+        };
+        GdbAction::Reply(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=1000;qXfer:features:read+;swbreak+".to_string()
+        } else if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            read_xfer(TARGET_XML, args)
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qC" {
+            "QC1".to_string()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else {
+            // unsupported packets get an empty reply, like vCont for
+            // which GDB falls back to c and s
+            String::new()
+        }
+    }
+
+    fn resume(&mut self, step: bool) -> GdbAction {
+        if step {
+            self.debugger.step();
+        } else {
+            self.debugger.resume();
+        }
+        GdbAction::Resume
+    }
+
+    fn read_registers(&self) -> String {
+        (0..=REG_ST)
+            .map(|reg| self.read_register(&format!("{reg:x}")))
+            .collect()
+    }
+
+    fn write_registers(&mut self, hex: &str) -> String {
+        let Some(bytes) = decode_hex(hex) else {
+            return "E01".to_string();
+        };
+        let mut offset = 0;
+        for reg in 0..=REG_ST {
+            let size = register_size(reg);
+            let Some(val) = bytes.get(offset..offset + size) else {
+                return "E01".to_string();
+            };
+            self.set_register(reg, val);
+            offset += size;
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, arg: &str) -> String {
+        let ch8 = self.debugger.emulator();
+        let bytes = match usize::from_str_radix(arg, 16) {
+            Ok(reg @ 0..=0xF) => vec![ch8.v_regs[reg]],
+            Ok(REG_I) => ch8.i_reg.to_le_bytes().to_vec(),
+            Ok(REG_PC) => ch8.pc.to_le_bytes().to_vec(),
+            Ok(REG_SP) => vec![ch8.stack.sp as u8],
+            Ok(REG_DT) => vec![ch8.delay_t],
+            Ok(REG_ST) => vec![ch8.sound_t],
+            _ => return "E01".to_string(),
+        };
+        encode_hex(&bytes)
+    }
+
+    fn write_register(&mut self, arg: &str) -> String {
+        let Some((reg, hex)) = arg.split_once('=') else {
+            return "E01".to_string();
+        };
+        match (usize::from_str_radix(reg, 16), decode_hex(hex)) {
+            (Ok(reg @ 0..=REG_ST), Some(val)) if val.len() == register_size(reg) => {
+                self.set_register(reg, &val);
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn set_register(&mut self, reg: usize, val: &[u8]) {
+        let ch8 = self.debugger.emulator_mut();
+        match reg {
+            0..=0xF => ch8.v_regs[reg] = val[0],
+            REG_I => ch8.i_reg = u16::from_le_bytes([val[0], val[1]]),
+            REG_PC => ch8.pc = u16::from_le_bytes([val[0], val[1]]),
+            // the stack can't grow past its slots
+            REG_SP => ch8.stack.sp = (val[0] as usize).min(STACK_SIZE),
+            REG_DT => ch8.delay_t = val[0],
+            _ => ch8.sound_t = val[0],
+        }
+    }
+
+    fn read_memory(&self, arg: &str) -> String {
+        let ram = &self.debugger.emulator().ram;
+        match parse_range(arg).and_then(|(addr, end)| ram.get(addr..end)) {
+            Some(bytes) => encode_hex(bytes),
+            None => "E01".to_string(),
+        }
+    }
+
+    /// Writes go through the watchpoints, like the ones of the program.
+    fn write_memory(&mut self, arg: &str) -> String {
+        let ch8 = self.debugger.emulator_mut();
+        let Some((range, hex)) = arg.split_once(':') else {
+            return "E01".to_string();
+        };
+        let bytes = decode_hex(hex);
+        match parse_range(range).zip(bytes) {
+            Some(((addr, end), bytes)) if bytes.len() == end - addr && end <= ch8.ram.len() => {
+                ch8.watches.idle();
+                for (offset, byte) in bytes.into_iter().enumerate() {
+                    if ch8.write_ram(addr + offset, byte).is_err() {
+                        return "E01".to_string();
+                    }
+                }
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    /// `type,addr,kind`: software breakpoints and the three kinds of
+    /// watchpoints, which watch `kind` bytes.
+    fn breakpoint(&mut self, arg: &str, insert: bool) -> String {
+        let mut fields = arg.split(',');
+        let (Some(kind), Some(addr), Some(len)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return "E01".to_string();
+        };
+        let (Ok(addr), Ok(len)) = (u16::from_str_radix(addr, 16), u16::from_str_radix(len, 16))
+        else {
+            return "E01".to_string();
+        };
+        let range = addr..=addr.saturating_add(len.max(1) - 1);
+
+        let kinds: &[WatchKind] = match kind {
+            "0" => {
+                if insert {
+                    self.debugger.add_breakpoint(addr);
+                } else {
+                    self.debugger.remove_breakpoint(addr);
+                }
+                return "OK".to_string();
+            }
+            "2" => &[WatchKind::Write],
+            "3" => &[WatchKind::Read],
+            "4" => &[WatchKind::Read, WatchKind::Write],
+            // hardware breakpoints are not supported
+            _ => return String::new(),
+        };
+        let ch8 = self.debugger.emulator_mut();
+        for kind in kinds {
+            if insert {
+                ch8.add_watchpoint(range.clone(), *kind);
+            } else {
+                ch8.remove_watchpoint(range.clone(), *kind);
+            }
+        }
+        "OK".to_string()
+    }
+
+    /// Run up to `cycles` cycles if the target is running, and return
+    /// the stop reply if it stopped.
+    pub fn run(&mut self, cycles: usize) -> Option<String> {
+        let reason = self.debugger.run(cycles)?;
+        Some(stop_reply(reason))
+    }
+
+    /// Stop the target on a Ctrl-C from GDB, and return the stop reply.
+    pub fn interrupt(&mut self) -> String {
+        self.debugger.pause();
+        format!("T{SIGINT:02x}")
+    }
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Breakpoint(_) => format!("T{SIGTRAP:02x}swbreak:;"),
+        StopReason::Watchpoint(hit) => {
+            let name = match hit.kind {
+                WatchKind::Read => "rwatch",
+                WatchKind::Write | WatchKind::Change => "watch",
+            };
+            format!("T{SIGTRAP:02x}{name}:{:x};", hit.addr)
+        }
+        StopReason::Fault(C8Error::MemoryFault { .. }) => format!("T{SIGSEGV:02x}"),
+        StopReason::Fault(_) => format!("T{SIGILL:02x}"),
+        StopReason::Halted => "W00".to_string(),
+        StopReason::Step | StopReason::AtReturn | StopReason::Returned => {
+            format!("T{SIGTRAP:02x}")
+        }
+    }
+}
+
+fn register_size(reg: usize) -> usize {
+    match reg {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+/// `addr,length` in hexadecimal, as the start and end of the range.
+fn parse_range(arg: &str) -> Option<(usize, usize)> {
+    let (addr, len) = arg.split_once(',')?;
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    Some((addr, addr.checked_add(len)?))
+}
+
+/// Reply to `offset,length` of a qXfer read.
+fn read_xfer(document: &str, args: &str) -> String {
+    let Some((offset, end)) = parse_range(args) else {
+        return "E01".to_string();
+    };
+    let start = offset.min(document.len());
+    let end = end.min(document.len());
+    let prefix = if end == document.len() { 'l' } else { 'm' };
+    format!("{prefix}{}", &document[start..end])
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stub() -> GdbStub {
+        let mut c8 = C8Emulator::new();
+        c8.load(&[
+            0x60, 0x2A, // 200: LD V0, 0x2A
+            0xA3, 0x00, // 202: LD I, 0x300
+            0xF0, 0x55, // 204: LD [I], V0
+            0x12, 0x06, // 206: JP 206
+        ])
+        .unwrap();
+        GdbStub::new(c8)
+    }
+
+    fn reply(stub: &mut GdbStub, packet: &str) -> String {
+        match stub.handle(packet) {
+            GdbAction::Reply(reply) => reply,
+            action => panic!("unexpected {action:?}"),
+        }
+    }
+
+    #[test]
+    fn registers() {
+        let mut stub = stub();
+        let regs = reply(&mut stub, "g");
+        assert_eq!(23 * 2, regs.len());
+        // I then PC, little endian
+        assert_eq!("00000002", &regs[32..40]);
+
+        assert_eq!("OK", reply(&mut stub, "P3=7f"));
+        assert_eq!("OK", reply(&mut stub, "P11=0402"));
+        assert_eq!("7f", reply(&mut stub, "p3"));
+        assert_eq!(0x204, stub.debugger.emulator().pc());
+        assert_eq!("E01", reply(&mut stub, "p20"));
+    }
+
+    #[test]
+    fn memory() {
+        let mut stub = stub();
+        assert_eq!("602a", reply(&mut stub, "m200,2"));
+        assert_eq!("OK", reply(&mut stub, "M300,2:beef"));
+        assert_eq!("beef", reply(&mut stub, "m300,2"));
+        assert_eq!("E01", reply(&mut stub, "mffff,2"));
+
+        // ranges that overflow are errors too
+        assert_eq!("E01", reply(&mut stub, "mffffffffffffffff,1"));
+        assert_eq!("E01", reply(&mut stub, "Mffffffffffffffff,1:00"));
+        assert_eq!(
+            "E01",
+            reply(
+                &mut stub,
+                "qXfer:features:read:target.xml:1,ffffffffffffffff"
+            )
+        );
+    }
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let mut stub = stub();
+        assert_eq!("OK", reply(&mut stub, "Z0,204,2"));
+
+        assert_eq!(GdbAction::Resume, stub.handle("c"));
+        assert_eq!(Some("T05swbreak:;".to_string()), stub.run(100));
+        assert_eq!(0x204, stub.debugger.emulator().pc());
+
+        assert_eq!(GdbAction::Resume, stub.handle("s"));
+        assert_eq!(Some("T05".to_string()), stub.run(100));
+        assert_eq!(0x206, stub.debugger.emulator().pc());
+
+        assert_eq!(GdbAction::Resume, stub.handle("c"));
+        assert_eq!(None, stub.run(100));
+        assert_eq!("T02", stub.interrupt());
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut stub = stub();
+        assert_eq!("OK", reply(&mut stub, "Z2,300,1"));
+
+        stub.handle("c");
+        assert_eq!(Some("T05watch:300;".to_string()), stub.run(100));
+    }
+
+    #[test]
+    fn written_memory_is_watched() {
+        let mut stub = stub();
+        assert_eq!("OK", reply(&mut stub, "Z2,300,1"));
+        assert_eq!("OK", reply(&mut stub, "M2ff,2:0102"));
+
+        // the hit is reported before running anything
+        stub.handle("c");
+        assert_eq!(Some("T05watch:300;".to_string()), stub.run(100));
+        assert_eq!(0x200, stub.debugger.emulator().pc());
+    }
+
+    #[test]
+    fn target_description() {
+        let mut stub = stub();
+        assert!(reply(&mut stub, "qSupported:multiprocess+").contains("qXfer:features:read+"));
+
+        let first = reply(&mut stub, "qXfer:features:read:target.xml:0,10");
+        assert!(first.starts_with("m<?xml"));
+        let rest = reply(&mut stub, "qXfer:features:read:target.xml:10,1000");
+        assert!(rest.starts_with('l') && rest.ends_with("</target>\n"));
+    }
+}
@@ -0,0 +1,323 @@
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{C8Emulator, C8Error, InputScript, Quirks, Seed};
+
+/// Set to anything but `0` to write the goldens instead of comparing.
+pub const BLESS_VAR: &str = "CHIP8_BLESS";
+
+/// How the screen is stored in a golden file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoldenFormat {
+    /// `Screen::to_text`, in a `.txt` file.
+    Text,
+    /// `Screen::to_pbm`, in a `.pbm` file.
+    Pbm,
+}
+
+impl GoldenFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            GoldenFormat::Text => "txt",
+            GoldenFormat::Pbm => "pbm",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+    /// The ROM faulted before the end of the run.
+    Fault {
+        frame: u64,
+        err: C8Error,
+    },
+    Io {
+        path: PathBuf,
+        err: io::Error,
+    },
+    /// The screen differs from the golden, `diff` shows the lines.
+    Mismatch {
+        path: PathBuf,
+        diff: String,
+    },
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldenError::Fault { frame, err } => write!(f, "fault at frame {frame}: {err}"),
+            GoldenError::Io { path, err } => {
+                write!(f, "{}: {err}", path.display())?;
+                if err.kind() == io::ErrorKind::NotFound {
+                    write!(f, ", run with {BLESS_VAR}=1 to create it")?;
+                }
+                Ok(())
+            }
+            GoldenError::Mismatch { path, diff } => write!(
+                f,
+                "the screen differs from {}, run with {BLESS_VAR}=1 if it's expected\n{diff}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {}
+
+/// Runs a ROM for a number of frames, with scripted input, and compares
+/// the final screen against a golden file:
+///
+/// ```no_run
+/// # use chip8_core::GoldenTest;
+/// # let rom = [0x12, 0x00];
+/// GoldenTest::new("tests/golden/spin", &rom)
+///     .with_frames(60)
+///     .check()
+///     .unwrap();
+/// ```
+///
+/// Relative paths are from the current directory, the crate directory
+/// when running `cargo test`. With `CHIP8_BLESS=1` the golden is written
+/// instead.
+pub struct GoldenTest<'a> {
+    path: PathBuf,
+    rom: &'a [u8],
+    frames: u64,
+    cycles_per_frame: u32,
+    quirks: Quirks,
+    xo_chip: bool,
+    seed: Seed,
+    input: InputScript,
+    format: GoldenFormat,
+}
+
+impl<'a> GoldenTest<'a> {
+    /// A test of the golden at `path`, without its extension. By default
+    /// it runs 60 frames of 10 instructions with the random numbers of
+    /// seed 1, in text.
+    pub fn new(path: impl AsRef<Path>, rom: &'a [u8]) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            rom,
+            frames: 60,
+            cycles_per_frame: 10,
+            quirks: Quirks::default(),
+            xo_chip: false,
+            // goldens must not change with the default of the emulator
+            seed: Seed::Fixed(1),
+            input: InputScript::default(),
+            format: GoldenFormat::Text,
+        }
+    }
+
+    pub fn with_frames(mut self, frames: u64) -> Self {
+        self.frames = frames;
+        self
+    }
+
+    pub fn with_cycles_per_frame(mut self, cycles: u32) -> Self {
+        self.cycles_per_frame = cycles;
+        self
+    }
+
+    pub fn with_quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    pub fn with_xo_chip(mut self, enabled: bool) -> Self {
+        self.xo_chip = enabled;
+        self
+    }
+
+    /// `Seed::Entropy` makes the screen of ROMs using random numbers
+    /// change on every run.
+    pub fn with_seed(mut self, seed: Seed) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_input(mut self, input: InputScript) -> Self {
+        self.input = input;
+        self
+    }
+
+    pub fn with_format(mut self, format: GoldenFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// The golden file, with its extension.
+    pub fn golden_path(&self) -> PathBuf {
+        self.path.with_extension(self.format.extension())
+    }
+
+    /// Run the ROM and give back the emulator at the end.
+    pub fn run(&self) -> Result<C8Emulator, GoldenError> {
+        let mut c8 = C8Emulator::with_quirks(self.quirks).with_seed(self.seed);
+        c8.set_xo_chip(self.xo_chip);
+        c8.load(self.rom)
+            .map_err(|err| GoldenError::Fault { frame: 0, err })?;
+        run_frames(&mut c8, self.frames, self.cycles_per_frame, &self.input)
+            .map_err(|(frame, err)| GoldenError::Fault { frame, err })?;
+        Ok(c8)
+    }
+
+    /// Run the ROM and compare its screen with the golden, or write it
+    /// when blessing.
+    pub fn check(&self) -> Result<(), GoldenError> {
+        let c8 = self.run()?;
+        let screen = c8.get_screen();
+        let actual = match self.format {
+            GoldenFormat::Text => screen.to_text(),
+            GoldenFormat::Pbm => screen.to_pbm(),
+        };
+
+        let path = self.golden_path();
+        let io_error = |err| GoldenError::Io {
+            path: path.clone(),
+            err,
+        };
+        if blessing() {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(io_error)?;
+            }
+            return fs::write(&path, actual).map_err(io_error);
+        }
+
+        let expected = fs::read_to_string(&path).map_err(io_error)?;
+        match diff(&expected, &actual) {
+            Some(diff) => Err(GoldenError::Mismatch { path, diff }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Run `frames` frames of at most `cycles` instructions, with the keys
+/// of the script. On a fault, give back the frame it happened in.
+pub(crate) fn run_frames(
+    c8: &mut C8Emulator,
+    frames: u64,
+    cycles: u32,
+    input: &InputScript,
+) -> Result<(), (u64, C8Error)> {
+    for frame in 0..frames {
+        input.apply(frame, c8).map_err(|err| (frame, err))?;
+        for _ in 0..cycles {
+            if c8.is_waiting_vblank() || c8.is_halted() {
+                break;
+            }
+            c8.cpu_cycle().map_err(|err| (frame, err))?;
+        }
+        c8.frame_cycle();
+    }
+    Ok(())
+}
+
+fn blessing() -> bool {
+    env::var(BLESS_VAR).is_ok_and(|val| !val.is_empty() && val != "0")
+}
+
+/// The lines that differ, with the differing columns marked, or `None`
+/// when the texts are the same.
+fn diff(expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut diff = String::new();
+    for idx in 0..expected.len().max(actual.len()) {
+        let old = expected.get(idx).copied().unwrap_or_default();
+        let new = actual.get(idx).copied().unwrap_or_default();
+        if old == new {
+            continue;
+        }
+        let marks: String = (0..old.len().max(new.len()))
+            .map(|col| {
+                if old.as_bytes().get(col) == new.as_bytes().get(col) {
+                    ' '
+                } else {
+                    '^'
+                }
+            })
+            .collect();
+        diff.push_str(&format!(
+            "line {}:\n  expected {old}\n  actual   {new}\n           {}\n",
+            idx + 1,
+            marks.trim_end()
+        ));
+    }
+    if diff.is_empty() {
+        // only the line endings differ
+        diff.push_str("the line endings differ\n");
+    }
+    Some(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draws the digit of the first key pressed.
+    const KEYPAD: [u8; 8] = [
+        0xF0, 0x0A, // F00A - V0 = key
+        0xF0, 0x29, // F029 - I = font of V0
+        0xD1, 0x15, // D115 - draw at V1, V1
+        0x12, 0x06, // 1206 - loop
+    ];
+
+    #[test]
+    fn scripted_input() {
+        let input = InputScript::parse("3 7 down\n5 7 up\n").unwrap();
+        GoldenTest::new("tests/golden/keypad", &KEYPAD)
+            .with_frames(8)
+            .with_input(input)
+            .with_format(GoldenFormat::Pbm)
+            .check()
+            .unwrap();
+    }
+
+    #[test]
+    fn mismatch() {
+        let test = GoldenTest::new("tests/golden/keypad", &KEYPAD)
+            .with_frames(8)
+            .with_format(GoldenFormat::Pbm);
+        let golden = fs::read_to_string(test.golden_path()).unwrap();
+
+        // nothing is drawn without the key, the first row is line 3
+        let actual = test.run().unwrap().get_screen().to_pbm();
+        let diff = diff(&golden, &actual).unwrap();
+        assert!(diff.starts_with("line 3:\n  expected 1 1 1 1 0"), "{diff}");
+    }
+
+    #[test]
+    fn seeds() {
+        // V0 = random, then draws the digit of its low nibble
+        let rom = [0xC0, 0x0F, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06];
+        let screen = |seed| {
+            let test = GoldenTest::new("unused", &rom).with_frames(1);
+            test.with_seed(seed).run().unwrap().get_screen().to_text()
+        };
+
+        let default = GoldenTest::new("unused", &rom)
+            .with_frames(1)
+            .run()
+            .unwrap();
+        assert_eq!(screen(Seed::Fixed(1)), default.get_screen().to_text());
+        assert_ne!(screen(Seed::Fixed(1)), screen(Seed::Fixed(3)));
+    }
+
+    #[test]
+    fn diffs() {
+        assert_eq!(None, diff("..#\n", "..#\n"));
+        assert_eq!(
+            Some("line 2:\n  expected .#.\n  actual   ..#\n            ^^\n".to_string()),
+            diff("...\n.#.\n", "...\n..#\n")
+        );
+        assert!(diff("#\n", "#").is_some());
+    }
+}
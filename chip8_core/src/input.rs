@@ -0,0 +1,143 @@
+use std::fmt;
+
+use crate::{C8Emulator, C8Error, KEYS_SIZE};
+
+/// An error in an input script, `line` starts at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// A key pressed or released at the start of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// Key presses to replay without a keyboard, one per line as
+/// `<frame> <key> down|up`, with the key in hexadecimal:
+///
+/// ```text
+/// # hold 5 for half a second
+/// 120 5 down
+/// 150 5 up
+/// ```
+///
+/// Frames count from 0, the events of a frame are applied before any of
+/// its instructions run. Everything after a `#` is a comment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    /// Sorted by frame, in the order of the script within a frame.
+    events: Vec<KeyEvent>,
+}
+
+impl InputScript {
+    pub fn parse(source: &str) -> Result<Self, ScriptError> {
+        let mut events = Vec::new();
+        for (idx, line) in source.lines().enumerate() {
+            let error = |message: String| ScriptError {
+                line: idx + 1,
+                message,
+            };
+            let line = line.split('#').next().unwrap_or_default();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [frame, key, action] = fields[..] else {
+                if fields.is_empty() {
+                    continue;
+                }
+                return Err(error("expected <frame> <key> down|up".to_string()));
+            };
+
+            let frame = frame
+                .parse()
+                .map_err(|_| error(format!("invalid frame {frame}")))?;
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|key| (*key as usize) < KEYS_SIZE)
+                .ok_or_else(|| error(format!("invalid key {key}")))?;
+            let pressed = match action {
+                "down" => true,
+                "up" => false,
+                _ => return Err(error(format!("unknown action {action}"))),
+            };
+            events.push(KeyEvent {
+                frame,
+                key,
+                pressed,
+            });
+        }
+
+        // stable, so the events of a frame keep their order
+        events.sort_by_key(|event| event.frame);
+        Ok(Self { events })
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    /// The frame of the last event, nothing changes after it.
+    pub fn last_frame(&self) -> Option<u64> {
+        self.events.last().map(|event| event.frame)
+    }
+
+    /// Press and release the keys of `frame` on the emulator.
+    pub fn apply(&self, frame: u64, c8: &mut C8Emulator) -> Result<(), C8Error> {
+        let start = self.events.partition_point(|event| event.frame < frame);
+        for event in self.events[start..]
+            .iter()
+            .take_while(|event| event.frame == frame)
+        {
+            c8.press_key(event.key as usize, event.pressed)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_apply() {
+        let script = InputScript::parse(
+            "# comment\n\
+             10 a down\n\
+             \n\
+             3 1 down # inline\n\
+             10 1 up\n",
+        )
+        .unwrap();
+        assert_eq!(Some(10), script.last_frame());
+        assert_eq!(3, script.events().len());
+
+        let mut c8 = C8Emulator::new();
+        script.apply(3, &mut c8).unwrap();
+        assert!(c8.keys[1]);
+        script.apply(4, &mut c8).unwrap();
+        script.apply(10, &mut c8).unwrap();
+        assert!(c8.keys[0xA]);
+        assert!(!c8.keys[1]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = InputScript::parse("1 2 down\n2 10 up\n").unwrap_err();
+        assert_eq!(2, error.line);
+        assert_eq!("line 2: invalid key 10", error.to_string());
+        assert!(InputScript::parse("1 2 press").is_err());
+        assert!(InputScript::parse("1 2").is_err());
+        assert!(InputScript::parse("-1 2 up").is_err());
+    }
+}
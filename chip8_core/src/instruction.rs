@@ -0,0 +1,360 @@
+use std::fmt;
+
+/// A decoded instruction of the CHIP-8 CPU and of its SUPER-CHIP and
+/// XO-CHIP extensions.
+///
+/// Variants are named after the Cowgod's reference mnemonics, `x` and
+/// `y` are register indexes, `n` a nibble, `nn` a byte, and the
+/// addresses of `Jp`, `Call`, `LdI` and `JpV0` are 12 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// 0000 - do nothing
+    Nop,
+    /// 00Cn - scroll the display down n pixels
+    Scd(u8),
+    /// 00E0 - clear the display
+    Cls,
+    /// 00EE - return from a subroutine
+    Ret,
+    /// 00FB - scroll the display right 4 pixels
+    Scr,
+    /// 00FC - scroll the display left 4 pixels
+    Scl,
+    /// 00FD - exit the interpreter
+    Exit,
+    /// 00FE - low resolution mode
+    Low,
+    /// 00FF - high resolution mode
+    High,
+    /// 1nnn - jump to nnn
+    Jp(u16),
+    /// 2nnn - call the subroutine at nnn
+    Call(u16),
+    /// 3xnn - skip if VX == nn
+    SeByte { x: u8, nn: u8 },
+    /// 4xnn - skip if VX != nn
+    SneByte { x: u8, nn: u8 },
+    /// 5xy0 - skip if VX == VY
+    SeReg { x: u8, y: u8 },
+    /// 5xy2 - store VX to VY at I (XO-CHIP)
+    SaveRange { x: u8, y: u8 },
+    /// 5xy3 - load VX to VY from I (XO-CHIP)
+    LoadRange { x: u8, y: u8 },
+    /// 6xnn - VX = nn
+    LdByte { x: u8, nn: u8 },
+    /// 7xnn - VX += nn
+    AddByte { x: u8, nn: u8 },
+    /// 8xy0 - VX = VY
+    LdReg { x: u8, y: u8 },
+    /// 8xy1 - VX |= VY
+    Or { x: u8, y: u8 },
+    /// 8xy2 - VX &= VY
+    And { x: u8, y: u8 },
+    /// 8xy3 - VX ^= VY
+    Xor { x: u8, y: u8 },
+    /// 8xy4 - VX += VY, VF = carry
+    AddReg { x: u8, y: u8 },
+    /// 8xy5 - VX -= VY, VF = not borrow
+    Sub { x: u8, y: u8 },
+    /// 8xy6 - VX >>= 1, VF = lsb
+    Shr { x: u8, y: u8 },
+    /// 8xy7 - VX = VY - VX, VF = not borrow
+    Subn { x: u8, y: u8 },
+    /// 8xyE - VX <<= 1, VF = msb
+    Shl { x: u8, y: u8 },
+    /// 9xy0 - skip if VX != VY
+    SneReg { x: u8, y: u8 },
+    /// Annn - I = nnn
+    LdI(u16),
+    /// Bnnn - jump to nnn + V0
+    JpV0(u16),
+    /// Cxnn - VX = random & nn
+    Rnd { x: u8, nn: u8 },
+    /// DDDD - (custom instruction) draw a random screen
+    RandomScreen,
+    /// Dxyn - draw a sprite n rows high, 16x16 when n is 0
+    Drw { x: u8, y: u8, n: u8 },
+    /// Ex9E - skip if the key VX is pressed
+    Skp(u8),
+    /// ExA1 - skip if the key VX is not pressed
+    Sknp(u8),
+    /// F000 nnnn - I = nnnn, the address is the next word (XO-CHIP)
+    LdILong,
+    /// Fn01 - select the drawing planes (XO-CHIP)
+    Plane(u8),
+    /// F002 - load the audio pattern from I (XO-CHIP)
+    Audio,
+    /// Fx07 - VX = delay timer
+    LdVxDt(u8),
+    /// Fx0A - wait for a key press, VX = key
+    LdKey(u8),
+    /// Fx15 - delay timer = VX
+    LdDt(u8),
+    /// Fx18 - sound timer = VX
+    LdSt(u8),
+    /// Fx1E - I += VX
+    AddI(u8),
+    /// Fx29 - I = small font sprite of VX
+    LdF(u8),
+    /// Fx30 - I = big font sprite of VX (SUPER-CHIP)
+    LdHf(u8),
+    /// Fx33 - store the BCD of VX at I
+    Bcd(u8),
+    /// Fx3A - pitch = VX (XO-CHIP)
+    Pitch(u8),
+    /// Fx55 - store V0 to VX at I
+    Store(u8),
+    /// Fx65 - load V0 to VX from I
+    Load(u8),
+    /// Fx75 - store V0 to VX in the user flags (SUPER-CHIP)
+    SaveFlags(u8),
+    /// Fx85 - load V0 to VX from the user flags (SUPER-CHIP)
+    LoadFlags(u8),
+}
+
+/// The opcode doesn't match any instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub op_code: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown opcode 0x{:04X}", self.op_code)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A field of the instruction doesn't fit in its opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeError {
+    pub instruction: Instruction,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} has no opcode", self.instruction)
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+impl Instruction {
+    pub fn decode(op_code: u16) -> Result<Instruction, DecodeError> {
+        use Instruction::*;
+
+        // isolate every digits from the opcode
+        let digit1 = (op_code & 0xF000) >> 12;
+        let digit2 = ((op_code & 0x0F00) >> 8) as u8;
+        let digit3 = ((op_code & 0x00F0) >> 4) as u8;
+        let digit4 = (op_code & 0x000F) as u8;
+
+        let nnn = op_code & 0xFFF;
+        let nn = (op_code & 0xFF) as u8;
+
+        let instruction = match (digit1, digit2, digit3, digit4) {
+            (0, 0, 0, 0) => Nop,
+            (0, 0, 0xC, n) => Scd(n),
+            (0, 0, 0xE, 0) => Cls,
+            (0, 0, 0xE, 0xE) => Ret,
+            (0, 0, 0xF, 0xB) => Scr,
+            (0, 0, 0xF, 0xC) => Scl,
+            (0, 0, 0xF, 0xD) => Exit,
+            (0, 0, 0xF, 0xE) => Low,
+            (0, 0, 0xF, 0xF) => High,
+            (1, _, _, _) => Jp(nnn),
+            (2, _, _, _) => Call(nnn),
+            (3, x, _, _) => SeByte { x, nn },
+            (4, x, _, _) => SneByte { x, nn },
+            (5, x, y, 0) => SeReg { x, y },
+            (5, x, y, 2) => SaveRange { x, y },
+            (5, x, y, 3) => LoadRange { x, y },
+            (6, x, _, _) => LdByte { x, nn },
+            (7, x, _, _) => AddByte { x, nn },
+            (8, x, y, 0) => LdReg { x, y },
+            (8, x, y, 1) => Or { x, y },
+            (8, x, y, 2) => And { x, y },
+            (8, x, y, 3) => Xor { x, y },
+            (8, x, y, 4) => AddReg { x, y },
+            (8, x, y, 5) => Sub { x, y },
+            (8, x, y, 6) => Shr { x, y },
+            (8, x, y, 7) => Subn { x, y },
+            (8, x, y, 0xE) => Shl { x, y },
+            (9, x, y, 0) => SneReg { x, y },
+            (0xA, _, _, _) => LdI(nnn),
+            (0xB, _, _, _) => JpV0(nnn),
+            (0xC, x, _, _) => Rnd { x, nn },
+            (0xD, 0xD, 0xD, 0xD) => RandomScreen,
+            (0xD, x, y, n) => Drw { x, y, n },
+            (0xE, x, 9, 0xE) => Skp(x),
+            (0xE, x, 0xA, 1) => Sknp(x),
+            (0xF, 0, 0, 0) => LdILong,
+            (0xF, n, 0, 1) if n <= 0b11 => Plane(n),
+            (0xF, 0, 0, 2) => Audio,
+            (0xF, x, 0, 7) => LdVxDt(x),
+            (0xF, x, 0, 0xA) => LdKey(x),
+            (0xF, x, 1, 5) => LdDt(x),
+            (0xF, x, 1, 8) => LdSt(x),
+            (0xF, x, 1, 0xE) => AddI(x),
+            (0xF, x, 2, 9) => LdF(x),
+            (0xF, x, 3, 0) => LdHf(x),
+            (0xF, x, 3, 3) => Bcd(x),
+            (0xF, x, 3, 0xA) => Pitch(x),
+            (0xF, x, 5, 5) => Store(x),
+            (0xF, x, 6, 5) => Load(x),
+            (0xF, x, 7, 5) => SaveFlags(x),
+            (0xF, x, 8, 5) => LoadFlags(x),
+            (_, _, _, _) => return Err(DecodeError { op_code }),
+        };
+
+        Ok(instruction)
+    }
+
+    /// The opcode of the instruction, an error when a field is out of
+    /// range for it.
+    pub fn encode(&self) -> Result<u16, EncodeError> {
+        // an out of range field spills over the others, it doesn't read back
+        let op_code = self.encode_fields();
+        match Instruction::decode(op_code) {
+            Ok(instruction) if instruction == *self => Ok(op_code),
+            _ => Err(EncodeError { instruction: *self }),
+        }
+    }
+
+    fn encode_fields(&self) -> u16 {
+        use Instruction::*;
+
+        let xy = |x: u8, y: u8| (x as u16) << 8 | (y as u16) << 4;
+        let xnn = |x: u8, nn: u8| (x as u16) << 8 | nn as u16;
+        let x_ = |x: u8| (x as u16) << 8;
+
+        match *self {
+            Nop => 0x0000,
+            Scd(n) => 0x00C0 | n as u16,
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            Scr => 0x00FB,
+            Scl => 0x00FC,
+            Exit => 0x00FD,
+            Low => 0x00FE,
+            High => 0x00FF,
+            Jp(addr) => 0x1000 | addr,
+            Call(addr) => 0x2000 | addr,
+            SeByte { x, nn } => 0x3000 | xnn(x, nn),
+            SneByte { x, nn } => 0x4000 | xnn(x, nn),
+            SeReg { x, y } => 0x5000 | xy(x, y),
+            SaveRange { x, y } => 0x5002 | xy(x, y),
+            LoadRange { x, y } => 0x5003 | xy(x, y),
+            LdByte { x, nn } => 0x6000 | xnn(x, nn),
+            AddByte { x, nn } => 0x7000 | xnn(x, nn),
+            LdReg { x, y } => 0x8000 | xy(x, y),
+            Or { x, y } => 0x8001 | xy(x, y),
+            And { x, y } => 0x8002 | xy(x, y),
+            Xor { x, y } => 0x8003 | xy(x, y),
+            AddReg { x, y } => 0x8004 | xy(x, y),
+            Sub { x, y } => 0x8005 | xy(x, y),
+            Shr { x, y } => 0x8006 | xy(x, y),
+            Subn { x, y } => 0x8007 | xy(x, y),
+            Shl { x, y } => 0x800E | xy(x, y),
+            SneReg { x, y } => 0x9000 | xy(x, y),
+            LdI(addr) => 0xA000 | addr,
+            JpV0(addr) => 0xB000 | addr,
+            Rnd { x, nn } => 0xC000 | xnn(x, nn),
+            RandomScreen => 0xDDDD,
+            Drw { x, y, n } => 0xD000 | xy(x, y) | n as u16,
+            Skp(x) => 0xE09E | x_(x),
+            Sknp(x) => 0xE0A1 | x_(x),
+            LdILong => 0xF000,
+            Plane(n) => 0xF001 | x_(n),
+            Audio => 0xF002,
+            LdVxDt(x) => 0xF007 | x_(x),
+            LdKey(x) => 0xF00A | x_(x),
+            LdDt(x) => 0xF015 | x_(x),
+            LdSt(x) => 0xF018 | x_(x),
+            AddI(x) => 0xF01E | x_(x),
+            LdF(x) => 0xF029 | x_(x),
+            LdHf(x) => 0xF030 | x_(x),
+            Bcd(x) => 0xF033 | x_(x),
+            Pitch(x) => 0xF03A | x_(x),
+            Store(x) => 0xF055 | x_(x),
+            Load(x) => 0xF065 | x_(x),
+            SaveFlags(x) => 0xF075 | x_(x),
+            LoadFlags(x) => 0xF085 | x_(x),
+        }
+    }
+
+    /// Whether the instruction only exists in the XO-CHIP extension.
+    pub fn is_xo_chip(&self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            SaveRange { .. } | LoadRange { .. } | LdILong | Plane(_) | Audio | Pitch(_)
+        )
+    }
+
+    /// Size in bytes, F000 nnnn is followed by its address.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LdILong => 4,
+            _ => 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_and_encode_every_opcode() {
+        let mut decoded = 0;
+        for op_code in 0..=0xFFFF {
+            if let Ok(instruction) = Instruction::decode(op_code) {
+                assert_eq!(Ok(op_code), instruction.encode(), "{instruction:?}");
+                decoded += 1;
+            }
+        }
+        // every opcode of the 1nnn-4xnn, 6xnn-7xnn and Annn-Dxyn blocks
+        assert!(decoded > 0xA000);
+    }
+
+    #[test]
+    fn decode_fields() {
+        assert_eq!(
+            Ok(Instruction::Drw { x: 1, y: 2, n: 5 }),
+            Instruction::decode(0xD125)
+        );
+        assert_eq!(Ok(Instruction::Jp(0x333)), Instruction::decode(0x1333));
+        assert_eq!(Ok(Instruction::RandomScreen), Instruction::decode(0xDDDD));
+        assert_eq!(
+            Err(DecodeError { op_code: 0x8008 }),
+            Instruction::decode(0x8008)
+        );
+    }
+
+    #[test]
+    fn encode_out_of_range_fields() {
+        let encode_error = |instruction| Err(EncodeError { instruction });
+
+        for instruction in [
+            Instruction::Jp(0x1FFF),
+            Instruction::LdI(0x1000),
+            Instruction::Drw { x: 16, y: 0, n: 1 },
+            Instruction::Drw { x: 0, y: 0, n: 16 },
+            Instruction::AddByte { x: 0x10, nn: 1 },
+            Instruction::Scd(0x10),
+            Instruction::Plane(4),
+            // DDDD is the random screen
+            Instruction::Drw {
+                x: 13,
+                y: 13,
+                n: 13,
+            },
+        ] {
+            assert_eq!(encode_error(instruction), instruction.encode());
+        }
+        assert_eq!(Ok(0x1FFF), Instruction::Jp(0xFFF).encode());
+    }
+}
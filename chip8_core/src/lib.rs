@@ -1,13 +1,80 @@
+mod asm;
+mod audio;
+mod cdp1802;
+mod conformance;
+mod debugger;
+mod disasm;
+mod error;
+mod gdb;
+mod golden;
+mod input;
+mod instruction;
+mod machine;
+mod movie;
+mod observer;
+mod quirks;
+mod rewind;
+mod rng;
+mod scheduler;
+mod screen;
+mod snapshot;
+mod timing;
+mod tracer;
+mod vip;
+mod watch;
+mod wav;
+
+pub use asm::{assemble, assemble_program, AsmError, Program};
+pub use audio::{BEEP_HZ, DEFAULT_SAMPLE_RATE};
+pub use conformance::{
+    CheckResult, ConformanceReport, Manifest, ManifestError, Outcome, MANIFEST_NAME,
+};
+pub use debugger::{Debugger, StopReason};
+pub use disasm::{disassemble, Line, LineKind, Listing, Syntax};
+pub use error::C8Error;
+pub use gdb::{GdbAction, GdbStub};
+pub use golden::{GoldenError, GoldenFormat, GoldenTest, BLESS_VAR};
+pub use input::{InputScript, KeyEvent, ScriptError};
+pub use instruction::{DecodeError, EncodeError, Instruction};
+pub use machine::Machine;
+pub use movie::{Movie, MovieError, MovieStatus};
+pub use observer::{Observer, ObserverId};
+pub use quirks::{LoadStore, Quirks};
+pub use rng::{Lcg, Rng, Seed};
+pub use scheduler::{Scheduler, Timing, TIMER_HZ};
+pub use screen::Screen;
+pub use snapshot::{Snapshot, SnapshotError};
+pub use timing::{VIP_CYCLES_PER_FRAME, VIP_DISPLAY_CYCLES};
+pub use tracer::{OpClass, TraceFormat, Tracer, TRACE_RECORD_SIZE};
+pub use vip::CosmacVip;
+pub use watch::{WatchHit, WatchKind, Watchpoint};
+pub use wav::WavWriter;
+
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
+/// SUPER-CHIP high resolution mode, enabled by 00FF.
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+const SCREEN_SIZE: usize = HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT;
 const KEYS_SIZE: usize = 16;
 
 const RAM_SIZE: usize = 4096;
+/// XO-CHIP programs can address the whole 64 KiB of memory.
+const XO_RAM_SIZE: usize = 0x10000;
 const STACK_SIZE: usize = 16;
 
 const V_REGS_NUM: usize = 16;
 
 const FONTSET_SIZE: usize = 16 * 5;
+const BIG_FONTSET_SIZE: usize = 16 * 10;
+
+/// SUPER-CHIP user flags, saved by Fx75 and restored by Fx85.
+const RPL_FLAGS_NUM: usize = 16;
+
+/// XO-CHIP audio pattern buffer, loaded by F002.
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+/// Pitch register value that plays the pattern at 4000 bits per second.
+const DEFAULT_PITCH: u8 = 64;
 
 /// ROM code are loaded starting from the 0x0200 address because the
 /// first 512 addresses are used by the system.
@@ -36,29 +103,26 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F => 1111, 1000, 1111, 1000, 1000
 ];
 
-/// Linear Congruential Generator
-struct LCG {
-    state: u32,
-}
-
-impl LCG {
-    fn new(seed: u32) -> Self {
-        Self { state: seed }
-    }
-
-    fn rand(&mut self) -> u32 {
-        self.state = self.state.wrapping_mul(1103515245).wrapping_add(12345);
-        (self.state >> 16) & 0x7FFF
-    }
-
-    fn s_rand(&mut self, seed: u32) {
-        self.state = seed;
-    }
-
-    fn rand_u8(&mut self) -> u8 {
-        (self.rand() & 0xFF) as u8
-    }
-}
+/// SUPER-CHIP fontset of 8x10 Hexadecimal digits, loaded right after
+/// the small one and selected by Fx30.
+const BIG_FONTSET: [u8; BIG_FONTSET_SIZE] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x3C, 0x7E, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
 
 struct Stack {
     arr: [u16; STACK_SIZE],
@@ -78,192 +142,499 @@ impl Stack {
         self.sp = 0;
     }
 
-    fn pop(&mut self) -> u16 {
+    fn pop(&mut self) -> Result<u16, C8Error> {
+        if self.sp == 0 {
+            return Err(C8Error::StackUnderflow);
+        }
         self.sp -= 1;
-        self.arr[self.sp]
+        Ok(self.arr[self.sp])
     }
 
-    fn push(&mut self, val: u16) {
+    fn push(&mut self, val: u16) -> Result<(), C8Error> {
+        if self.sp == STACK_SIZE {
+            return Err(C8Error::StackOverflow);
+        }
         self.arr[self.sp] = val;
         self.sp += 1;
+        Ok(())
     }
 }
 
 pub struct C8Emulator {
-    pc: u16, // program counter
-    ram: [u8; RAM_SIZE],
+    pc: u16,      // program counter
+    ram: Vec<u8>, // RAM_SIZE bytes, or XO_RAM_SIZE in XO-CHIP mode
     stack: Stack,
-    v_regs: [u8; V_REGS_NUM], // v registers
-    i_reg: u16,               // i register
-    delay_t: u8,              // delay timer
-    sound_t: u8,              // sound timer
-    screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
+    v_regs: [u8; V_REGS_NUM],  // v registers
+    i_reg: u16,                // i register
+    delay_t: u8,               // delay timer
+    sound_t: u8,               // sound timer
+    screen: [u8; SCREEN_SIZE], // a bit for each plane, sized for the high resolution mode
+    hires: bool,
+    planes: u8, // bit mask of the planes selected by Fn01
     keys: [bool; KEYS_SIZE],
-    rand_gen: LCG,
+    rand_gen: Box<dyn Rng>,
+    seed: Seed,
+    quirks: Quirks,
+    waiting_vblank: bool, // set by Dxyn with the display_wait quirk
+    halted: bool,         // set by 00FD
+    rpl: [u8; RPL_FLAGS_NUM],
+    xo_chip: bool,
+    audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pitch: u8,
+    rewind: Option<rewind::RewindBuffer>,
+    watches: watch::Watches,
+    tracer: Option<Box<tracer::Tracer>>,
+    observers: observer::Observers,
+    audio: audio::Audio,
+    movie: Option<Box<movie::MovieState>>,
 }
 
 impl C8Emulator {
     pub fn new() -> Self {
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
         let mut c8_emulator = Self {
             pc: START_ADDR as u16,
-            ram: [0; RAM_SIZE],
+            ram: vec![0; RAM_SIZE],
             stack: Stack::new(),
             v_regs: [0; V_REGS_NUM],
             i_reg: 0,
             delay_t: 0,
             sound_t: 0,
-            screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            screen: [0; SCREEN_SIZE],
+            hires: false,
+            planes: 1,
             keys: [false; KEYS_SIZE],
-            rand_gen: LCG::new(1), // maybe the seed could be "randomized"
+            rand_gen: Box::new(Lcg::new(1)),
+            seed: Seed::default(),
+            quirks,
+            waiting_vblank: false,
+            halted: false,
+            rpl: [0; RPL_FLAGS_NUM],
+            xo_chip: false,
+            audio_pattern: [0; AUDIO_PATTERN_SIZE],
+            pitch: DEFAULT_PITCH,
+            rewind: None,
+            watches: watch::Watches::default(),
+            tracer: None,
+            observers: observer::Observers::default(),
+            audio: audio::Audio::default(),
+            movie: None,
         };
 
-        // Loading the fontset in memory.
-        c8_emulator.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        // Loading the fontsets in memory.
+        c8_emulator.load_fontsets();
 
         c8_emulator
     }
 
     pub fn reset(&mut self) {
         self.pc = START_ADDR as u16;
-        self.ram = [0; RAM_SIZE];
+        self.ram.fill(0);
         self.stack.reset();
         self.v_regs = [0; V_REGS_NUM];
         self.i_reg = 0;
         self.delay_t = 0;
         self.sound_t = 0;
-        self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        self.screen = [0; SCREEN_SIZE];
+        self.hires = false;
+        self.planes = 1;
         self.keys = [false; KEYS_SIZE];
+        self.load_fontsets();
+        self.reseed();
+        self.waiting_vblank = false;
+        self.halted = false;
+        self.audio_pattern = [0; AUDIO_PATTERN_SIZE];
+        self.pitch = DEFAULT_PITCH;
+        self.watches.clear_hit();
+        if self.rewind.is_some() {
+            // the history starts again from the reset state
+            let snapshot = self.snapshot();
+            if let Some(buffer) = self.rewind.as_mut() {
+                buffer.clear();
+                buffer.push(&snapshot);
+            }
+        }
+        // the user flags are persistent like on the HP-48, so they survive resets
+    }
+
+    fn load_fontsets(&mut self) {
         self.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
-        self.rand_gen.s_rand(1);
+        self.ram[FONTSET_SIZE..FONTSET_SIZE + BIG_FONTSET_SIZE].copy_from_slice(&BIG_FONTSET);
+    }
+
+    /// Enable or disable the XO-CHIP extensions and its 64 KiB of memory.
+    /// The emulator is reset, so the ROM must be loaded afterwards.
+    pub fn set_xo_chip(&mut self, enabled: bool) {
+        self.xo_chip = enabled;
+        let size = if enabled { XO_RAM_SIZE } else { RAM_SIZE };
+        self.ram.resize(size, 0);
+        self.reset();
+    }
+
+    pub fn is_xo_chip(&self) -> bool {
+        self.xo_chip
+    }
+
+    /// The XO-CHIP audio pattern, 128 1-bit samples played while the
+    /// sound timer is active.
+    pub fn audio_pattern(&self) -> &[u8; AUDIO_PATTERN_SIZE] {
+        &self.audio_pattern
+    }
+
+    /// The XO-CHIP pitch register, the pattern is played at
+    /// `4000 * 2 ^ ((pitch - 64) / 48)` bits per second.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// The display at its current resolution, 64x32 or 128x64 in
+    /// the SUPER-CHIP high resolution mode. Every pixel holds its palette
+    /// index, a bit for each of the two XO-CHIP planes.
+    pub fn get_screen(&self) -> Screen<'_> {
+        Screen::new(self.screen_width(), self.screen_height(), &self.screen)
     }
 
-    pub fn get_screen(&self) -> &[bool] {
-        &self.screen
+    /// True after the program executed 00FD.
+    pub fn is_halted(&self) -> bool {
+        self.halted
     }
 
-    pub fn press_key(&mut self, idx: usize, pressed: bool) {
-        self.keys[idx] = pressed;
+    /// True while a draw waits for the end of the frame, with the
+    /// display_wait quirk.
+    pub fn is_waiting_vblank(&self) -> bool {
+        self.waiting_vblank
     }
 
-    pub fn load(&mut self, data: &[u8]) {
-        let start = START_ADDR as usize;
-        let end = (START_ADDR as usize) + data.len();
-        self.ram[start..end].copy_from_slice(data);
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn v_regs(&self) -> &[u8; V_REGS_NUM] {
+        &self.v_regs
+    }
+
+    pub fn i_reg(&self) -> u16 {
+        self.i_reg
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_t
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_t
+    }
+
+    /// The return addresses of the active calls, the innermost last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack.arr[..self.stack.sp]
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Decode the instruction at the program counter without executing it.
+    pub fn current_instruction(&self) -> Result<Instruction, C8Error> {
+        let addr = self.pc as usize;
+        let op_code = u16::from_be_bytes([self.read_ram(addr)?, self.read_ram(addr + 1)?]);
+        self.decode(op_code, self.pc)
+    }
+
+    fn screen_width(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
+
+    fn screen_height(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
+
+    pub fn press_key(&mut self, idx: usize, pressed: bool) -> Result<(), C8Error> {
+        if self.movie_key(idx, pressed)? {
+            return Ok(());
+        }
+        let key = self.keys.get_mut(idx).ok_or(C8Error::InvalidKey(idx))?;
+        *key = pressed;
+        Ok(())
+    }
+
+    pub fn load(&mut self, data: &[u8]) -> Result<(), C8Error> {
+        let max = self.ram.len() - START_ADDR;
+        if data.len() > max {
+            return Err(C8Error::RomTooLarge {
+                size: data.len(),
+                max,
+            });
+        }
+
+        if self.watches.is_empty() {
+            let start = START_ADDR;
+            let end = START_ADDR + data.len();
+            self.ram[start..end].copy_from_slice(data);
+        } else {
+            self.watches.idle();
+            for (offset, byte) in data.iter().enumerate() {
+                self.write_ram(START_ADDR + offset, *byte)?;
+            }
+        }
+        Ok(())
     }
 
     /// Consist in the fetch-decode-execute cycle,
-    pub fn cpu_cycle(&mut self) {
-        let op_code = self.fetch();
+    /// on a fault the program counter is left on the faulting instruction.
+    pub fn cpu_cycle(&mut self) -> Result<(), C8Error> {
+        if self.waiting_vblank || self.halted || self.watches.is_hit() {
+            return Ok(());
+        }
+
+        let addr = self.pc;
+
+        let result = self.fetch().and_then(|op_code| {
+            let instruction = self.decode(op_code, addr)?;
+            if !self.watches.is_empty() {
+                self.watches.executing(addr, instruction);
+            }
+            if self.has_observers() {
+                self.notify(|observer, c8| observer.on_instruction(c8, addr, instruction));
+            }
 
-        self.decode_and_execute(op_code);
+            let traced = match self.tracer.as_mut() {
+                Some(tracer) => tracer.next_cycle(addr, instruction),
+                None => false,
+            };
+            if !traced {
+                return self.execute(instruction);
+            }
+
+            let before = tracer::Registers::of(self);
+            self.execute(instruction)?;
+            let after = tracer::Registers::of(self);
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.record(addr, op_code, instruction, &before, &after);
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            self.pc = addr;
+        }
+        result
     }
 
     pub fn frame_cycle(&mut self) {
+        self.waiting_vblank = false;
+        self.capture_frame();
+
         // handle delay and sound
         if self.delay_t > 0 {
             self.delay_t -= 1;
         }
         if self.sound_t > 0 {
-            if self.sound_t == 1 {
-                // TODO: make sound
-            }
+            // the samples come from `fill_audio` while it's not 0
             self.sound_t -= 1;
+            if self.sound_t == 0 && self.has_observers() {
+                self.notify(|observer, c8| observer.on_sound_stop(c8));
+            }
         }
+        if self.has_observers() {
+            self.notify(|observer, c8| observer.on_frame(c8));
+        }
+        self.movie_frame();
+
+        self.record_frame();
     }
 
-    fn fetch(&mut self) -> u16 {
+    fn fetch(&mut self) -> Result<u16, C8Error> {
         let addr = self.pc as usize;
-        let bytes = &self.ram[addr..(addr + 2)];
+        let hi = self.read_ram(addr)?;
+        let lo = self.read_ram(addr + 1)?;
         // move the first byte to the left then add the second byte
-        let op = (bytes[0] as u16) << 0x8 | bytes[1] as u16;
-        self.pc += 2;
-        op
-    }
-
-    /// Decode and execute an instruction of the C8 CPU.
-    /// Opcodes contains parameters of the instruction.
-    fn decode_and_execute(&mut self, op_code: u16) {
-        // isolate every digits from the opcode
-        let digit1 = (op_code & 0xF000) >> 12;
-        let digit2 = (op_code & 0x0F00) >> 8;
-        let digit3 = (op_code & 0x00F0) >> 4;
-        let digit4 = op_code & 0x000F;
-
-        // pattern match the opcode and implements its instruction
-        match (digit1, digit2, digit3, digit4) {
-            (0, 0, 0, 0) => return, // NO-OP
-            (0, 0, 0xE, 0) => {
-                // Clear Sreen
-                self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
-            }
-            (0, 0, 0xE, 0xE) => {
+        let op = (hi as u16) << 0x8 | lo as u16;
+        // the 64 KiB of XO-CHIP wrap around, as in Octo
+        self.pc = self.pc.wrapping_add(2);
+        Ok(op)
+    }
+
+    fn read_ram(&self, addr: usize) -> Result<u8, C8Error> {
+        self.ram
+            .get(addr)
+            .copied()
+            .ok_or(C8Error::MemoryFault { addr })
+    }
+
+    fn write_ram(&mut self, addr: usize, val: u8) -> Result<(), C8Error> {
+        let cell = self
+            .ram
+            .get_mut(addr)
+            .ok_or(C8Error::MemoryFault { addr })?;
+        let old = *cell;
+        *cell = val;
+        if !self.watches.is_empty() {
+            self.watches.on_write(self.pc, addr, old, val);
+        }
+        Ok(())
+    }
+
+    /// Read memory on behalf of an instruction, unlike fetching opcodes
+    /// these reads are reported to the watchpoints.
+    fn read_data(&mut self, addr: usize) -> Result<u8, C8Error> {
+        let val = self.read_ram(addr)?;
+        if !self.watches.is_empty() {
+            self.watches.on_read(self.pc, addr, val);
+        }
+        Ok(val)
+    }
+
+    fn key_pressed(&self, idx: u8) -> Result<bool, C8Error> {
+        self.keys
+            .get(idx as usize)
+            .copied()
+            .ok_or(C8Error::InvalidKey(idx as usize))
+    }
+
+    /// Decode an opcode fetched at `addr`, XO-CHIP instructions are
+    /// unknown outside of the XO-CHIP mode.
+    fn decode(&self, op_code: u16, addr: u16) -> Result<Instruction, C8Error> {
+        Instruction::decode(op_code)
+            .ok()
+            .filter(|instruction| self.xo_chip || !instruction.is_xo_chip())
+            .ok_or(C8Error::UnknownOpcode { op_code, addr })
+    }
+
+    /// Execute an instruction of the C8 CPU.
+    fn execute(&mut self, instruction: Instruction) -> Result<(), C8Error> {
+        use Instruction::*;
+
+        match instruction {
+            Nop => (),
+            Scd(n) => {
+                // Scroll down N pixels
+                self.scroll(0, n as isize);
+            }
+            Cls => {
+                // Clear Sreen (only the selected planes)
+                let keep = !self.planes;
+                self.screen.iter_mut().for_each(|px| *px &= keep);
+            }
+            Ret => {
                 // Return from Subroutine
-                let ret_addr = self.stack.pop();
+                let ret_addr = self.stack.pop()?;
                 self.pc = ret_addr;
             }
-            (1, _, _, _) => {
+            Scr => {
+                // Scroll right 4 pixels
+                self.scroll(4, 0);
+            }
+            Scl => {
+                // Scroll left 4 pixels
+                self.scroll(-4, 0);
+            }
+            Exit => {
+                // Exit the interpreter
+                self.halted = true;
+            }
+            Low => {
+                // Low resolution mode
+                self.hires = false;
+                self.screen = [0; SCREEN_SIZE];
+            }
+            High => {
+                // High resolution mode
+                self.hires = true;
+                self.screen = [0; SCREEN_SIZE];
+            }
+            Jp(nnn) => {
                 // Jump to NNN
-                let nnn = op_code & 0xFFF;
                 self.pc = nnn;
             }
-            (2, _, _, _) => {
+            Call(nnn) => {
                 // Call Subroutine
-                let nnn = op_code & 0xFFF;
-                self.stack.push(self.pc);
+                self.stack.push(self.pc)?;
                 self.pc = nnn;
             }
-            (3, x, _, _) => {
+            SeByte { x, nn } => {
                 // Skip if VX == NN
-                let nn = (op_code & 0xFF) as u8;
                 let vx = self.v_regs[x as usize];
                 if nn == vx {
-                    self.pc += 2;
+                    self.skip_next()?;
                 }
             }
-            (4, x, _, _) => {
+            SneByte { x, nn } => {
                 // Skip if VX != NN
-                let nn = (op_code & 0xFF) as u8;
                 let vx = self.v_regs[x as usize];
                 if nn != vx {
-                    self.pc += 2;
+                    self.skip_next()?;
                 }
             }
-            (5, x, y, 0) => {
+            SeReg { x, y } => {
                 // Skip if VX == VY
                 let vx = self.v_regs[x as usize];
                 let vy = self.v_regs[y as usize];
                 if vx == vy {
-                    self.pc += 2;
+                    self.skip_next()?;
+                }
+            }
+            SaveRange { x, y } => {
+                // Store VX - VY into I
+                let addr = self.i_reg as usize;
+                for (offset, idx) in Self::reg_range(x, y).enumerate() {
+                    self.write_ram(addr + offset, self.v_regs[idx])?;
+                }
+            }
+            LoadRange { x, y } => {
+                // Load I into VX - VY
+                let addr = self.i_reg as usize;
+                for (offset, idx) in Self::reg_range(x, y).enumerate() {
+                    self.v_regs[idx] = self.read_data(addr + offset)?;
                 }
             }
-            (6, x, _, _) => {
+            LdByte { x, nn } => {
                 // VX = NN
-                let nn = (op_code & 0xFF) as u8;
                 self.v_regs[x as usize] = nn;
             }
-            (7, x, _, _) => {
+            AddByte { x, nn } => {
                 // VX += NN
-                let nn = (op_code & 0xFF) as u8;
                 let new_vx = self.v_regs[x as usize].wrapping_add(nn);
                 self.v_regs[x as usize] = new_vx;
             }
-            (8, x, y, 0) => {
+            LdReg { x, y } => {
                 // VX = VY
                 self.v_regs[x as usize] = self.v_regs[y as usize];
             }
-            (8, x, y, 1) => {
+            Or { x, y } => {
                 // VX |= VY
                 self.v_regs[x as usize] |= self.v_regs[y as usize];
+                self.vf_reset();
             }
-            (8, x, y, 2) => {
+            And { x, y } => {
                 // VX &= VY
                 self.v_regs[x as usize] &= self.v_regs[y as usize];
+                self.vf_reset();
             }
-            (8, x, y, 3) => {
+            Xor { x, y } => {
                 // VX ^= VY
                 self.v_regs[x as usize] ^= self.v_regs[y as usize];
+                self.vf_reset();
             }
-            (8, x, y, 4) => {
+            AddReg { x, y } => {
                 // VX += VY
                 let vx = self.v_regs[x as usize];
                 let vy = self.v_regs[y as usize];
@@ -272,7 +643,7 @@ impl C8Emulator {
                 self.v_regs[x as usize] = new_vx;
                 self.v_regs[0xF] = if carry { 1 } else { 0 };
             }
-            (8, x, y, 5) => {
+            Sub { x, y } => {
                 // VX -= VY
                 let vx = self.v_regs[x as usize];
                 let vy = self.v_regs[y as usize];
@@ -281,14 +652,15 @@ impl C8Emulator {
                 self.v_regs[x as usize] = new_vx;
                 self.v_regs[0xF] = if borrow { 0 } else { 1 };
             }
-            (8, x, _, 6) => {
+            Shr { x, y } => {
                 // VX >>= 1; VF = lsb
+                self.shift_source(x, y);
                 let lsb = self.v_regs[x as usize] & 1;
 
                 self.v_regs[x as usize] >>= 1;
                 self.v_regs[0xF] = lsb;
             }
-            (8, x, y, 7) => {
+            Subn { x, y } => {
                 // VX = VY - VX
                 let vx = self.v_regs[x as usize];
                 let vy = self.v_regs[y as usize];
@@ -297,103 +669,93 @@ impl C8Emulator {
                 self.v_regs[x as usize] = new_vx;
                 self.v_regs[0xF] = if borrow { 0 } else { 1 };
             }
-            (8, x, _, 0xE) => {
+            Shl { x, y } => {
                 // VX <<= 1; VF = msb
+                self.shift_source(x, y);
                 let msb = (self.v_regs[x as usize] >> 7) & 1;
 
                 self.v_regs[x as usize] <<= 1;
                 self.v_regs[0xF] = msb;
             }
-            (9, x, y, 0) => {
+            SneReg { x, y } => {
                 // Skip if VX != VY
                 let vx = self.v_regs[x as usize];
                 let vy = self.v_regs[y as usize];
                 if vx != vy {
-                    self.pc += 2;
+                    self.skip_next()?;
                 }
             }
-            (0xA, _, _, _) => {
+            LdI(nnn) => {
                 // I = NNN
-                let nnn = op_code & 0xFFF;
                 self.i_reg = nnn;
             }
-            (0xB, _, _, _) => {
-                // Jump to V0 + NNN
-                let v0 = self.v_regs[0] as u16;
-                let nnn = op_code & 0xFFF;
-                self.pc = v0 + nnn;
+            JpV0(nnn) => {
+                // Jump to V0 + NNN (or VX + XNN)
+                let reg = if self.quirks.jump_vx { nnn >> 8 } else { 0 };
+                let offset = self.v_regs[reg as usize] as u16;
+                self.pc = offset + nnn;
             }
-            (0xC, x, _, _) => {
+            Rnd { x, nn } => {
                 // VX = rand_gen() & NN
-                let rand = self.rand_gen.rand_u8();
-                let nn = (op_code & 0xFF) as u8;
+                let rand = self.rand_gen.next_u8();
                 self.v_regs[x as usize] = rand & nn;
             }
-
-            (0xD, 0xD, 0xD, 0xD) => {
+            RandomScreen => {
                 // (Custom instruction) Draw a random screen
-                for i in 0..32 {
-                    for j in 0..64 {
-                        let pixel = if self.rand_gen.rand_u8() > 127 {
-                            true
-                        } else {
-                            false
-                        };
-                        self.screen[j + SCREEN_WIDTH * i] = pixel;
+                let width = self.screen_width();
+                for i in 0..self.screen_height() {
+                    for j in 0..width {
+                        let pixel = self.rand_gen.next_u8() > 127;
+                        self.screen[j + width * i] = if pixel { self.planes } else { 0 };
                     }
                 }
             }
-            (0xD, vx, vy, n) => {
+            Drw { x, y, n: 0 } => {
+                // Draw 16x16 Sprite
+                self.draw_sprite(x, y, 16, 16)?;
+            }
+            Drw { x, y, n } => {
                 // Draw Sprite
-                let sprite_p = self.i_reg as usize;
-
-                let x = self.v_regs[vx as usize] as usize;
-                let y = self.v_regs[vy as usize] as usize;
-
-                let mut flipped = false;
-                for i in 0..n {
-                    let sprite_row = self.ram[sprite_p + i as usize];
-
-                    for j in 0..8 {
-                        if (sprite_row & (0b_1000_0000 >> j)) != 0 {
-                            let x_coord = (x + j) % SCREEN_WIDTH;
-                            let y_coord = (y + i as usize) % SCREEN_HEIGHT;
-
-                            let idx = x_coord + SCREEN_WIDTH * y_coord;
-
-                            flipped |= self.screen[idx];
-                            self.screen[idx] ^= true;
-                        }
-                    }
-                }
-
-                if flipped {
-                    self.v_regs[0xF] = 1;
-                } else {
-                    self.v_regs[0xF] = 0;
-                }
+                self.draw_sprite(x, y, 8, n as usize)?;
             }
-            (0xE, x, 9, 0xE) => {
+            Skp(x) => {
                 // Skip if Key pressed
                 let vx = self.v_regs[x as usize];
-                let key = self.keys[vx as usize];
-                if key {
-                    self.pc += 2;
+                if self.key_pressed(vx)? {
+                    self.skip_next()?;
                 }
             }
-            (0xE, x, 0xA, 1) => {
+            Sknp(x) => {
                 // Skip if not Key pressed
                 let vx = self.v_regs[x as usize];
-                let key = self.keys[vx as usize];
-                if !key {
-                    self.pc += 2;
+                if !self.key_pressed(vx)? {
+                    self.skip_next()?;
                 }
             }
-            (0xF, x, 0, 7) => {
+            LdILong => {
+                // I = NNNN (the address is the next word)
+                let addr = self.pc as usize;
+                let hi = self.read_ram(addr)?;
+                let lo = self.read_ram(addr + 1)?;
+                self.i_reg = (hi as u16) << 8 | lo as u16;
+                self.pc = self.pc.wrapping_add(2);
+            }
+            Plane(n) => {
+                // Select the drawing planes
+                self.planes = n;
+            }
+            Audio => {
+                // Load the audio pattern from I
+                let addr = self.i_reg as usize;
+                for idx in 0..AUDIO_PATTERN_SIZE {
+                    self.audio_pattern[idx] = self.read_data(addr + idx)?;
+                }
+            }
+            LdVxDt(x) => {
                 // VX = Delay_T
                 self.v_regs[x as usize] = self.delay_t;
             }
-            (0xF, x, 0, 0xA) => {
+            LdKey(x) => {
                 // Wait for pressing key
                 let mut pressed = false;
                 for i in 0..self.keys.len() {
@@ -405,24 +767,31 @@ impl C8Emulator {
                 }
 
                 if !pressed {
-                    self.pc -= 2;
+                    self.pc = self.pc.wrapping_sub(2);
+                    if self.has_observers() {
+                        self.notify(|observer, c8| observer.on_key_wait(c8, x));
+                    }
                 }
             }
-            (0xF, x, 1, 5) => {
+            LdDt(x) => {
                 // Delay_T = VX
                 self.delay_t = self.v_regs[x as usize];
             }
-            (0xF, x, 1, 8) => {
+            LdSt(x) => {
                 // Sound_T = VX
+                let was_sounding = self.sound_t > 0;
                 self.sound_t = self.v_regs[x as usize];
+                if self.has_observers() {
+                    self.notify_sound(was_sounding);
+                }
             }
-            (0xF, x, 1, 0xE) => {
+            AddI(x) => {
                 // I += VX
                 let vx = self.v_regs[x as usize] as u16;
                 let i_reg = self.i_reg;
                 self.i_reg = i_reg.wrapping_add(vx);
             }
-            (0xF, x, 2, 9) => {
+            LdF(x) => {
                 // I = FONT ADDRESS (vx = font_value)
                 let vx = self.v_regs[x as usize] as u16;
 
@@ -431,7 +800,14 @@ impl C8Emulator {
                 // to obtain the start address of the sprite.
                 self.i_reg = vx * 5;
             }
-            (0xF, x, 3, 3) => {
+            LdHf(x) => {
+                // I = BIG FONT ADDRESS (vx = font_value)
+                let vx = self.v_regs[x as usize] as u16;
+
+                // big fonts are stored right after the small ones
+                self.i_reg = FONTSET_SIZE as u16 + vx * 10;
+            }
+            Bcd(x) => {
                 // I = BCD of VX
                 let vx = self.v_regs[x as usize];
 
@@ -440,35 +816,193 @@ impl C8Emulator {
                 let d_3 = vx % 10;
 
                 let i_reg = self.i_reg as usize;
-                self.ram[i_reg] = d_1; // decimal1
-                self.ram[i_reg + 1] = d_2; // decimal2
-                self.ram[i_reg + 2] = d_3; // decimal3
+                self.write_ram(i_reg, d_1)?; // decimal1
+                self.write_ram(i_reg + 1, d_2)?; // decimal2
+                self.write_ram(i_reg + 2, d_3)?; // decimal3
             }
-            (0xF, x, 5, 5) => {
+            Pitch(x) => {
+                // Pitch = VX
+                self.pitch = self.v_regs[x as usize];
+            }
+            Store(x) => {
                 // Store V0 - VX into I
 
                 let addr = self.i_reg as usize;
-                for idx in 0..=x {
-                    self.ram[addr + idx as usize] = self.v_regs[idx as usize];
+                for idx in 0..=x as usize {
+                    self.write_ram(addr + idx, self.v_regs[idx])?;
                 }
+                self.load_store_increment(x);
             }
-            (0xF, x, 6, 5) => {
+            Load(x) => {
                 // Load I into V0 - VX
 
                 let addr = self.i_reg as usize;
-                for idx in 0..=x {
-                    self.v_regs[idx as usize] = self.ram[addr + idx as usize];
+                for idx in 0..=x as usize {
+                    self.v_regs[idx] = self.read_data(addr + idx)?;
+                }
+                self.load_store_increment(x);
+            }
+            SaveFlags(x) => {
+                // Store V0 - VX into the user flags
+                for idx in 0..=x as usize {
+                    self.rpl[idx] = self.v_regs[idx];
+                }
+            }
+            LoadFlags(x) => {
+                // Load the user flags into V0 - VX
+                for idx in 0..=x as usize {
+                    self.v_regs[idx] = self.rpl[idx];
+                }
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Draw a sprite `width` pixels wide (8 or 16) and `height` rows high
+    /// from I at (VX, VY), VF is set if any lit pixel gets turned off.
+    /// With more planes selected the sprite of each plane follows the
+    /// previous one in memory.
+    fn draw_sprite(&mut self, vx: u8, vy: u8, width: usize, height: usize) -> Result<(), C8Error> {
+        let mut sprite_p = self.i_reg as usize;
+        let bytes_per_row = width / 8;
+        let screen_width = self.screen_width();
+        let screen_height = self.screen_height();
+
+        // the starting position always wraps around the screen
+        let x = self.v_regs[vx as usize] as usize % screen_width;
+        let y = self.v_regs[vy as usize] as usize % screen_height;
+        let clipping = self.quirks.clipping;
+
+        let mut flipped = false;
+        for plane in [0b01, 0b10] {
+            if self.planes & plane == 0 {
+                continue;
+            }
+
+            for i in 0..height {
+                let mut sprite_row = 0u16;
+                for b in 0..bytes_per_row {
+                    let byte = self.read_data(sprite_p + i * bytes_per_row + b)?;
+                    sprite_row = sprite_row << 8 | byte as u16;
+                }
+
+                for j in 0..width {
+                    if (sprite_row & (1 << (width - 1 - j))) != 0 {
+                        let x_coord = x + j;
+                        let y_coord = y + i;
+                        if clipping && (x_coord >= screen_width || y_coord >= screen_height) {
+                            continue;
+                        }
+                        let x_coord = x_coord % screen_width;
+                        let y_coord = y_coord % screen_height;
+
+                        let idx = x_coord + screen_width * y_coord;
+
+                        flipped |= self.screen[idx] & plane != 0;
+                        self.screen[idx] ^= plane;
+                    }
                 }
             }
-            (_, _, _, _) => unimplemented!("op_code 0x{:x}", op_code),
+            sprite_p += height * bytes_per_row;
+        }
+
+        if flipped {
+            self.v_regs[0xF] = 1;
+        } else {
+            self.v_regs[0xF] = 0;
+        }
+
+        if self.has_observers() {
+            // every row was read above, so the range is in memory
+            let sprite = self.ram[self.i_reg as usize..sprite_p].to_vec();
+            self.notify(|observer, c8| observer.on_draw(c8, x as u8, y as u8, &sprite, flipped));
+        }
+
+        self.waiting_vblank = self.quirks.display_wait;
+        Ok(())
+    }
+
+    /// Move the selected planes by `dx` columns and `dy` rows,
+    /// pixels scrolled in from the edges are off.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.screen_width() as isize;
+        let height = self.screen_height() as isize;
+        let planes = self.planes;
+        let old = self.screen;
+
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let inside = (0..width).contains(&src_x) && (0..height).contains(&src_y);
+                let moved = if inside {
+                    old[(src_x + width * src_y) as usize] & planes
+                } else {
+                    0
+                };
+
+                let idx = (x + width * y) as usize;
+                self.screen[idx] = (self.screen[idx] & !planes) | moved;
+            }
+        }
+    }
+
+    /// Skip the next instruction, in XO-CHIP mode the four bytes long
+    /// F000 NNNN is skipped as a whole.
+    fn skip_next(&mut self) -> Result<(), C8Error> {
+        if self.xo_chip {
+            let addr = self.pc as usize;
+            if self.read_ram(addr)? == 0xF0 && self.read_ram(addr + 1)? == 0x00 {
+                self.pc = self.pc.wrapping_add(2);
+            }
+        }
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    /// Register indexes from VX to VY, backwards when X > Y.
+    fn reg_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+        let (x, y) = (x as usize, y as usize);
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    /// 8xy1/8xy2/8xy3 quirk: the logic operations reset VF.
+    fn vf_reset(&mut self) {
+        if self.quirks.vf_reset {
+            self.v_regs[0xF] = 0;
+        }
+    }
+
+    /// 8xy6/8xyE quirk: without it VY is copied into VX before shifting.
+    fn shift_source(&mut self, x: u8, y: u8) {
+        if !self.quirks.shift {
+            self.v_regs[x as usize] = self.v_regs[y as usize];
+        }
+    }
+
+    /// Fx55/Fx65 quirk: I is moved past the registers accessed.
+    fn load_store_increment(&mut self, x: u8) {
+        let increment = match self.quirks.load_store {
+            LoadStore::Unchanged => return,
+            LoadStore::AddX => x as u16,
+            LoadStore::AddXPlusOne => x as u16 + 1,
         };
+        self.i_reg = self.i_reg.wrapping_add(increment);
+    }
+}
+
+impl Default for C8Emulator {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{io::Write, thread::sleep, time::Duration};
-
     use super::*;
 
     const MAZE: [u8; 34] = [
@@ -491,18 +1025,22 @@ mod tests {
         assert_eq!(STACK_SIZE, stack.arr.len());
         assert_eq!(0, stack.sp);
 
-        stack.push(10);
+        stack.push(10).unwrap();
         assert_eq!(1, stack.sp);
 
-        assert_eq!(10, stack.pop());
+        assert_eq!(Ok(10), stack.pop());
         assert_eq!(0, stack.sp);
     }
 
     #[test]
-    #[should_panic]
     fn invalid_sp() {
         let mut stack = Stack::new();
-        stack.pop();
+        assert_eq!(Err(C8Error::StackUnderflow), stack.pop());
+
+        for i in 0..STACK_SIZE {
+            stack.push(i as u16).unwrap();
+        }
+        assert_eq!(Err(C8Error::StackOverflow), stack.push(0));
     }
 
     // C8 Init tests
@@ -520,7 +1058,7 @@ mod tests {
         // modify state
         c8.sound_t = 10;
         c8.ram[START_ADDR + 1] = 10;
-        c8.stack.push(10);
+        c8.stack.push(10).unwrap();
 
         // reset state
         c8.reset();
@@ -538,11 +1076,11 @@ mod tests {
         let mut c8 = C8Emulator::new();
 
         // add a random opcode in memory
-        c8.ram[START_ADDR] = 0xF0 as u8;
-        c8.ram[START_ADDR + 1] = 0x02 as u8;
+        c8.ram[START_ADDR] = 0xF0;
+        c8.ram[START_ADDR + 1] = 0x02;
 
-        let expected_op = 0xF002 as u16;
-        let fetched_op = c8.fetch(); // fetched from memory
+        let expected_op = 0xF002;
+        let fetched_op = c8.fetch().unwrap(); // fetched from memory
 
         assert_eq!(expected_op, fetched_op);
     }
@@ -552,10 +1090,10 @@ mod tests {
         let mut c8 = C8Emulator::new();
 
         // 1NNN - Jump to NNN
-        c8.ram[START_ADDR] = 0x13 as u8;
-        c8.ram[START_ADDR + 1] = 0x33 as u8;
+        c8.ram[START_ADDR] = 0x13;
+        c8.ram[START_ADDR + 1] = 0x33;
 
-        c8.cpu_cycle();
+        c8.cpu_cycle().unwrap();
 
         // program counter jumped to 0x333
         assert_eq!(0x333, c8.pc);
@@ -570,81 +1108,388 @@ mod tests {
         c8.v_regs[5] = 234;
 
         // FX33 - BCD of VX
-        c8.ram[START_ADDR] = 0xF5 as u8;
-        c8.ram[START_ADDR + 1] = 0x33 as u8;
+        c8.ram[START_ADDR] = 0xF5;
+        c8.ram[START_ADDR + 1] = 0x33;
 
-        c8.cpu_cycle();
+        c8.cpu_cycle().unwrap();
 
         assert_eq!(2, c8.ram[bcd_addr]);
         assert_eq!(3, c8.ram[bcd_addr + 1]);
         assert_eq!(4, c8.ram[bcd_addr + 2]);
     }
 
-    // test ROMs
+    // Fault tests
+
+    #[test]
+    fn unknown_opcode() {
+        let mut c8 = C8Emulator::new();
 
-    const MAZE_FIRST_LINE: &str =
-        "*   *     *   *   *   * *     * *   *     *   * *   *     *   * ";
+        c8.ram[START_ADDR] = 0xFF;
+        c8.ram[START_ADDR + 1] = 0xFF;
 
-    const MAZE_LAST_LINE: &str = "   *   *   *   *   *   *   *   *   *   *   *   *   *   *   *   *";
+        let expected = C8Error::UnknownOpcode {
+            op_code: 0xFFFF,
+            addr: START_ADDR as u16,
+        };
+        assert_eq!(Err(expected), c8.cpu_cycle());
+
+        // the program counter stays on the faulting instruction
+        assert_eq!(START_ADDR, c8.pc as usize);
+    }
 
     #[test]
-    fn execute_1000_instructions_of_maze() {
+    fn memory_fault() {
         let mut c8 = C8Emulator::new();
 
-        c8.load(&MAZE);
+        // Fx55 - Store V0 - VX into I, with I at the end of ram
+        c8.i_reg = (RAM_SIZE - 1) as u16;
+        c8.ram[START_ADDR] = 0xF1;
+        c8.ram[START_ADDR + 1] = 0x55;
 
-        let mut counter = 0;
-        loop {
-            c8.cpu_cycle();
+        let expected = C8Error::MemoryFault { addr: RAM_SIZE };
+        assert_eq!(Err(expected), c8.cpu_cycle());
 
-            counter += 1;
-            sleep(Duration::from_millis(1));
-            if counter % 33 == 0 {
-                print_screen(&c8);
-            }
-            if counter == 1000 {
-                let line1: String = c8.get_screen()[0..64]
-                    .iter()
-                    .map(|x| if *x { '*' } else { ' ' })
-                    .collect();
+        // fetching past the end of ram
+        c8.pc = (RAM_SIZE - 1) as u16;
+        let expected = C8Error::MemoryFault { addr: RAM_SIZE };
+        assert_eq!(Err(expected), c8.cpu_cycle());
+    }
 
-                let line2: String = c8.get_screen()[1984..2048]
-                    .iter()
-                    .map(|x| if *x { '*' } else { ' ' })
-                    .collect();
+    #[test]
+    fn pc_wraps_around() {
+        let mut c8 = C8Emulator::new();
+        c8.set_xo_chip(true);
 
-                assert_eq!(MAZE_FIRST_LINE, line1);
-                assert_eq!(MAZE_LAST_LINE, line2);
-                break;
-            }
+        // 6000 - V0 = 0, up to the last word of the 64 KiB
+        let rom: Vec<u8> = [0x60, 0x00].repeat(0xFE00 / 2);
+        c8.load(&rom).unwrap();
+        for _ in 0..rom.len() / 2 {
+            c8.cpu_cycle().unwrap();
         }
+        assert_eq!(0, c8.pc);
+
+        // a skip over F000 NNNN at the end wraps too
+        c8.pc = 0xFFFA;
+        c8.ram[0xFFFA..].copy_from_slice(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34]);
+        c8.cpu_cycle().unwrap();
+        assert_eq!(0x0000, c8.pc);
+
+        // F00A - wait for a key, in the last word
+        c8.pc = 0xFFFE;
+        c8.ram[0xFFFE..].copy_from_slice(&[0xF0, 0x0A]);
+        c8.cpu_cycle().unwrap();
+        assert_eq!(0xFFFE, c8.pc);
     }
 
-    fn print_screen(emu: &C8Emulator) {
-        let screen = emu.get_screen();
+    #[test]
+    fn rom_too_large() {
+        let mut c8 = C8Emulator::new();
 
-        // clear the screen
-        print!("\x1B[2J");
+        let max = RAM_SIZE - START_ADDR;
+        assert_eq!(Ok(()), c8.load(&vec![0; max]));
 
-        // move to the top-left
-        print!("\x1B[1;1H");
+        let expected = C8Error::RomTooLarge { size: max + 1, max };
+        assert_eq!(Err(expected), c8.load(&vec![0; max + 1]));
+    }
 
-        // flush the changes
-        std::io::stdout().flush().unwrap();
+    #[test]
+    fn invalid_key() {
+        let mut c8 = C8Emulator::new();
 
-        for i in 0..32 {
-            for j in 0..64 {
-                let pixel = if screen[j + SCREEN_WIDTH * i] {
-                    '*'
-                } else {
-                    ' '
-                };
-                print!("{pixel}");
-            }
-            println!();
+        assert_eq!(Ok(()), c8.press_key(0xF, true));
+        assert_eq!(Err(C8Error::InvalidKey(16)), c8.press_key(16, true));
+
+        // Ex9E - Skip if Key pressed, with VX out of the keypad
+        c8.v_regs[0] = 0x20;
+        c8.ram[START_ADDR] = 0xE0;
+        c8.ram[START_ADDR + 1] = 0x9E;
+
+        assert_eq!(Err(C8Error::InvalidKey(0x20)), c8.cpu_cycle());
+    }
+
+    // Quirks tests
+
+    /// Load the opcodes at START_ADDR and execute them.
+    fn run_ops(c8: &mut C8Emulator, ops: &[u16]) {
+        let rom: Vec<u8> = ops.iter().flat_map(|op| op.to_be_bytes()).collect();
+        c8.load(&rom).unwrap();
+        for _ in ops {
+            c8.cpu_cycle().unwrap();
         }
+    }
 
-        // flush the changes
-        std::io::stdout().flush().unwrap();
+    #[test]
+    fn shift_quirk() {
+        let mut c8 = C8Emulator::with_quirks(Quirks::chip48());
+        c8.v_regs[1] = 0b0000_0110;
+        c8.v_regs[2] = 0b0000_0001;
+        // 8126 - V1 >>= 1
+        run_ops(&mut c8, &[0x8126]);
+        assert_eq!(0b0000_0011, c8.v_regs[1]);
+        assert_eq!(0, c8.v_regs[0xF]);
+
+        let mut c8 = C8Emulator::with_quirks(Quirks::cosmac_vip());
+        c8.v_regs[1] = 0b0000_0110;
+        c8.v_regs[2] = 0b0000_0001;
+        // 8126 - V1 = V2 >> 1
+        run_ops(&mut c8, &[0x8126]);
+        assert_eq!(0, c8.v_regs[1]);
+        assert_eq!(1, c8.v_regs[0xF]);
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        let mut c8 = C8Emulator::with_quirks(Quirks::cosmac_vip());
+        c8.v_regs[0xF] = 5;
+        // 8121 - V1 |= V2
+        run_ops(&mut c8, &[0x8121]);
+        assert_eq!(0, c8.v_regs[0xF]);
+
+        let mut c8 = C8Emulator::with_quirks(Quirks::super_chip());
+        c8.v_regs[0xF] = 5;
+        run_ops(&mut c8, &[0x8121]);
+        assert_eq!(5, c8.v_regs[0xF]);
+    }
+
+    #[test]
+    fn jump_quirk() {
+        let mut c8 = C8Emulator::with_quirks(Quirks::super_chip());
+        c8.v_regs[0] = 1;
+        c8.v_regs[3] = 2;
+        // B300 - jump to 0x300 + V3
+        run_ops(&mut c8, &[0xB300]);
+        assert_eq!(0x302, c8.pc);
+
+        let mut c8 = C8Emulator::with_quirks(Quirks::cosmac_vip());
+        c8.v_regs[0] = 1;
+        c8.v_regs[3] = 2;
+        // B300 - jump to 0x300 + V0
+        run_ops(&mut c8, &[0xB300]);
+        assert_eq!(0x301, c8.pc);
+    }
+
+    #[test]
+    fn load_store_quirk() {
+        let mut c8 = C8Emulator::with_quirks(Quirks::cosmac_vip());
+        c8.i_reg = 0x300;
+        // F255 - store V0 - V2
+        run_ops(&mut c8, &[0xF255]);
+        assert_eq!(0x303, c8.i_reg);
+
+        let mut c8 = C8Emulator::with_quirks(Quirks::chip48());
+        c8.i_reg = 0x300;
+        run_ops(&mut c8, &[0xF255]);
+        assert_eq!(0x302, c8.i_reg);
+
+        let mut c8 = C8Emulator::with_quirks(Quirks::super_chip());
+        c8.i_reg = 0x300;
+        run_ops(&mut c8, &[0xF265]);
+        assert_eq!(0x300, c8.i_reg);
+    }
+
+    #[test]
+    fn clipping_quirk() {
+        // draw the "0" glyph at the bottom right corner
+        let ops = [0x603E, 0x611E, 0xA000, 0xD015];
+
+        let mut c8 = C8Emulator::with_quirks(Quirks::super_chip());
+        run_ops(&mut c8, &ops);
+        let lit = c8
+            .get_screen()
+            .pixels()
+            .iter()
+            .filter(|px| **px != 0)
+            .count();
+        assert_eq!(3, lit); // only the top-left 2x2 corner of the glyph
+        assert!(!c8.get_screen().pixel(1, 0));
+
+        let mut c8 = C8Emulator::with_quirks(Quirks::xo_chip());
+        run_ops(&mut c8, &ops);
+        let lit = c8
+            .get_screen()
+            .pixels()
+            .iter()
+            .filter(|px| **px != 0)
+            .count();
+        assert_eq!(14, lit); // the whole glyph, wrapped around
+        assert!(c8.get_screen().pixel(1, 0));
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        let mut c8 = C8Emulator::with_quirks(Quirks::cosmac_vip());
+        // D005 then 6001 - V0 = 1
+        let rom = [0xD0, 0x05, 0x60, 0x01];
+        c8.load(&rom).unwrap();
+
+        c8.cpu_cycle().unwrap();
+        c8.cpu_cycle().unwrap();
+        assert_eq!(START_ADDR + 2, c8.pc as usize); // waiting for the frame
+        assert_eq!(0, c8.v_regs[0]);
+
+        c8.frame_cycle();
+        c8.cpu_cycle().unwrap();
+        assert_eq!(1, c8.v_regs[0]);
+    }
+
+    // SUPER-CHIP tests
+
+    #[test]
+    fn hires_and_big_sprite() {
+        let mut c8 = C8Emulator::with_quirks(Quirks::super_chip());
+        c8.ram[0x300..0x320].fill(0xFF);
+
+        // 00FF - hires, 6064 - V0 = 100, A300 - I = 0x300, D010 - 16x16 sprite
+        run_ops(&mut c8, &[0x00FF, 0x6064, 0xA300, 0xD010]);
+
+        let screen = c8.get_screen();
+        assert_eq!(HIRES_SCREEN_WIDTH, screen.width());
+        assert_eq!(HIRES_SCREEN_HEIGHT, screen.height());
+        assert_eq!(256, screen.pixels().iter().filter(|px| **px != 0).count());
+        assert!(screen.pixel(100, 0) && screen.pixel(115, 15));
+        assert!(!screen.pixel(116, 0) && !screen.pixel(100, 16));
+    }
+
+    #[test]
+    fn scroll_screen() {
+        let mut c8 = C8Emulator::new();
+        c8.screen[0] = 1;
+
+        // 00C2 - scroll down 2, 00FB - scroll right 4
+        run_ops(&mut c8, &[0x00C2, 0x00FB]);
+        assert!(c8.get_screen().pixel(4, 2));
+        assert_eq!(1, c8.screen.iter().filter(|px| **px != 0).count());
+
+        // 00FC - scroll left 4, twice: the pixel leaves the screen
+        c8.pc = START_ADDR as u16;
+        run_ops(&mut c8, &[0x00FC, 0x00FC]);
+        assert_eq!(0, c8.screen.iter().filter(|px| **px != 0).count());
+    }
+
+    #[test]
+    fn exit_interpreter() {
+        let mut c8 = C8Emulator::new();
+
+        // 00FD - exit
+        run_ops(&mut c8, &[0x00FD]);
+        assert!(c8.is_halted());
+
+        c8.cpu_cycle().unwrap();
+        assert_eq!(START_ADDR + 2, c8.pc as usize);
+
+        c8.reset();
+        assert!(!c8.is_halted());
+    }
+
+    #[test]
+    fn big_font_and_user_flags() {
+        let mut c8 = C8Emulator::new();
+        c8.v_regs[0] = 3;
+        c8.v_regs[1] = 7;
+
+        // F030 - I = big 3, F175 - store V0 - V1 into the flags
+        run_ops(&mut c8, &[0xF030, 0xF175]);
+        assert_eq!(&BIG_FONTSET[30..40], &c8.ram[c8.i_reg as usize..][..10]);
+
+        // the flags survive a reset
+        c8.reset();
+        // F185 - load the flags into V0 - V1
+        run_ops(&mut c8, &[0xF185]);
+        assert_eq!([3, 7], c8.v_regs[..2]);
+    }
+
+    // XO-CHIP tests
+
+    fn xo_chip() -> C8Emulator {
+        let mut c8 = C8Emulator::with_quirks(Quirks::xo_chip());
+        c8.set_xo_chip(true);
+        c8
+    }
+
+    #[test]
+    fn xo_chip_memory() {
+        let mut c8 = xo_chip();
+        assert_eq!(Ok(()), c8.load(&vec![0; XO_RAM_SIZE - START_ADDR]));
+
+        // F000 NNNN - I = 0xFFF0, then Fx65 reads past 4 KiB
+        c8.reset();
+        run_ops(&mut c8, &[0xF000, 0xFFF0, 0xF065]);
+        assert_eq!(0xFFF1, c8.i_reg);
+
+        // without XO-CHIP mode the long load is unknown
+        let mut c8 = C8Emulator::new();
+        c8.load(&[0xF0, 0x00]).unwrap();
+        assert!(matches!(
+            c8.cpu_cycle(),
+            Err(C8Error::UnknownOpcode {
+                op_code: 0xF000,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn skip_long_load() {
+        let mut c8 = xo_chip();
+
+        // 3000 - skip if V0 == 0, over F000 NNNN
+        c8.load(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34]).unwrap();
+        c8.cpu_cycle().unwrap();
+        assert_eq!(START_ADDR + 6, c8.pc as usize);
+    }
+
+    #[test]
+    fn draw_on_planes() {
+        let mut c8 = xo_chip();
+        c8.ram[0x300] = 0x80; // first plane sprite
+        c8.ram[0x301] = 0xC0; // second plane sprite
+
+        // F301 - both planes, A300 - I = 0x300, D001 - draw
+        run_ops(&mut c8, &[0xF301, 0xA300, 0xD001]);
+        let screen = c8.get_screen();
+        assert_eq!(3, screen.color(0, 0));
+        assert_eq!(2, screen.color(1, 0));
+        assert!(screen.plane_pixel(1, 1, 0) && !screen.plane_pixel(0, 1, 0));
+
+        // F201 - second plane, 00E0 - clear it
+        c8.pc = START_ADDR as u16;
+        run_ops(&mut c8, &[0xF201, 0x00E0]);
+        assert_eq!(1, c8.get_screen().color(0, 0));
+        assert_eq!(0, c8.get_screen().color(1, 0));
+    }
+
+    #[test]
+    fn register_ranges() {
+        let mut c8 = xo_chip();
+        c8.v_regs[2..5].copy_from_slice(&[1, 2, 3]);
+        c8.i_reg = 0x300;
+
+        // 5242 - store V2 - V4, 5203 - load V2 - V0 (backwards)
+        run_ops(&mut c8, &[0x5242, 0x5203]);
+        assert_eq!([1, 2, 3], c8.ram[0x300..0x303]);
+        assert_eq!([3, 2, 1], c8.v_regs[0..3]);
+        assert_eq!(0x300, c8.i_reg);
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        let mut c8 = xo_chip();
+        c8.ram[0x300..0x310].copy_from_slice(&[0xAA; AUDIO_PATTERN_SIZE]);
+        c8.v_regs[1] = 100;
+
+        // A300 - I = 0x300, F002 - load the pattern, F13A - pitch = V1
+        run_ops(&mut c8, &[0xA300, 0xF002, 0xF13A]);
+        assert_eq!(&[0xAA; AUDIO_PATTERN_SIZE], c8.audio_pattern());
+        assert_eq!(100, c8.pitch());
+    }
+
+    // test ROMs
+
+    #[test]
+    fn execute_1000_instructions_of_maze() {
+        GoldenTest::new("tests/golden/maze", &MAZE)
+            .with_frames(100)
+            .check()
+            .unwrap_or_else(|err| panic!("{err}"));
     }
 }
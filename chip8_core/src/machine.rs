@@ -0,0 +1,49 @@
+use crate::{C8Emulator, C8Error, Screen};
+
+/// What a frontend needs to show and play an emulated CHIP-8 machine,
+/// so it can switch between `C8Emulator` and the COSMAC VIP running the
+/// original interpreter in `CosmacVip`.
+///
+/// Running the machine isn't part of it: `C8Emulator` is paced by a
+/// `Scheduler` while the VIP follows its own clock, frame by frame.
+pub trait Machine {
+    /// Load a CHIP-8 program at 0x200.
+    fn load(&mut self, rom: &[u8]) -> Result<(), C8Error>;
+
+    /// Start over, the program must be loaded again.
+    fn reset(&mut self);
+
+    fn press_key(&mut self, idx: usize, pressed: bool) -> Result<(), C8Error>;
+
+    fn get_screen(&self) -> Screen<'_>;
+
+    fn delay_timer(&self) -> u8;
+
+    fn sound_timer(&self) -> u8;
+}
+
+impl Machine for C8Emulator {
+    fn load(&mut self, rom: &[u8]) -> Result<(), C8Error> {
+        C8Emulator::load(self, rom)
+    }
+
+    fn reset(&mut self) {
+        C8Emulator::reset(self);
+    }
+
+    fn press_key(&mut self, idx: usize, pressed: bool) -> Result<(), C8Error> {
+        C8Emulator::press_key(self, idx, pressed)
+    }
+
+    fn get_screen(&self) -> Screen<'_> {
+        C8Emulator::get_screen(self)
+    }
+
+    fn delay_timer(&self) -> u8 {
+        C8Emulator::delay_timer(self)
+    }
+
+    fn sound_timer(&self) -> u8 {
+        C8Emulator::sound_timer(self)
+    }
+}
@@ -0,0 +1,450 @@
+use std::fmt;
+
+use crate::{
+    snapshot::{self, Reader},
+    C8Emulator, C8Error, Quirks, SnapshotError, KEYS_SIZE,
+};
+
+/// Every serialized movie starts with these bytes.
+const MAGIC: &[u8; 4] = b"C8MV";
+/// Bumped every time the layout of the serialized movie changes.
+const VERSION: u16 = 2;
+/// Frames between two hashes of the state, a second.
+const HASH_INTERVAL: u64 = 60;
+
+/// The keys held in every frame of a session, with what's needed to
+/// replay it: the hash of the ROM, the state of the random numbers and
+/// the quirks. Hashes of the state taken every second while recording
+/// tell when the playback went another way.
+///
+/// Playback is deterministic only if the emulator runs the same number
+/// of instructions in every frame, the frontend must keep the speed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    rom_hash: u64,
+    rng_state: Vec<u8>,
+    quirks: Quirks,
+    xo_chip: bool,
+    /// The keys of every frame, a bit per key.
+    inputs: Vec<u16>,
+    /// The state hash at the end of every `HASH_INTERVAL` frames.
+    hashes: Vec<u64>,
+}
+
+/// Reasons why a movie can't be read or played.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieError {
+    /// The bytes don't start with the movie magic header.
+    BadMagic,
+    /// The movie was written by an incompatible version.
+    UnsupportedVersion(u16),
+    /// The bytes end before the whole movie is read.
+    Truncated,
+    /// The movie was recorded with another ROM, or other settings.
+    Mismatch(&'static str),
+    /// The state of the random number generator is longer than the 255
+    /// bytes a movie can hold.
+    RngStateTooLarge(usize),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "unsupported movie version {version}")
+            }
+            MovieError::Truncated => write!(f, "truncated movie"),
+            MovieError::Mismatch(what) => write!(f, "the movie was recorded with another {what}"),
+            MovieError::RngStateTooLarge(len) => {
+                write!(f, "random number generator state too large: {len} bytes")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<SnapshotError> for MovieError {
+    /// The reader is shared with the snapshots, it only ever truncates.
+    fn from(_: SnapshotError) -> Self {
+        MovieError::Truncated
+    }
+}
+
+/// What an attached movie is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieStatus {
+    Recording {
+        frame: u64,
+    },
+    Playing {
+        frame: u64,
+        frames: u64,
+    },
+    /// Every frame was played, the keys are live again.
+    Finished {
+        frames: u64,
+    },
+    /// The state differed from the recording at the end of this frame,
+    /// the rest is still played.
+    Desynced {
+        frame: u64,
+    },
+}
+
+impl Movie {
+    /// Number of frames recorded.
+    pub fn frames(&self) -> u64 {
+        self.inputs.len() as u64
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn is_xo_chip(&self) -> bool {
+        self.xo_chip
+    }
+
+    /// Serialize the movie, multi-byte values are little endian.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MovieError> {
+        let len = self.rng_state.len();
+        let rng_len = u8::try_from(len).map_err(|_| MovieError::RngStateTooLarge(len))?;
+        let mut bytes = Vec::with_capacity(32 + 2 * self.inputs.len() + 8 * self.hashes.len());
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.rom_hash.to_le_bytes());
+        bytes.push(rng_len);
+        bytes.extend_from_slice(&self.rng_state);
+        bytes.push(snapshot::pack_quirks(self.quirks));
+        bytes.push(self.xo_chip as u8);
+
+        bytes.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        for keys in &self.inputs {
+            bytes.extend_from_slice(&keys.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.hashes.len() as u32).to_le_bytes());
+        for hash in &self.hashes {
+            bytes.extend_from_slice(&hash.to_le_bytes());
+        }
+
+        Ok(bytes)
+    }
+
+    /// Read back a movie serialized by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, MovieError> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let rom_hash = reader.u64()?;
+        let rng_len = reader.u8()? as usize;
+        let rng_state = reader.take(rng_len)?.to_vec();
+        let quirks = snapshot::unpack_quirks(reader.u8()?);
+        let xo_chip = reader.u8()? != 0;
+        let inputs = (0..reader.u32()?)
+            .map(|_| reader.u16())
+            .collect::<Result<_, _>>()?;
+        let hashes = (0..reader.u32()?)
+            .map(|_| reader.u64())
+            .collect::<Result<_, _>>()?;
+
+        Ok(Movie {
+            rom_hash,
+            rng_state,
+            quirks,
+            xo_chip,
+            inputs,
+            hashes,
+        })
+    }
+}
+
+/// A movie attached to the emulator.
+pub(crate) struct MovieState {
+    movie: Movie,
+    recording: bool,
+    /// The current frame, counted from the start of the movie.
+    frame: u64,
+    /// Keys pressed while recording, they take effect on the next frame.
+    pending: u16,
+    desync: Option<u64>,
+}
+
+impl C8Emulator {
+    /// Start recording the keys in a movie, from the state right after
+    /// `rom` was loaded. While recording, key presses only take effect
+    /// at the start of the next frame, so that playback can put them at
+    /// the same place.
+    pub fn record_movie(&mut self, rom: &[u8]) {
+        let pending = self.key_mask();
+        self.movie = Some(Box::new(MovieState {
+            movie: Movie {
+                rom_hash: snapshot::fnv1a(rom),
+                rng_state: self.rand_gen.state(),
+                quirks: self.quirks,
+                xo_chip: self.xo_chip,
+                inputs: vec![pending],
+                hashes: Vec::new(),
+            },
+            recording: true,
+            frame: 0,
+            pending,
+            desync: None,
+        }));
+    }
+
+    /// Play a movie from the state right after `rom` was loaded, with
+    /// the quirks and XO-CHIP mode it was recorded with. The keys of the
+    /// frontend are ignored until it ends.
+    pub fn play_movie(&mut self, movie: Movie, rom: &[u8]) -> Result<(), MovieError> {
+        if movie.rom_hash != snapshot::fnv1a(rom) {
+            return Err(MovieError::Mismatch("ROM"));
+        }
+        if movie.quirks != self.quirks {
+            return Err(MovieError::Mismatch("quirks"));
+        }
+        if movie.xo_chip != self.xo_chip {
+            return Err(MovieError::Mismatch("XO-CHIP mode"));
+        }
+
+        if !self.rand_gen.set_state(&movie.rng_state) {
+            return Err(MovieError::Mismatch("random number generator"));
+        }
+        self.movie = Some(Box::new(MovieState {
+            movie,
+            recording: false,
+            frame: 0,
+            pending: 0,
+            desync: None,
+        }));
+        self.latch_movie_keys();
+        Ok(())
+    }
+
+    /// Stop recording or playing, and give back the movie.
+    pub fn take_movie(&mut self) -> Option<Movie> {
+        self.movie.take().map(|state| state.movie)
+    }
+
+    pub fn movie_status(&self) -> Option<MovieStatus> {
+        let state = self.movie.as_ref()?;
+        let frames = state.movie.frames();
+        Some(if state.recording {
+            MovieStatus::Recording { frame: state.frame }
+        } else if let Some(frame) = state.desync {
+            MovieStatus::Desynced { frame }
+        } else if state.frame >= frames {
+            MovieStatus::Finished { frames }
+        } else {
+            MovieStatus::Playing {
+                frame: state.frame,
+                frames,
+            }
+        })
+    }
+
+    /// Hold a key press of the frontend while a movie is attached, return
+    /// whether it was taken.
+    pub(crate) fn movie_key(&mut self, idx: usize, pressed: bool) -> Result<bool, C8Error> {
+        if idx >= KEYS_SIZE {
+            return Err(C8Error::InvalidKey(idx));
+        }
+        let Some(state) = self.movie.as_mut() else {
+            return Ok(false);
+        };
+        if state.recording {
+            state.pending = state.pending & !(1 << idx) | (pressed as u16) << idx;
+            return Ok(true);
+        }
+        // the movie presses the keys until it ends
+        Ok(state.frame < state.movie.frames())
+    }
+
+    /// Check or record the state at the end of a frame, then move on to
+    /// the keys of the next one.
+    pub(crate) fn movie_frame(&mut self) {
+        let Some(state) = self.movie.as_ref() else {
+            return;
+        };
+        let frame = state.frame + 1;
+        let hash = frame
+            .is_multiple_of(HASH_INTERVAL)
+            .then(|| self.state_hash());
+
+        let Some(state) = self.movie.as_mut() else {
+            return;
+        };
+        state.frame = frame;
+        if state.recording {
+            state.movie.hashes.extend(hash);
+            state.movie.inputs.push(state.pending);
+        } else if let Some(hash) = hash {
+            let recorded = state.movie.hashes.get((frame / HASH_INTERVAL - 1) as usize);
+            if recorded.is_some_and(|recorded| *recorded != hash) && state.desync.is_none() {
+                state.desync = Some(frame);
+            }
+        }
+        self.latch_movie_keys();
+    }
+
+    fn latch_movie_keys(&mut self) {
+        let Some(state) = self.movie.as_ref() else {
+            return;
+        };
+        let Some(mask) = state.movie.inputs.get(state.frame as usize).copied() else {
+            return;
+        };
+        for (idx, key) in self.keys.iter_mut().enumerate() {
+            *key = mask & (1 << idx) != 0;
+        }
+    }
+
+    fn key_mask(&self) -> u16 {
+        self.keys
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (idx, pressed)| mask | (*pressed as u16) << idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draws random numbers and counts the frames with key 0 held.
+    const ROM: [u8; 8] = [
+        0xC0, 0xFF, // C0FF - V0 = random
+        0xE1, 0xA1, // E1A1 - skip if V1 (key 0) isn't pressed
+        0x72, 0x01, // 7201 - V2 += 1
+        0x12, 0x00, // 1200 - loop
+    ];
+
+    /// Run 10 instructions per frame, pressing the keys of the frontend
+    /// in the middle of some frames.
+    fn run(c8: &mut C8Emulator, frames: u64, presses: &[(u64, bool)]) {
+        for frame in 0..frames {
+            for cycle in 0..10 {
+                if cycle == 5 {
+                    for (_, pressed) in presses.iter().filter(|(at, _)| *at == frame) {
+                        c8.press_key(0, *pressed).unwrap();
+                    }
+                }
+                c8.cpu_cycle().unwrap();
+            }
+            c8.frame_cycle();
+        }
+    }
+
+    fn record(frames: u64) -> (Movie, u64) {
+        let mut c8 = C8Emulator::new();
+        c8.load(&ROM).unwrap();
+        c8.record_movie(&ROM);
+        run(&mut c8, frames, &[(30, true), (70, false), (100, true)]);
+        let hash = c8.state_hash();
+        (c8.take_movie().unwrap(), hash)
+    }
+
+    #[test]
+    fn playback_reproduces_the_session() {
+        let (movie, hash) = record(150);
+        assert_eq!(151, movie.frames());
+        assert_eq!(2, movie.hashes.len());
+
+        let mut c8 = C8Emulator::new();
+        c8.load(&ROM).unwrap();
+        // other seeds and live keys don't matter
+        c8.set_seed(crate::Seed::Fixed(77));
+        c8.play_movie(movie, &ROM).unwrap();
+        run(&mut c8, 150, &[(10, true)]);
+
+        assert_eq!(
+            Some(MovieStatus::Playing {
+                frame: 150,
+                frames: 151
+            }),
+            c8.movie_status()
+        );
+        assert_eq!(hash, c8.state_hash());
+        assert!(c8.v_regs[2] > 0);
+    }
+
+    #[test]
+    fn desync() {
+        let (mut movie, _) = record(130);
+        movie.hashes[1] ^= 1;
+
+        let mut c8 = C8Emulator::new();
+        c8.load(&ROM).unwrap();
+        c8.play_movie(movie, &ROM).unwrap();
+        run(&mut c8, 60, &[]);
+        assert!(matches!(
+            c8.movie_status(),
+            Some(MovieStatus::Playing { .. })
+        ));
+        run(&mut c8, 60, &[]);
+        assert_eq!(
+            Some(MovieStatus::Desynced { frame: 120 }),
+            c8.movie_status()
+        );
+    }
+
+    #[test]
+    fn bytes_and_mismatches() {
+        let (movie, _) = record(61);
+        let bytes = movie.to_bytes().unwrap();
+        assert_eq!(Ok(movie.clone()), Movie::from_bytes(&bytes));
+        assert_eq!(
+            Err(MovieError::Truncated),
+            Movie::from_bytes(&bytes[..bytes.len() - 1])
+        );
+        assert_eq!(Err(MovieError::BadMagic), Movie::from_bytes(b"C8SN"));
+
+        let mut c8 = C8Emulator::with_quirks(Quirks::cosmac_vip());
+        assert_eq!(
+            Err(MovieError::Mismatch("ROM")),
+            c8.play_movie(movie.clone(), &[0x12, 0x00])
+        );
+        assert_eq!(
+            Err(MovieError::Mismatch("quirks")),
+            c8.play_movie(movie, &ROM)
+        );
+    }
+
+    /// A generator with a state too large for movies.
+    struct Wide;
+
+    impl crate::Rng for Wide {
+        fn next_u8(&mut self) -> u8 {
+            0
+        }
+
+        fn reseed(&mut self, _seed: u64) {}
+
+        fn state(&self) -> Vec<u8> {
+            vec![0; 300]
+        }
+
+        fn set_state(&mut self, _state: &[u8]) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn unsaveable_state() {
+        let mut c8 = C8Emulator::new().with_rng(Wide);
+        c8.load(&ROM).unwrap();
+        c8.record_movie(&ROM);
+        run(&mut c8, 2, &[]);
+        let movie = c8.take_movie().unwrap();
+        assert_eq!(Err(MovieError::RngStateTooLarge(300)), movie.to_bytes());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{C8Emulator, Instruction};
+
+/// Callbacks on what the emulated program does, for plugins like
+/// achievement trackers, bots or analytics.
+///
+/// Every method does nothing by default, so observers only implement
+/// the events they care about. The emulator is passed along to read its
+/// state, but it can't be changed from a callback.
+pub trait Observer: Send {
+    /// The instruction at `pc` is about to execute.
+    fn on_instruction(&mut self, c8: &C8Emulator, pc: u16, instruction: Instruction) {
+        let _ = (c8, pc, instruction);
+    }
+
+    /// A sprite was drawn at (`x`, `y`) on the screen, `sprite` holds
+    /// its rows for every selected plane. `collided` is the new VF.
+    fn on_draw(&mut self, c8: &C8Emulator, x: u8, y: u8, sprite: &[u8], collided: bool) {
+        let _ = (c8, x, y, sprite, collided);
+    }
+
+    /// The sound timer was set while silent.
+    fn on_sound_start(&mut self, c8: &C8Emulator) {
+        let _ = c8;
+    }
+
+    /// The sound timer ran out or was cleared.
+    fn on_sound_stop(&mut self, c8: &C8Emulator) {
+        let _ = c8;
+    }
+
+    /// Fx0A found no key pressed, called on every cycle it keeps waiting.
+    fn on_key_wait(&mut self, c8: &C8Emulator, x: u8) {
+        let _ = (c8, x);
+    }
+
+    /// The timers ticked at the end of a frame.
+    fn on_frame(&mut self, c8: &C8Emulator) {
+        let _ = c8;
+    }
+}
+
+/// Identifies a registered observer, to remove it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(usize);
+
+#[derive(Default)]
+pub(crate) struct Observers {
+    list: Vec<(ObserverId, Box<dyn Observer>)>,
+    next_id: usize,
+}
+
+impl Observers {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+}
+
+impl C8Emulator {
+    /// Register an observer, called after the ones already registered.
+    pub fn add_observer(&mut self, observer: impl Observer + 'static) -> ObserverId {
+        let id = ObserverId(self.observers.next_id);
+        self.observers.next_id += 1;
+        self.observers.list.push((id, Box::new(observer)));
+        id
+    }
+
+    /// Unregister an observer and give it back, `None` if it was
+    /// already removed.
+    pub fn remove_observer(&mut self, id: ObserverId) -> Option<Box<dyn Observer>> {
+        let idx = self
+            .observers
+            .list
+            .iter()
+            .position(|(other, _)| *other == id)?;
+        Some(self.observers.list.remove(idx).1)
+    }
+
+    pub(crate) fn has_observers(&self) -> bool {
+        !self.observers.is_empty()
+    }
+
+    /// Call `event` on every observer.
+    pub(crate) fn notify(&mut self, mut event: impl FnMut(&mut dyn Observer, &C8Emulator)) {
+        // the observers are moved out so that they can see the emulator
+        let mut list = std::mem::take(&mut self.observers.list);
+        for (_, observer) in &mut list {
+            event(observer.as_mut(), self);
+        }
+        self.observers.list = list;
+    }
+
+    /// Report the sound starting or stopping, when the sound timer
+    /// changed from `was_sounding`.
+    pub(crate) fn notify_sound(&mut self, was_sounding: bool) {
+        match (was_sounding, self.sound_t > 0) {
+            (false, true) => self.notify(|observer, c8| observer.on_sound_start(c8)),
+            (true, false) => self.notify(|observer, c8| observer.on_sound_stop(c8)),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Recorder {
+        fn push(&self, event: String) {
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn events(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl Observer for Recorder {
+        fn on_instruction(&mut self, _: &C8Emulator, pc: u16, instruction: Instruction) {
+            self.push(format!("{pc:03X} {instruction}"));
+        }
+
+        fn on_draw(&mut self, _: &C8Emulator, x: u8, y: u8, sprite: &[u8], collided: bool) {
+            self.push(format!("draw {x},{y} {sprite:?} {collided}"));
+        }
+
+        fn on_sound_start(&mut self, _: &C8Emulator) {
+            self.push("sound on".to_string());
+        }
+
+        fn on_sound_stop(&mut self, _: &C8Emulator) {
+            self.push("sound off".to_string());
+        }
+
+        fn on_key_wait(&mut self, _: &C8Emulator, x: u8) {
+            self.push(format!("wait V{x:X}"));
+        }
+
+        fn on_frame(&mut self, c8: &C8Emulator) {
+            self.push(format!("frame ST={}", c8.sound_timer()));
+        }
+    }
+
+    #[test]
+    fn events() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&[
+            0x60, 0x02, // 200: LD V0, 0x02
+            0xF0, 0x18, // 202: LD ST, V0
+            0xF0, 0x29, // 204: LD F, V0
+            0xD0, 0x05, // 206: DRW V0, V0, 5
+            0xF1, 0x0A, // 208: LD V1, K
+        ])
+        .unwrap();
+        let recorder = Recorder::default();
+        c8.add_observer(recorder.clone());
+
+        for _ in 0..5 {
+            c8.cpu_cycle().unwrap();
+        }
+        c8.frame_cycle();
+        c8.frame_cycle();
+
+        assert_eq!(
+            vec![
+                "200 LD V0, 0x02",
+                "202 LD ST, V0",
+                "sound on",
+                "204 LD F, V0",
+                "206 DRW V0, V0, 5",
+                "draw 2,2 [240, 16, 240, 128, 240] false",
+                "208 LD V1, K",
+                "wait V1",
+                "frame ST=1",
+                "sound off",
+                "frame ST=0",
+            ],
+            recorder.events()
+        );
+    }
+
+    #[test]
+    fn remove() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&[0x12, 0x00]).unwrap();
+        let first = Recorder::default();
+        let second = Recorder::default();
+        let id = c8.add_observer(first.clone());
+        c8.add_observer(second.clone());
+
+        c8.cpu_cycle().unwrap();
+        assert!(c8.remove_observer(id).is_some());
+        assert!(c8.remove_observer(id).is_none());
+        c8.cpu_cycle().unwrap();
+
+        assert_eq!(1, first.events().len());
+        assert_eq!(2, second.events().len());
+    }
+}
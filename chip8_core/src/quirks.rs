@@ -0,0 +1,113 @@
+/// Interpretations of the CHIP-8 instructions whose behavior changed
+/// between the interpreters of the different platforms.
+///
+/// The presets pick the behaviors of each platform. `Default` is the
+/// behavior of this emulator from before quirks were configurable, which
+/// matches none of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8xy6/8xyE shift VX in place, ignoring VY.
+    /// When disabled VX = VY is shifted, like on the COSMAC VIP.
+    pub shift: bool,
+    /// How far Fx55/Fx65 move I.
+    pub load_store: LoadStore,
+    /// Bnnn jumps to XNN + VX instead of NNN + V0.
+    pub jump_vx: bool,
+    /// 8xy1/8xy2/8xy3 reset VF to 0.
+    pub vf_reset: bool,
+    /// Dxyn clips the sprites at the edges of the screen instead of
+    /// wrapping them around. The starting position always wraps.
+    pub clipping: bool,
+    /// Dxyn waits for the next frame before the execution goes on,
+    /// limiting the drawing to 60 sprites per second.
+    pub display_wait: bool,
+}
+
+/// What Fx55/Fx65 do to I after accessing the registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStore {
+    /// I is left unchanged, like on SUPER-CHIP 1.1.
+    Unchanged,
+    /// I is moved by X, on the last register accessed, like on CHIP-48.
+    AddX,
+    /// I is moved by X + 1, after the last register accessed, like on
+    /// the COSMAC VIP.
+    AddXPlusOne,
+}
+
+impl Quirks {
+    /// The original CHIP-8 interpreter of the COSMAC VIP.
+    pub const fn cosmac_vip() -> Self {
+        Self {
+            shift: false,
+            load_store: LoadStore::AddXPlusOne,
+            jump_vx: false,
+            vf_reset: true,
+            clipping: true,
+            display_wait: true,
+        }
+    }
+
+    /// The CHIP-48 interpreter for the HP-48 calculators, SUPER-CHIP
+    /// 1.1 differs only by leaving I unchanged in Fx55/Fx65.
+    pub const fn chip48() -> Self {
+        Self {
+            shift: true,
+            load_store: LoadStore::AddX,
+            jump_vx: true,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+        }
+    }
+
+    /// The SUPER-CHIP 1.1 interpreter for the HP-48 calculators.
+    pub const fn super_chip() -> Self {
+        Self {
+            shift: true,
+            load_store: LoadStore::Unchanged,
+            jump_vx: true,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+        }
+    }
+
+    /// The XO-CHIP extension, as implemented by Octo.
+    pub const fn xo_chip() -> Self {
+        Self {
+            shift: false,
+            load_store: LoadStore::AddXPlusOne,
+            jump_vx: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+        }
+    }
+
+    /// Look up a preset by name, useful for parsing command line flags.
+    /// Accepted names are `vip`, `chip48`, `schip` and `xochip`.
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "vip" => Some(Self::cosmac_vip()),
+            "chip48" => Some(Self::chip48()),
+            "schip" => Some(Self::super_chip()),
+            "xochip" => Some(Self::xo_chip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    /// The behavior of this emulator before quirks were configurable.
+    fn default() -> Self {
+        Self {
+            shift: true,
+            load_store: LoadStore::Unchanged,
+            jump_vx: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::VecDeque;
+
+use crate::{C8Emulator, Snapshot, SnapshotError};
+
+/// A full snapshot is kept every this many frames, the frames in
+/// between only store what changed since it.
+const KEYFRAME_INTERVAL: usize = 60;
+
+/// Bounded history of the emulator state, one entry per frame.
+///
+/// Frames are grouped in segments: the first frame of every segment is a
+/// serialized keyframe and every frame stores its XOR against it, run
+/// length encoded. Since deltas only depend on their keyframe the oldest
+/// frames can be dropped one by one.
+pub(crate) struct RewindBuffer {
+    capacity: usize,
+    len: usize,
+    segments: VecDeque<Segment>,
+}
+
+struct Segment {
+    keyframe: Vec<u8>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            len: 0,
+            segments: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+        self.segments.clear();
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn push(&mut self, snapshot: &Snapshot) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        // a frame that can't be saved leaves a gap, the history is over
+        let Ok(bytes) = snapshot.to_bytes() else {
+            self.clear();
+            return;
+        };
+        let new_segment = match self.segments.back() {
+            Some(segment) => {
+                // after an XO-CHIP mode switch the layout changes as well
+                segment.deltas.len() >= KEYFRAME_INTERVAL || segment.keyframe.len() != bytes.len()
+            }
+            None => true,
+        };
+
+        if new_segment {
+            self.segments.push_back(Segment {
+                deltas: VecDeque::from([encode_delta(&bytes, &bytes)]),
+                keyframe: bytes,
+            });
+        } else {
+            let segment = self.segments.back_mut().unwrap();
+            let delta = encode_delta(&segment.keyframe, &bytes);
+            segment.deltas.push_back(delta);
+        }
+        self.len += 1;
+
+        while self.len > self.capacity {
+            let oldest = self.segments.front_mut().unwrap();
+            oldest.deltas.pop_front();
+            if oldest.deltas.is_empty() {
+                self.segments.pop_front();
+            }
+            self.len -= 1;
+        }
+    }
+
+    /// Drop the newest `frames` entries, always keeping the oldest one,
+    /// and return how many were dropped.
+    pub(crate) fn pop(&mut self, frames: usize) -> usize {
+        let frames = frames.min(self.len.saturating_sub(1));
+        for _ in 0..frames {
+            let newest = self.segments.back_mut().unwrap();
+            newest.deltas.pop_back();
+            if newest.deltas.is_empty() {
+                self.segments.pop_back();
+            }
+        }
+        self.len -= frames;
+        frames
+    }
+
+    /// The entry `depth` frames before the newest one, if any.
+    pub(crate) fn get(&self, mut depth: usize) -> Result<Option<Snapshot>, SnapshotError> {
+        for segment in self.segments.iter().rev() {
+            match segment.deltas.len().checked_sub(depth + 1) {
+                Some(idx) => {
+                    let bytes = decode_delta(&segment.keyframe, &segment.deltas[idx]);
+                    return Snapshot::from_bytes(&bytes).map(Some);
+                }
+                None => depth -= segment.deltas.len(),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// XOR `bytes` against `keyframe` and compress the result as a sequence
+/// of (zeros run, literals count, literals) tuples, counts are LEB128.
+fn encode_delta(keyframe: &[u8], bytes: &[u8]) -> Vec<u8> {
+    let xored: Vec<u8> = keyframe.iter().zip(bytes).map(|(a, b)| a ^ b).collect();
+
+    let mut delta = Vec::new();
+    let mut idx = 0;
+    while idx < xored.len() {
+        let zeros = xored[idx..].iter().take_while(|b| **b == 0).count();
+        idx += zeros;
+        let literals = xored[idx..].iter().take_while(|b| **b != 0).count();
+
+        write_varint(&mut delta, zeros);
+        write_varint(&mut delta, literals);
+        delta.extend_from_slice(&xored[idx..idx + literals]);
+        idx += literals;
+    }
+    delta
+}
+
+fn decode_delta(keyframe: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut bytes = keyframe.to_vec();
+
+    let mut pos = 0;
+    let mut idx = 0;
+    while pos < delta.len() {
+        idx += read_varint(delta, &mut pos);
+        let literals = read_varint(delta, &mut pos);
+        for byte in &delta[pos..pos + literals] {
+            bytes[idx] ^= byte;
+            idx += 1;
+        }
+        pos += literals;
+    }
+    bytes
+}
+
+fn write_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push((val as u8 & 0x7F) | 0x80);
+        val >>= 7;
+    }
+    out.push(val as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        val |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return val;
+        }
+        shift += 7;
+    }
+}
+
+impl C8Emulator {
+    /// Start recording the state at every `frame_cycle`, keeping the
+    /// last `frames` ones. Any previous history is dropped.
+    pub fn enable_rewind(&mut self, frames: usize) {
+        let mut buffer = RewindBuffer::new(frames);
+        buffer.push(&self.snapshot());
+        self.rewind = Some(buffer);
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    /// How many frames back `rewind` can go.
+    pub fn rewind_len(&self) -> usize {
+        self.rewind
+            .as_ref()
+            .map_or(0, |buffer| buffer.len().saturating_sub(1))
+    }
+
+    /// Go back `frames` frames in the recorded history and return how
+    /// many frames were actually rewound, fewer when the history is
+    /// shorter. Rewound frames are discarded. When the state can't be
+    /// read back, the emulator is left as it was.
+    pub fn rewind(&mut self, frames: usize) -> Result<usize, SnapshotError> {
+        let Some(buffer) = self.rewind.as_ref() else {
+            return Ok(0);
+        };
+
+        let frames = frames.min(buffer.len().saturating_sub(1));
+        if let Some(snapshot) = buffer.get(frames)? {
+            self.restore(&snapshot);
+        }
+        Ok(self.rewind.as_mut().map_or(0, |buffer| buffer.pop(frames)))
+    }
+
+    pub(crate) fn record_frame(&mut self) {
+        if self.rewind.is_some() {
+            let snapshot = self.snapshot();
+            if let Some(buffer) = self.rewind.as_mut() {
+                buffer.push(&snapshot);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Moves a sprite one pixel to the right every frame.
+    const MOVING: [u8; 10] = [
+        0xA0, 0x00, // A000 - I = font 0
+        0xD0, 0x15, // D015 - draw
+        0xD0, 0x15, // D015 - erase
+        0x70, 0x01, // 7001 - V0 += 1
+        0x12, 0x02, // 1202 - loop
+    ];
+
+    fn run_frame(c8: &mut C8Emulator) {
+        for _ in 0..4 {
+            c8.cpu_cycle().unwrap();
+        }
+        c8.frame_cycle();
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let keyframe = vec![0, 1, 2, 3, 4, 5, 6, 7];
+        let bytes = vec![0, 1, 9, 3, 4, 5, 0, 0];
+
+        let delta = encode_delta(&keyframe, &bytes);
+        assert_eq!(bytes, decode_delta(&keyframe, &delta));
+
+        let same = encode_delta(&keyframe, &keyframe);
+        assert_eq!(vec![8, 0], same);
+    }
+
+    #[test]
+    fn rewind_frames() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&MOVING).unwrap();
+        c8.enable_rewind(200);
+
+        let mut history = vec![c8.snapshot()];
+        for _ in 0..150 {
+            run_frame(&mut c8);
+            history.push(c8.snapshot());
+        }
+
+        assert_eq!(150, c8.rewind_len());
+        assert_eq!(10, c8.rewind(10).unwrap());
+        assert_eq!(history[140], c8.snapshot());
+
+        // the history goes on from the rewound frame
+        run_frame(&mut c8);
+        assert_eq!(history[141], c8.snapshot());
+        assert_eq!(1, c8.rewind(1).unwrap());
+        assert_eq!(history[140], c8.snapshot());
+
+        assert_eq!(140, c8.rewind(1000).unwrap());
+        assert_eq!(history[0], c8.snapshot());
+    }
+
+    #[test]
+    fn bounded_history() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&MOVING).unwrap();
+        c8.enable_rewind(100);
+
+        let mut history = vec![c8.snapshot()];
+        for _ in 0..250 {
+            run_frame(&mut c8);
+            history.push(c8.snapshot());
+        }
+
+        assert_eq!(99, c8.rewind_len());
+        assert_eq!(99, c8.rewind(1000).unwrap());
+        assert_eq!(history[151], c8.snapshot());
+    }
+
+    #[test]
+    fn unreadable_state() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&MOVING).unwrap();
+        c8.enable_rewind(100);
+        for _ in 0..10 {
+            run_frame(&mut c8);
+        }
+        let len = c8.rewind_len();
+        let current = c8.snapshot();
+
+        // every frame of the segment reads back as a bad magic
+        let buffer = c8.rewind.as_mut().unwrap();
+        buffer.segments.back_mut().unwrap().keyframe[0] ^= 0xFF;
+        assert_eq!(Err(SnapshotError::BadMagic), c8.rewind(3));
+        assert_eq!(len, c8.rewind_len());
+        assert_eq!(current, c8.snapshot());
+    }
+
+    /// A generator with a state too large for snapshots.
+    struct Wide;
+
+    impl crate::Rng for Wide {
+        fn next_u8(&mut self) -> u8 {
+            0
+        }
+
+        fn reseed(&mut self, _seed: u64) {}
+
+        fn state(&self) -> Vec<u8> {
+            vec![0; 300]
+        }
+
+        fn set_state(&mut self, _state: &[u8]) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn unsaveable_state() {
+        let mut c8 = C8Emulator::new().with_rng(Wide);
+        c8.load(&MOVING).unwrap();
+        assert_eq!(
+            Err(SnapshotError::RngStateTooLarge(300)),
+            c8.snapshot().to_bytes()
+        );
+
+        // there's no history to go back to, rather than a broken one
+        c8.enable_rewind(100);
+        for _ in 0..10 {
+            run_frame(&mut c8);
+        }
+        assert_eq!(0, c8.rewind_len());
+        assert_eq!(Ok(0), c8.rewind(5));
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::C8Emulator;
+
+/// A source of the random numbers of Cxnn and of the random screen of
+/// DDDD.
+///
+/// Its state is saved in snapshots, so that rewinding and movies replay
+/// the same numbers.
+pub trait Rng: Send {
+    fn next_u8(&mut self) -> u8;
+
+    /// Start the sequence over from `seed`.
+    fn reseed(&mut self, seed: u64);
+
+    /// At most 255 bytes, as saved in snapshots.
+    fn state(&self) -> Vec<u8>;
+
+    /// Go back to a state given by `state`, return false when it isn't
+    /// one of this generator.
+    fn set_state(&mut self, state: &[u8]) -> bool;
+}
+
+/// How the random numbers are seeded, on construction and every reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Seed {
+    /// The same sequence on every run.
+    Fixed(u64),
+    /// A new seed from the system every time.
+    Entropy,
+}
+
+impl Default for Seed {
+    /// The seed of this emulator before it was configurable.
+    fn default() -> Self {
+        Seed::Fixed(1)
+    }
+}
+
+/// Linear Congruential Generator, the default `Rng`.
+#[derive(Debug, Clone)]
+pub struct Lcg {
+    state: u32,
+}
+
+impl Lcg {
+    pub fn new(seed: u64) -> Self {
+        let mut lcg = Self { state: 0 };
+        lcg.reseed(seed);
+        lcg
+    }
+
+    fn rand(&mut self) -> u32 {
+        self.state = self.state.wrapping_mul(1103515245).wrapping_add(12345);
+        (self.state >> 16) & 0x7FFF
+    }
+}
+
+impl Rng for Lcg {
+    fn next_u8(&mut self) -> u8 {
+        (self.rand() & 0xFF) as u8
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        // the state is only 32 bits, fold the seed on it
+        self.state = (seed ^ seed >> 32) as u32;
+    }
+
+    fn state(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> bool {
+        match state.try_into() {
+            Ok(bytes) => {
+                self.state = u32::from_le_bytes(bytes);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl C8Emulator {
+    pub fn with_seed(mut self, seed: Seed) -> Self {
+        self.set_seed(seed);
+        self
+    }
+
+    /// Replace the random number generator, it's seeded right away.
+    pub fn with_rng(mut self, rng: impl Rng + 'static) -> Self {
+        self.rand_gen = Box::new(rng);
+        self.reseed();
+        self
+    }
+
+    pub fn seed(&self) -> Seed {
+        self.seed
+    }
+
+    /// Seed the random numbers now, and on every reset.
+    pub fn set_seed(&mut self, seed: Seed) {
+        self.seed = seed;
+        self.reseed();
+    }
+
+    pub(crate) fn reseed(&mut self) {
+        let seed = match self.seed {
+            Seed::Fixed(seed) => seed,
+            Seed::Entropy => entropy(),
+        };
+        self.rand_gen.reseed(seed);
+    }
+}
+
+/// A seed from the system: the keys of the hash maps are random for
+/// every process, mixed with the time for every call.
+fn entropy() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(nanos);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps V0 = random.
+    const ROM: [u8; 4] = [0xC0, 0xFF, 0x12, 0x00];
+
+    fn numbers(c8: &mut C8Emulator) -> Vec<u8> {
+        c8.load(&ROM).unwrap();
+        (0..8)
+            .map(|_| {
+                c8.cpu_cycle().unwrap();
+                c8.cpu_cycle().unwrap();
+                c8.v_regs[0]
+            })
+            .collect()
+    }
+
+    /// Counts up from the seed.
+    struct Counter(u8);
+
+    impl Rng for Counter {
+        fn next_u8(&mut self) -> u8 {
+            self.0 = self.0.wrapping_add(1);
+            self.0
+        }
+
+        fn reseed(&mut self, seed: u64) {
+            self.0 = seed as u8;
+        }
+
+        fn state(&self) -> Vec<u8> {
+            vec![self.0]
+        }
+
+        fn set_state(&mut self, state: &[u8]) -> bool {
+            let [counter] = state else {
+                return false;
+            };
+            self.0 = *counter;
+            true
+        }
+    }
+
+    #[test]
+    fn seeds() {
+        let default = numbers(&mut C8Emulator::new());
+        let mut c8 = C8Emulator::new().with_seed(Seed::Fixed(1));
+        assert_eq!(default, numbers(&mut c8));
+
+        // a reset starts over from the seed
+        c8.set_seed(Seed::Fixed(42));
+        let seeded = numbers(&mut c8);
+        assert_ne!(default, seeded);
+        c8.reset();
+        assert_eq!(seeded, numbers(&mut c8));
+
+        let mut c8 = C8Emulator::new().with_seed(Seed::Entropy);
+        let first = numbers(&mut c8);
+        c8.reset();
+        assert_ne!(first, numbers(&mut c8));
+    }
+
+    #[test]
+    fn pluggable() {
+        let mut c8 = C8Emulator::new()
+            .with_seed(Seed::Fixed(10))
+            .with_rng(Counter(0));
+        assert_eq!(vec![11, 12, 13, 14, 15, 16, 17, 18], numbers(&mut c8));
+
+        // snapshots keep the state of the generator
+        let snapshot = c8.snapshot();
+        let next = numbers(&mut c8);
+        c8.restore(&snapshot);
+        assert_eq!(next, numbers(&mut c8));
+    }
+}
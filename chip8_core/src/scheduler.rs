@@ -0,0 +1,382 @@
+use std::time::Duration;
+
+use crate::{
+    timing::FETCH_CYCLES, C8Emulator, C8Error, Debugger, Instruction, StopReason,
+    VIP_CYCLES_PER_FRAME, VIP_DISPLAY_CYCLES,
+};
+
+/// The timers tick at 60 Hz.
+pub const TIMER_HZ: u32 = 60;
+
+/// Time is counted in 1/60 of a nanosecond, so that both a frame and a
+/// cycle at any whole rate are a whole number of units.
+const UNITS_PER_FRAME: u128 = 1_000_000_000;
+const UNITS_PER_SECOND: u128 = UNITS_PER_FRAME * TIMER_HZ as u128;
+
+/// How the scheduler paces the instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    /// Every instruction takes the same time, at this many per second.
+    Instructions(u32),
+    /// Every instruction takes as many machine cycles as on the COSMAC
+    /// VIP, see `Instruction::vip_cycles`. With the display_wait quirk a
+    /// draw waits for the next frame and its cycles are taken from it.
+    VipCycles,
+}
+
+/// Runs the emulator in real time: given the wall-clock time elapsed, it
+/// executes the instructions due at the configured rate and ticks the
+/// timers at exactly 60 Hz, interleaving them as they would happen.
+///
+/// When the frontend falls behind by more than the catch-up limit, the
+/// time over it is dropped and the emulation slows down instead of
+/// running a long burst of frames.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    timing: Timing,
+    max_catch_up: Duration,
+    /// Time since the last instruction, times the rate of the timing.
+    cycle_credit: u128,
+    /// Time since the last timer tick.
+    frame_credit: u128,
+    /// Cycles of a draw that waited for the end of the frame, they are
+    /// taken from the next one.
+    debt: u128,
+}
+
+impl Scheduler {
+    /// A scheduler executing `ips` instructions per second, catching up
+    /// on at most 100 ms.
+    pub fn new(ips: u32) -> Self {
+        Self::with_timing(Timing::Instructions(ips))
+    }
+
+    /// A scheduler following the instruction timings of the COSMAC VIP.
+    pub fn vip() -> Self {
+        Self::with_timing(Timing::VipCycles)
+    }
+
+    pub fn with_timing(timing: Timing) -> Self {
+        Self {
+            timing,
+            max_catch_up: Duration::from_millis(100),
+            cycle_credit: 0,
+            frame_credit: 0,
+            debt: 0,
+        }
+    }
+
+    pub fn with_max_catch_up(mut self, max_catch_up: Duration) -> Self {
+        self.max_catch_up = max_catch_up;
+        self
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.cycle_credit = 0;
+        self.debt = 0;
+    }
+
+    /// Run what's due after `elapsed` of wall-clock time and return the
+    /// number of frames that ended, that is how many times the timers
+    /// ticked. On a fault the time left is dropped.
+    pub fn advance(&mut self, c8: &mut C8Emulator, elapsed: Duration) -> Result<u32, C8Error> {
+        let elapsed = elapsed.min(self.max_catch_up);
+        self.run(c8, elapsed.as_nanos() * TIMER_HZ as u128)
+    }
+
+    /// Like `advance`, with every instruction executed by the debugger.
+    /// When it stops the time left is dropped, as on a fault, and the
+    /// timers only tick at the end of the frame after resuming.
+    pub fn advance_debugger(
+        &mut self,
+        dbg: &mut Debugger,
+        elapsed: Duration,
+    ) -> Result<u32, StopReason> {
+        let elapsed = elapsed.min(self.max_catch_up);
+        self.run(dbg, elapsed.as_nanos() * TIMER_HZ as u128)
+    }
+
+    /// Run exactly one frame: the instructions of 1/60 of a second, then
+    /// a tick of the timers. Useful when the frontend paces itself, like
+    /// on vsync or without real time at all.
+    pub fn run_frame(&mut self, c8: &mut C8Emulator) -> Result<(), C8Error> {
+        self.run(c8, UNITS_PER_FRAME - self.frame_credit)?;
+        Ok(())
+    }
+
+    fn run<R: Runner>(&mut self, runner: &mut R, mut units: u128) -> Result<u32, R::Stop> {
+        let mut frames = 0;
+        while units > 0 {
+            // run up to the next timer tick at most
+            let step = units.min(UNITS_PER_FRAME - self.frame_credit);
+            units -= step;
+
+            self.cycle_credit += step * self.rate();
+            let paid = self.debt.min(self.cycle_credit);
+            self.debt -= paid;
+            self.cycle_credit -= paid;
+
+            loop {
+                let c8 = runner.emulator();
+                if c8.is_waiting_vblank() || c8.is_halted() || c8.watches.is_hit() {
+                    // nothing runs until the end of the frame
+                    self.cycle_credit = 0;
+                    break;
+                }
+                let (cost, after_vblank) = self.cost(runner.emulator());
+                if self.cycle_credit < cost * UNITS_PER_SECOND {
+                    break;
+                }
+                self.cycle_credit -= cost * UNITS_PER_SECOND;
+
+                if let Err(err) = runner.step() {
+                    self.cycle_credit = 0;
+                    return Err(err);
+                }
+                self.debt += after_vblank * UNITS_PER_SECOND;
+            }
+
+            self.frame_credit += step;
+            if self.frame_credit == UNITS_PER_FRAME {
+                self.frame_credit = 0;
+                runner.emulator_mut().frame_cycle();
+                frames += 1;
+            }
+        }
+        Ok(frames)
+    }
+
+    /// Cycles executed per second.
+    fn rate(&self) -> u128 {
+        match self.timing {
+            Timing::Instructions(ips) => ips as u128,
+            Timing::VipCycles => ((VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES) * TIMER_HZ) as u128,
+        }
+    }
+
+    /// The cycles the next instruction takes before it executes, and
+    /// the ones it takes after the end of the frame.
+    fn cost(&self, c8: &C8Emulator) -> (u128, u128) {
+        if self.timing != Timing::VipCycles {
+            return (1, 0);
+        }
+        match c8.current_instruction() {
+            Ok(instruction @ Instruction::Drw { .. }) if c8.quirks().display_wait => {
+                (0, instruction.vip_cycles(c8) as u128)
+            }
+            Ok(instruction) => (instruction.vip_cycles(c8) as u128, 0),
+            // the fault is for cpu_cycle to report, once fetched
+            Err(_) => (FETCH_CYCLES as u128, 0),
+        }
+    }
+}
+
+/// What the scheduler executes the instructions with.
+trait Runner {
+    /// Why the execution stopped.
+    type Stop;
+
+    fn emulator(&self) -> &C8Emulator;
+
+    fn emulator_mut(&mut self) -> &mut C8Emulator;
+
+    fn step(&mut self) -> Result<(), Self::Stop>;
+}
+
+impl Runner for C8Emulator {
+    type Stop = C8Error;
+
+    fn emulator(&self) -> &C8Emulator {
+        self
+    }
+
+    fn emulator_mut(&mut self) -> &mut C8Emulator {
+        self
+    }
+
+    fn step(&mut self) -> Result<(), C8Error> {
+        self.cpu_cycle()
+    }
+}
+
+impl Runner for Debugger {
+    type Stop = StopReason;
+
+    fn emulator(&self) -> &C8Emulator {
+        Debugger::emulator(self)
+    }
+
+    fn emulator_mut(&mut self) -> &mut C8Emulator {
+        Debugger::emulator_mut(self)
+    }
+
+    fn step(&mut self) -> Result<(), StopReason> {
+        self.run(1).map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts up in V0 forever.
+    const COUNTER: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    fn counter(delay: u8) -> C8Emulator {
+        let mut c8 = C8Emulator::new();
+        c8.load(&COUNTER).unwrap();
+        c8.delay_t = delay;
+        c8
+    }
+
+    #[test]
+    fn real_time_rates() {
+        let mut c8 = counter(200);
+        let mut scheduler = Scheduler::new(600);
+
+        // a second in uneven slices, like a frontend would see it
+        let mut frames = 0;
+        for ms in [7, 13, 3, 30, 47].iter().cycle().take(50) {
+            frames += scheduler
+                .advance(&mut c8, Duration::from_millis(*ms))
+                .unwrap();
+        }
+
+        assert_eq!(60, frames);
+        assert_eq!(200 - 60, c8.delay_t);
+        // 300 additions and 300 jumps
+        assert_eq!(300 % 256, c8.v_regs[0] as usize);
+    }
+
+    #[test]
+    fn frames() {
+        let mut c8 = counter(10);
+        // 11.5 instructions per frame
+        let mut scheduler = Scheduler::new(690);
+
+        scheduler.run_frame(&mut c8).unwrap();
+        assert_eq!(9, c8.delay_t);
+        assert_eq!(6, c8.v_regs[0]);
+        scheduler.run_frame(&mut c8).unwrap();
+        assert_eq!(8, c8.delay_t);
+        assert_eq!(12, c8.v_regs[0]);
+    }
+
+    #[test]
+    fn catch_up_limit() {
+        let mut c8 = counter(100);
+        let mut scheduler = Scheduler::new(600).with_max_catch_up(Duration::from_millis(50));
+
+        let frames = scheduler.advance(&mut c8, Duration::from_secs(5)).unwrap();
+        assert_eq!(3, frames);
+        assert_eq!(97, c8.delay_t);
+    }
+
+    #[test]
+    fn vip_timing() {
+        let mut c8 = counter(10);
+        let mut scheduler = Scheduler::vip();
+
+        scheduler.run_frame(&mut c8).unwrap();
+        // ADD and JP take 50 and 52 cycles
+        let available = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+        let pairs = available / 102;
+        let extra = (available % 102 >= 50) as u32;
+        assert_eq!(pairs + extra, c8.v_regs[0] as u32);
+    }
+
+    #[test]
+    fn vip_draws_wait_for_the_next_frame() {
+        let mut c8 = C8Emulator::with_quirks(crate::Quirks::cosmac_vip());
+        c8.load(&[
+            0xD0, 0x0F, // 200: DRW V0, V0, 15
+            0x70, 0x01, // 202: ADD V0, 1
+            0x12, 0x00, // 204: JP 200
+        ])
+        .unwrap();
+        let mut scheduler = Scheduler::vip();
+
+        for _ in 0..3 {
+            scheduler.run_frame(&mut c8).unwrap();
+        }
+        // the first frame ends on the first draw, then there's a draw
+        // and an addition per frame, the draw cycles taken after the wait
+        assert_eq!(2, c8.v_regs[0]);
+        assert_eq!(0x202, c8.pc);
+    }
+
+    #[test]
+    fn fault() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&[0x00, 0xEE]).unwrap();
+        let mut scheduler = Scheduler::new(600);
+
+        assert_eq!(Err(C8Error::StackUnderflow), scheduler.run_frame(&mut c8));
+    }
+
+    #[test]
+    fn vip_fault() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&[0xFF, 0xFF]).unwrap();
+        let mut scheduler = Scheduler::vip();
+
+        assert!(scheduler.run_frame(&mut c8).is_err());
+        assert_eq!(0x200, c8.pc);
+    }
+
+    #[test]
+    fn stopped_emulator_idles() {
+        // halted before data that doesn't decode, or before a draw
+        for rom in [[0x00, 0xFD, 0xFF, 0xFF], [0x00, 0xFD, 0xD0, 0x05]] {
+            let mut c8 = C8Emulator::with_quirks(crate::Quirks::cosmac_vip());
+            c8.load(&rom).unwrap();
+            let mut scheduler = Scheduler::vip();
+            for _ in 0..2 {
+                scheduler.run_frame(&mut c8).unwrap();
+            }
+            assert!(c8.is_halted());
+            assert_eq!(0x202, c8.pc);
+        }
+
+        // a watchpoint hit waits to be taken
+        let mut c8 = C8Emulator::new();
+        c8.load(&[
+            0xA3, 0x00, // 200: LD I, 300
+            0xF0, 0x55, // 202: LD [I], V0
+            0x12, 0x02, // 204: JP 202
+        ])
+        .unwrap();
+        c8.add_watchpoint(0x300..=0x300, crate::WatchKind::Write);
+        let mut scheduler = Scheduler::vip();
+        for _ in 0..2 {
+            scheduler.run_frame(&mut c8).unwrap();
+        }
+        assert_eq!(0x202, c8.take_watch_hit().unwrap().pc);
+        assert_eq!(0x204, c8.pc);
+    }
+
+    #[test]
+    fn debugger_stops_mid_frame() {
+        let mut dbg = Debugger::new(counter(10));
+        dbg.add_breakpoint(0x202);
+        dbg.resume();
+        let mut scheduler = Scheduler::new(600);
+
+        let frame = Duration::from_micros(16_667);
+        let stop = scheduler.advance_debugger(&mut dbg, frame);
+        assert_eq!(Err(StopReason::Breakpoint(0x202)), stop);
+        assert_eq!(10, dbg.emulator().delay_t);
+
+        // the frame ends after resuming
+        dbg.remove_breakpoint(0x202);
+        dbg.resume();
+        assert_eq!(Ok(1), scheduler.advance_debugger(&mut dbg, frame));
+        assert_eq!(9, dbg.emulator().delay_t);
+        assert_eq!(6, dbg.emulator().v_regs[0]);
+    }
+}
@@ -0,0 +1,81 @@
+/// Characters of the palette indexes in `Screen::to_text`.
+const TEXT_COLORS: [char; 4] = ['.', '#', '+', '@'];
+
+/// A view on the display of the emulator at its current resolution.
+///
+/// Pixels are stored row by row, `width` pixels per row. Each pixel is
+/// a palette index in `0..4`: bit 0 is the first plane and bit 1 the
+/// second XO-CHIP plane, so plain CHIP-8 programs only use 0 and 1.
+#[derive(Debug, Clone, Copy)]
+pub struct Screen<'a> {
+    width: usize,
+    height: usize,
+    pixels: &'a [u8],
+}
+
+impl<'a> Screen<'a> {
+    pub(crate) fn new(width: usize, height: usize, pixels: &'a [u8]) -> Self {
+        Self {
+            width,
+            height,
+            pixels: &pixels[..width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The palette indexes of every pixel.
+    pub fn pixels(&self) -> &'a [u8] {
+        self.pixels
+    }
+
+    /// Whether the pixel at column `x` and row `y` is lit on any plane.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.color(x, y) != 0
+    }
+
+    /// The palette index of the pixel at column `x` and row `y`.
+    pub fn color(&self, x: usize, y: usize) -> u8 {
+        self.pixels[x + self.width * y]
+    }
+
+    /// Whether the pixel at column `x` and row `y` is lit on `plane` (0 or 1).
+    pub fn plane_pixel(&self, plane: usize, x: usize, y: usize) -> bool {
+        self.color(x, y) & (1 << plane) != 0
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> {
+        self.pixels.chunks(self.width)
+    }
+
+    /// A line of characters per row: `.` for unlit pixels, then `#`, `+`
+    /// and `@` for the palette indexes 1 to 3.
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((self.width + 1) * self.height);
+        for row in self.rows() {
+            text.extend(row.iter().map(|color| TEXT_COLORS[*color as usize & 3]));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// A plain PBM image, lit pixels are black.
+    pub fn to_pbm(&self) -> String {
+        let mut pbm = format!("P1\n{} {}\n", self.width, self.height);
+        for row in self.rows() {
+            let line: Vec<&str> = row
+                .iter()
+                .map(|color| if *color != 0 { "1" } else { "0" })
+                .collect();
+            pbm.push_str(&line.join(" "));
+            pbm.push('\n');
+        }
+        pbm
+    }
+}
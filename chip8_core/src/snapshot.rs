@@ -0,0 +1,468 @@
+use std::fmt;
+
+use crate::{
+    C8Emulator, LoadStore, Quirks, AUDIO_PATTERN_SIZE, KEYS_SIZE, RAM_SIZE, RPL_FLAGS_NUM,
+    SCREEN_SIZE, STACK_SIZE, V_REGS_NUM, XO_RAM_SIZE,
+};
+
+/// Every serialized snapshot starts with these bytes.
+const MAGIC: &[u8; 4] = b"C8SN";
+/// Bumped every time the layout of the serialized snapshot changes.
+const VERSION: u16 = 2;
+/// Before the random number generators were pluggable, the state was
+/// always the 32 bits of `Lcg`.
+const LCG_VERSION: u16 = 1;
+
+/// The whole state of a `C8Emulator`, taken by `C8Emulator::snapshot`
+/// and given back to `C8Emulator::restore`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub(crate) pc: u16,
+    pub(crate) ram: Vec<u8>,
+    pub(crate) stack: [u16; STACK_SIZE],
+    pub(crate) sp: usize,
+    pub(crate) v_regs: [u8; V_REGS_NUM],
+    pub(crate) i_reg: u16,
+    pub(crate) delay_t: u8,
+    pub(crate) sound_t: u8,
+    pub(crate) screen: Vec<u8>,
+    pub(crate) hires: bool,
+    pub(crate) planes: u8,
+    pub(crate) keys: [bool; KEYS_SIZE],
+    pub(crate) rng_state: Vec<u8>,
+    pub(crate) quirks: Quirks,
+    pub(crate) waiting_vblank: bool,
+    pub(crate) halted: bool,
+    pub(crate) rpl: [u8; RPL_FLAGS_NUM],
+    pub(crate) xo_chip: bool,
+    pub(crate) audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pub(crate) pitch: u8,
+}
+
+/// Reasons why some bytes can't be read back as a `Snapshot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The bytes don't start with the snapshot magic header.
+    BadMagic,
+    /// The snapshot was written by an incompatible version.
+    UnsupportedVersion(u16),
+    /// The bytes end before the whole snapshot is read.
+    Truncated,
+    /// A field holds a value that no emulator state can have.
+    Corrupted(&'static str),
+    /// The state of the random number generator is longer than the 255
+    /// bytes a snapshot can hold.
+    RngStateTooLarge(usize),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {version}")
+            }
+            SnapshotError::Truncated => write!(f, "truncated snapshot"),
+            SnapshotError::Corrupted(field) => write!(f, "corrupted snapshot: bad {field}"),
+            SnapshotError::RngStateTooLarge(len) => {
+                write!(f, "random number generator state too large: {len} bytes")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl C8Emulator {
+    /// Capture the whole state of the emulator.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            ram: self.ram.clone(),
+            stack: self.stack.arr,
+            sp: self.stack.sp,
+            v_regs: self.v_regs,
+            i_reg: self.i_reg,
+            delay_t: self.delay_t,
+            sound_t: self.sound_t,
+            screen: self.screen.to_vec(),
+            hires: self.hires,
+            planes: self.planes,
+            keys: self.keys,
+            rng_state: self.rand_gen.state(),
+            quirks: self.quirks,
+            waiting_vblank: self.waiting_vblank,
+            halted: self.halted,
+            rpl: self.rpl,
+            xo_chip: self.xo_chip,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+        }
+    }
+
+    /// Bring the emulator back to the state captured by `snapshot`.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.pc = snapshot.pc;
+        self.ram.clone_from(&snapshot.ram);
+        self.stack.arr = snapshot.stack;
+        self.stack.sp = snapshot.sp;
+        self.v_regs = snapshot.v_regs;
+        self.i_reg = snapshot.i_reg;
+        self.delay_t = snapshot.delay_t;
+        self.sound_t = snapshot.sound_t;
+        self.screen.copy_from_slice(&snapshot.screen);
+        self.hires = snapshot.hires;
+        self.planes = snapshot.planes;
+        self.keys = snapshot.keys;
+        // the state of another kind of generator is left out
+        self.rand_gen.set_state(&snapshot.rng_state);
+        self.quirks = snapshot.quirks;
+        self.waiting_vblank = snapshot.waiting_vblank;
+        self.halted = snapshot.halted;
+        self.rpl = snapshot.rpl;
+        self.xo_chip = snapshot.xo_chip;
+        self.audio_pattern = snapshot.audio_pattern;
+        self.pitch = snapshot.pitch;
+    }
+
+    /// A hash of the whole state, see `Snapshot::hash`.
+    pub fn state_hash(&self) -> u64 {
+        self.snapshot().hash()
+    }
+}
+
+impl Snapshot {
+    /// Serialize the snapshot, multi-byte values are little endian.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        let len = self.rng_state.len();
+        let rng_len = u8::try_from(len).map_err(|_| SnapshotError::RngStateTooLarge(len))?;
+        Ok(self.write(rng_len))
+    }
+
+    fn write(&self, rng_len: u8) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.ram.len() + self.screen.len() + 128);
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+        let flags = [self.hires, self.waiting_vblank, self.halted, self.xo_chip];
+        bytes.push(pack_bits(&flags));
+        bytes.push(pack_quirks(self.quirks));
+
+        bytes.extend_from_slice(&self.pc.to_le_bytes());
+        bytes.extend_from_slice(&self.i_reg.to_le_bytes());
+        bytes.push(self.sp as u8);
+        for addr in self.stack {
+            bytes.extend_from_slice(&addr.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.v_regs);
+        bytes.push(self.delay_t);
+        bytes.push(self.sound_t);
+        bytes.push(self.planes);
+        bytes.push(self.pitch);
+        bytes.push(rng_len);
+        bytes.extend_from_slice(&self.rng_state);
+
+        let keys = self
+            .keys
+            .iter()
+            .enumerate()
+            .fold(0u16, |mask, (idx, pressed)| mask | (*pressed as u16) << idx);
+        bytes.extend_from_slice(&keys.to_le_bytes());
+
+        bytes.extend_from_slice(&self.rpl);
+        bytes.extend_from_slice(&self.audio_pattern);
+        bytes.extend_from_slice(&(self.ram.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.ram);
+        bytes.extend_from_slice(&self.screen);
+
+        bytes
+    }
+
+    /// A 64-bit FNV-1a hash of the serialized snapshot, stable across
+    /// runs and platforms, to compare states without keeping them.
+    pub fn hash(&self) -> u64 {
+        // states too large to save are hashed whole all the same
+        fnv1a(&self.write(self.rng_state.len() as u8))
+    }
+
+    /// Read back a snapshot serialized by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != VERSION && version != LCG_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let [hires, waiting_vblank, halted, xo_chip, ..] = unpack_bits(reader.u8()?);
+        let quirks = unpack_quirks(reader.u8()?);
+
+        let pc = reader.u16()?;
+        let i_reg = reader.u16()?;
+        let sp = reader.u8()? as usize;
+        if sp > STACK_SIZE {
+            return Err(SnapshotError::Corrupted("stack pointer"));
+        }
+        let mut stack = [0; STACK_SIZE];
+        for addr in stack.iter_mut() {
+            *addr = reader.u16()?;
+        }
+        let v_regs = reader.array()?;
+        let delay_t = reader.u8()?;
+        let sound_t = reader.u8()?;
+        let planes = reader.u8()?;
+        if planes > 0b11 {
+            return Err(SnapshotError::Corrupted("planes"));
+        }
+        let pitch = reader.u8()?;
+        let rng_len = match version {
+            LCG_VERSION => 4,
+            _ => reader.u8()? as usize,
+        };
+        let rng_state = reader.take(rng_len)?.to_vec();
+
+        let key_mask = reader.u16()?;
+        let mut keys = [false; KEYS_SIZE];
+        for (idx, key) in keys.iter_mut().enumerate() {
+            *key = key_mask & (1 << idx) != 0;
+        }
+
+        let rpl = reader.array()?;
+        let audio_pattern = reader.array()?;
+
+        let ram_len = reader.u32()? as usize;
+        let expected_len = if xo_chip { XO_RAM_SIZE } else { RAM_SIZE };
+        if ram_len != expected_len {
+            return Err(SnapshotError::Corrupted("memory size"));
+        }
+        let ram = reader.take(ram_len)?.to_vec();
+        let screen = reader.take(SCREEN_SIZE)?.to_vec();
+        if screen.iter().any(|px| *px > 0b11) {
+            return Err(SnapshotError::Corrupted("screen"));
+        }
+
+        Ok(Snapshot {
+            pc,
+            ram,
+            stack,
+            sp,
+            v_regs,
+            i_reg,
+            delay_t,
+            sound_t,
+            screen,
+            hires,
+            planes,
+            keys,
+            rng_state,
+            quirks,
+            waiting_vblank,
+            halted,
+            rpl,
+            xo_chip,
+            audio_pattern,
+            pitch,
+        })
+    }
+}
+
+/// The 64-bit FNV-1a hash of the bytes.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+pub(crate) fn pack_quirks(quirks: Quirks) -> u8 {
+    // the CHIP-48 increment came after the others, in a bit of its own
+    pack_bits(&[
+        quirks.shift,
+        quirks.load_store == LoadStore::AddXPlusOne,
+        quirks.jump_vx,
+        quirks.vf_reset,
+        quirks.clipping,
+        quirks.display_wait,
+        quirks.load_store == LoadStore::AddX,
+    ])
+}
+
+pub(crate) fn unpack_quirks(byte: u8) -> Quirks {
+    let [shift, add_x_plus_one, jump_vx, vf_reset, clipping, display_wait, add_x, _] =
+        unpack_bits(byte);
+    let load_store = match (add_x_plus_one, add_x) {
+        (true, _) => LoadStore::AddXPlusOne,
+        (false, true) => LoadStore::AddX,
+        (false, false) => LoadStore::Unchanged,
+    };
+    Quirks {
+        shift,
+        load_store,
+        jump_vx,
+        vf_reset,
+        clipping,
+        display_wait,
+    }
+}
+
+fn pack_bits(bits: &[bool]) -> u8 {
+    bits.iter()
+        .enumerate()
+        .fold(0, |byte, (idx, bit)| byte | (*bit as u8) << idx)
+}
+
+fn unpack_bits(byte: u8) -> [bool; 8] {
+    let mut bits = [false; 8];
+    for (idx, bit) in bits.iter_mut().enumerate() {
+        *bit = byte & (1 << idx) != 0;
+    }
+    bits
+}
+
+/// Cursor over the serialized bytes.
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draws the font digits in a loop, so every cycle changes the state.
+    const COUNTER: [u8; 10] = [
+        0x00, 0xE0, // 00E0 - clear
+        0xF0, 0x29, // F029 - I = font of V0
+        0xD1, 0x15, // D115 - draw
+        0x70, 0x01, // 7001 - V0 += 1
+        0x12, 0x00, // 1200 - loop
+    ];
+
+    #[test]
+    fn restore_resumes_execution() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&COUNTER).unwrap();
+        for _ in 0..17 {
+            c8.cpu_cycle().unwrap();
+        }
+
+        let snapshot = c8.snapshot();
+        for _ in 0..23 {
+            c8.cpu_cycle().unwrap();
+        }
+        let expected = c8.snapshot();
+
+        c8.restore(&snapshot);
+        assert_eq!(snapshot, c8.snapshot());
+        for _ in 0..23 {
+            c8.cpu_cycle().unwrap();
+        }
+        assert_eq!(expected, c8.snapshot());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut c8 = C8Emulator::with_quirks(Quirks::cosmac_vip());
+        c8.set_xo_chip(true);
+        c8.load(&COUNTER).unwrap();
+        c8.press_key(0xA, true).unwrap();
+        for _ in 0..9 {
+            c8.cpu_cycle().unwrap();
+        }
+
+        let snapshot = c8.snapshot();
+        let bytes = snapshot.to_bytes().unwrap();
+        assert_eq!(MAGIC, &bytes[..4]);
+        assert_eq!(Ok(snapshot), Snapshot::from_bytes(&bytes));
+    }
+
+    #[test]
+    fn quirks_round_trip() {
+        for name in ["vip", "chip48", "schip", "xochip"] {
+            let quirks = Quirks::preset(name).unwrap();
+            assert_eq!(quirks, unpack_quirks(pack_quirks(quirks)), "{name}");
+        }
+    }
+
+    #[test]
+    fn state_hash() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&COUNTER).unwrap();
+        let hash = c8.state_hash();
+        assert_eq!(hash, c8.state_hash());
+
+        let snapshot = c8.snapshot();
+        c8.cpu_cycle().unwrap();
+        assert_ne!(hash, c8.state_hash());
+        c8.restore(&snapshot);
+        assert_eq!(hash, c8.state_hash());
+    }
+
+    #[test]
+    fn reads_lcg_version() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&COUNTER).unwrap();
+        let snapshot = c8.snapshot();
+
+        // version 1 had the 4 bytes of the LCG state without a length
+        let mut bytes = snapshot.to_bytes().unwrap();
+        bytes[4..6].copy_from_slice(&LCG_VERSION.to_le_bytes());
+        let rng_at =
+            bytes.len() - RAM_SIZE - SCREEN_SIZE - 4 - AUDIO_PATTERN_SIZE - RPL_FLAGS_NUM - 2 - 5;
+        assert_eq!(4, bytes.remove(rng_at));
+        assert_eq!(Ok(snapshot), Snapshot::from_bytes(&bytes));
+    }
+
+    #[test]
+    fn invalid_bytes() {
+        let bytes = C8Emulator::new().snapshot().to_bytes().unwrap();
+
+        assert_eq!(Err(SnapshotError::BadMagic), Snapshot::from_bytes(b"NOPE"));
+        assert_eq!(
+            Err(SnapshotError::Truncated),
+            Snapshot::from_bytes(&bytes[..bytes.len() - 1])
+        );
+
+        let mut newer = bytes.clone();
+        newer[4] = 0xFF;
+        assert_eq!(
+            Err(SnapshotError::UnsupportedVersion(0x00FF)),
+            Snapshot::from_bytes(&newer)
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{C8Emulator, Instruction};
+
+/// Machine cycles of the COSMAC VIP in a 60 Hz frame: its 1802 runs at
+/// 1.76064 MHz and a machine cycle takes 8 clock cycles.
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+/// Cycles of every frame taken by the CDP1861 display, 128 rows of 8
+/// bytes fetched by DMA, and by its interrupt routine that also ticks
+/// the timers.
+pub const VIP_DISPLAY_CYCLES: u32 = 1024 + 46;
+
+/// Cycles the interpreter spends fetching and decoding any instruction.
+pub(crate) const FETCH_CYCLES: u32 = 40;
+
+impl Instruction {
+    /// How many machine cycles the CHIP-8 interpreter of the COSMAC VIP
+    /// takes to execute this instruction in the state of `c8`, fetching
+    /// included. Costs follow the interpreter routines: whether a skip is
+    /// taken, how many registers or sprite rows are moved, and if the
+    /// sprite is aligned on a byte all change the count.
+    ///
+    /// On the VIP a draw first waits for the display interrupt, that's
+    /// not counted here: the scheduler idles until the next frame and
+    /// charges the draw to it.
+    ///
+    /// SUPER-CHIP and XO-CHIP instructions didn't exist on the VIP, they
+    /// get the cost of a similar instruction.
+    pub fn vip_cycles(&self, c8: &C8Emulator) -> u32 {
+        use Instruction::*;
+
+        let vx = |x: u8| c8.v_regs[x as usize];
+        let skip = |taken: bool| if taken { 14 } else { 10 };
+        let key = |x: u8| c8.keys.get(vx(x) as usize).copied().unwrap_or(false);
+
+        let cycles = match *self {
+            // 0nnn calls machine code, a 0000 returns right away
+            Nop => 12,
+            Cls => 24 + 6 * 256,
+            Ret => 10,
+            Jp(_) => 12,
+            Call(_) => 26,
+            SeByte { x, nn } => skip(vx(x) == nn),
+            SneByte { x, nn } => skip(vx(x) != nn),
+            SeReg { x, y } => 4 + skip(vx(x) == vx(y)),
+            SneReg { x, y } => 4 + skip(vx(x) != vx(y)),
+            Skp(x) => 4 + skip(key(x)),
+            Sknp(x) => 4 + skip(!key(x)),
+            LdByte { .. } => 6,
+            AddByte { .. } => 10,
+            // the ALU instructions are assembled in memory and called
+            LdReg { .. } | Or { .. } | And { .. } | Xor { .. } => 20,
+            AddReg { .. } | Sub { .. } | Shr { .. } | Subn { .. } | Shl { .. } => 24,
+            LdI(_) => 12,
+            JpV0(_) => 22,
+            Rnd { .. } => 36,
+            Drw { x, n, .. } => {
+                let rows = if n == 0 { 32 } else { n as u32 };
+                let per_row = if vx(x) % 8 == 0 { 32 } else { 48 };
+                26 + rows * per_row
+            }
+            LdVxDt(_) | LdDt(_) | LdSt(_) => 10,
+            // a poll of the keyboard, repeated while waiting
+            LdKey(_) => 14,
+            AddI(_) => 16,
+            LdF(_) | LdHf(_) => 16,
+            Bcd(x) => {
+                // each decimal digit is counted by repeated subtraction
+                let val = vx(x) as u32;
+                84 + 16 * (val / 100 + val / 10 % 10 + val % 10)
+            }
+            Store(x) | Load(x) | SaveFlags(x) | LoadFlags(x) => 14 + 14 * (x as u32 + 1),
+            SaveRange { x, y } | LoadRange { x, y } => 14 + 14 * (x.abs_diff(y) as u32 + 1),
+            Scd(_) | Scr | Scl => 24 + 6 * 256,
+            Low | High | Exit | Plane(_) | Pitch(_) => 10,
+            RandomScreen => 24 + 36 * 256,
+            LdILong => 24,
+            Audio => 14 + 14 * 16,
+        };
+        FETCH_CYCLES + cycles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn costs_depend_on_the_state() {
+        let mut c8 = C8Emulator::new();
+        c8.v_regs[1] = 8;
+        c8.v_regs[2] = 3;
+
+        let skip = Instruction::SeByte { x: 1, nn: 8 };
+        let no_skip = Instruction::SeByte { x: 2, nn: 8 };
+        assert!(skip.vip_cycles(&c8) > no_skip.vip_cycles(&c8));
+
+        let aligned = Instruction::Drw { x: 1, y: 1, n: 5 };
+        let unaligned = Instruction::Drw { x: 2, y: 1, n: 5 };
+        assert_eq!(40 + 26 + 5 * 32, aligned.vip_cycles(&c8));
+        assert_eq!(40 + 26 + 5 * 48, unaligned.vip_cycles(&c8));
+
+        assert_eq!(
+            Instruction::Store(0).vip_cycles(&c8) + 14 * 3,
+            Instruction::Store(3).vip_cycles(&c8)
+        );
+    }
+}
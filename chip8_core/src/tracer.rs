@@ -0,0 +1,329 @@
+use std::{
+    io::{self, Write},
+    ops::RangeInclusive,
+};
+
+use crate::{C8Emulator, Instruction, V_REGS_NUM};
+
+/// Size of a record in the binary format.
+pub const TRACE_RECORD_SIZE: usize = 8 + 2 + 2 + 2 * REGISTERS_SIZE;
+const REGISTERS_SIZE: usize = V_REGS_NUM + 2 + 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// A line per instruction, with the mnemonic and the registers
+    /// before and after it in hexadecimal.
+    Text,
+    /// `TRACE_RECORD_SIZE` bytes per instruction, little-endian: the
+    /// cycle (u64), PC (u16), opcode (u16), then the registers before
+    /// and after as V0-VF, I (u16), SP, DT and ST.
+    Binary,
+}
+
+/// Groups of instructions to filter the trace by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpClass {
+    /// Jumps, calls, returns and exit.
+    Flow,
+    /// Conditional skips, key checks included.
+    Skip,
+    /// Loads and arithmetic on the V registers and I.
+    Alu,
+    /// Reads and writes of memory at I.
+    Memory,
+    /// Drawing, scrolling and the display modes.
+    Display,
+    /// Timers, sound and waiting for a key.
+    Timer,
+}
+
+impl OpClass {
+    pub fn of(instruction: Instruction) -> OpClass {
+        use Instruction::*;
+
+        match instruction {
+            Nop | Ret | Exit | Jp(_) | Call(_) | JpV0(_) => OpClass::Flow,
+            SeByte { .. } | SneByte { .. } | SeReg { .. } | SneReg { .. } | Skp(_) | Sknp(_) => {
+                OpClass::Skip
+            }
+            LdByte { .. }
+            | AddByte { .. }
+            | LdReg { .. }
+            | Or { .. }
+            | And { .. }
+            | Xor { .. }
+            | AddReg { .. }
+            | Sub { .. }
+            | Shr { .. }
+            | Subn { .. }
+            | Shl { .. }
+            | LdI(_)
+            | LdILong
+            | Rnd { .. }
+            | AddI(_)
+            | LdF(_)
+            | LdHf(_) => OpClass::Alu,
+            SaveRange { .. }
+            | LoadRange { .. }
+            | Bcd(_)
+            | Store(_)
+            | Load(_)
+            | SaveFlags(_)
+            | LoadFlags(_) => OpClass::Memory,
+            Scd(_) | Cls | Scr | Scl | Low | High | RandomScreen | Drw { .. } | Plane(_) => {
+                OpClass::Display
+            }
+            Audio | LdVxDt(_) | LdKey(_) | LdDt(_) | LdSt(_) | Pitch(_) => OpClass::Timer,
+        }
+    }
+}
+
+/// The registers recorded around every traced instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Registers {
+    v_regs: [u8; V_REGS_NUM],
+    i_reg: u16,
+    sp: u8,
+    delay_t: u8,
+    sound_t: u8,
+}
+
+impl Registers {
+    pub(crate) fn of(c8: &C8Emulator) -> Self {
+        Self {
+            v_regs: c8.v_regs,
+            i_reg: c8.i_reg,
+            sp: c8.stack.sp as u8,
+            delay_t: c8.delay_t,
+            sound_t: c8.sound_t,
+        }
+    }
+
+    fn write_text(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "V=")?;
+        for val in self.v_regs {
+            write!(out, "{val:02X}")?;
+        }
+        write!(
+            out,
+            " I={:04X} SP={:X} DT={:02X} ST={:02X}",
+            self.i_reg, self.sp, self.delay_t, self.sound_t
+        )
+    }
+
+    fn to_bytes(self) -> [u8; REGISTERS_SIZE] {
+        let mut bytes = [0; REGISTERS_SIZE];
+        bytes[..V_REGS_NUM].copy_from_slice(&self.v_regs);
+        bytes[V_REGS_NUM..V_REGS_NUM + 2].copy_from_slice(&self.i_reg.to_le_bytes());
+        bytes[V_REGS_NUM + 2] = self.sp;
+        bytes[V_REGS_NUM + 3] = self.delay_t;
+        bytes[V_REGS_NUM + 4] = self.sound_t;
+        bytes
+    }
+}
+
+/// Writes a record for every instruction the emulator executes, to diff
+/// executions against other emulators.
+///
+/// Cycles are counted from when the tracer is set, filtered out
+/// instructions are counted as well so the numbers match between
+/// differently filtered traces.
+pub struct Tracer {
+    sink: Box<dyn Write + Send>,
+    format: TraceFormat,
+    range: Option<RangeInclusive<u16>>,
+    classes: Option<Vec<OpClass>>,
+    cycles: u64,
+    /// The first write error, nothing is written after it.
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(sink: impl Write + Send + 'static, format: TraceFormat) -> Self {
+        Self {
+            sink: Box::new(sink),
+            format,
+            range: None,
+            classes: None,
+            cycles: 0,
+            error: None,
+        }
+    }
+
+    /// Only trace the instructions at these addresses.
+    pub fn with_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// Only trace the instructions of these classes.
+    pub fn with_classes(mut self, classes: &[OpClass]) -> Self {
+        self.classes = Some(classes.to_vec());
+        self
+    }
+
+    /// The instructions executed since the tracer was set.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Flush the sink and return it, or the first write error.
+    pub fn finish(mut self) -> io::Result<Box<dyn Write + Send>> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.sink.flush()?;
+        Ok(self.sink)
+    }
+
+    /// Count the instruction about to execute and return whether it's
+    /// traced.
+    pub(crate) fn next_cycle(&mut self, addr: u16, instruction: Instruction) -> bool {
+        self.cycles += 1;
+        self.error.is_none()
+            && self
+                .range
+                .as_ref()
+                .is_none_or(|range| range.contains(&addr))
+            && self
+                .classes
+                .as_ref()
+                .is_none_or(|classes| classes.contains(&OpClass::of(instruction)))
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        addr: u16,
+        op_code: u16,
+        instruction: Instruction,
+        before: &Registers,
+        after: &Registers,
+    ) {
+        let cycle = self.cycles - 1;
+        let result = match self.format {
+            TraceFormat::Text => {
+                let out = &mut self.sink;
+                write!(
+                    out,
+                    "{cycle:>8} {addr:04X} {op_code:04X} {instruction:<20} "
+                )
+                .and_then(|_| before.write_text(out))
+                .and_then(|_| write!(out, " -> "))
+                .and_then(|_| after.write_text(out))
+                .and_then(|_| writeln!(out))
+            }
+            TraceFormat::Binary => {
+                let mut bytes = Vec::with_capacity(TRACE_RECORD_SIZE);
+                bytes.extend_from_slice(&cycle.to_le_bytes());
+                bytes.extend_from_slice(&addr.to_le_bytes());
+                bytes.extend_from_slice(&op_code.to_le_bytes());
+                bytes.extend_from_slice(&before.to_bytes());
+                bytes.extend_from_slice(&after.to_bytes());
+                self.sink.write_all(&bytes)
+            }
+        };
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+}
+
+impl C8Emulator {
+    /// Trace every instruction executed from now on, replacing the
+    /// current tracer. Without a tracer the execution doesn't pay for it.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    /// Stop tracing and give the tracer back, to `finish` it.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take().map(|tracer| *tracer)
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.tracer.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// A sink the test can still read after the tracer took it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const PROGRAM: [u8; 8] = [
+        0x60, 0x05, // 200: LD V0, 0x05
+        0xA3, 0x00, // 202: LD I, 0x300
+        0xF0, 0x15, // 204: LD DT, V0
+        0x12, 0x06, // 206: JP 206
+    ];
+
+    fn run(tracer: Tracer, cycles: usize) {
+        let mut c8 = C8Emulator::new();
+        c8.load(&PROGRAM).unwrap();
+        c8.set_tracer(tracer);
+        for _ in 0..cycles {
+            c8.cpu_cycle().unwrap();
+        }
+        c8.take_tracer().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn text_trace() {
+        let out = Shared::default();
+        run(Tracer::new(out.clone(), TraceFormat::Text), 4);
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(4, lines.len());
+        assert!(lines[0].starts_with("       0 0200 6005 LD V0, 0x05          V=00"));
+        assert!(lines[0].ends_with("-> V=05000000000000000000000000000000 I=0000 SP=0 DT=00 ST=00"));
+        assert!(lines[2].ends_with("I=0300 SP=0 DT=05 ST=00"));
+        assert!(lines[3].starts_with("       3 0206 1206"));
+    }
+
+    #[test]
+    fn binary_trace() {
+        let out = Shared::default();
+        run(Tracer::new(out.clone(), TraceFormat::Binary), 3);
+
+        let bytes = out.0.lock().unwrap().clone();
+        assert_eq!(3 * TRACE_RECORD_SIZE, bytes.len());
+        let record = &bytes[TRACE_RECORD_SIZE..2 * TRACE_RECORD_SIZE];
+        assert_eq!(1, u64::from_le_bytes(record[..8].try_into().unwrap()));
+        assert_eq!([0x02, 0x02, 0x00, 0xA3], record[8..12]);
+        // I after the instruction
+        let after = &record[12 + REGISTERS_SIZE..];
+        assert_eq!([0x00, 0x03], after[V_REGS_NUM..V_REGS_NUM + 2]);
+    }
+
+    #[test]
+    fn filters() {
+        let out = Shared::default();
+        let tracer = Tracer::new(out.clone(), TraceFormat::Text)
+            .with_range(0x202..=0x206)
+            .with_classes(&[OpClass::Alu, OpClass::Flow]);
+        run(tracer, 5);
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let cycles: Vec<&str> = text
+            .lines()
+            .map(|line| line.split_whitespace().next().unwrap())
+            .collect();
+        assert_eq!(vec!["1", "3", "4"], cycles);
+    }
+}
@@ -0,0 +1,342 @@
+use crate::{
+    cdp1802::{Bus, Cdp1802},
+    C8Error, Machine, Screen, START_ADDR,
+};
+
+/// The VIP with its 4 KiB of RAM, mirrored up to 0x7FFF.
+const RAM_SIZE: usize = 0x1000;
+/// The monitor ROM at 0x8000, mirrored up to 0xFFFF.
+const ROM_SIZE: usize = 0x200;
+/// The original interpreter keeps its stack and variables from 0xEA0
+/// and the display buffer from 0xF00.
+const PROGRAM_END: usize = 0xEA0;
+
+/// The 1861 draws 262 lines of 14 machine cycles each frame.
+const CYCLES_PER_LINE: u32 = 14;
+const LINES_PER_FRAME: u32 = 262;
+/// The interrupt comes two lines before the first displayed one, so the
+/// interrupt routine can point R0 at the display buffer.
+const INTERRUPT_LINE: u32 = 78;
+const FIRST_LINE: u32 = 80;
+/// Every line is 8 bytes fetched by DMA, 64 pixels.
+const DISPLAY_LINES: usize = 128;
+const LINE_BYTES: usize = 8;
+
+const SCREEN_WIDTH: usize = 64;
+const SCREEN_HEIGHT: usize = 32;
+
+/// The memory and the devices of the VIP, as seen by the CPU.
+struct Hardware {
+    ram: Vec<u8>,
+    rom: Vec<u8>,
+    /// After a reset the ROM shows at 0x0000 as well, until the CPU
+    /// addresses it at 0x8000.
+    rom_at_zero: bool,
+    /// Turned on by INP 1 and off by OUT 1.
+    display: bool,
+    /// The key selected by OUT 2, EF3 tells if it's pressed.
+    key_latch: u8,
+    keys: [bool; 16],
+    /// EF1, asserted by the 1861 around the displayed lines.
+    display_flag: bool,
+}
+
+impl Bus for Hardware {
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr & 0x8000 != 0 {
+            self.rom_at_zero = false;
+        }
+        if addr & 0x8000 != 0 || self.rom_at_zero {
+            self.rom[addr as usize % ROM_SIZE]
+        } else {
+            self.ram[addr as usize % RAM_SIZE]
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        if addr & 0x8000 == 0 {
+            self.ram[addr as usize % RAM_SIZE] = val;
+        }
+    }
+
+    fn output(&mut self, port: u8, val: u8) {
+        match port {
+            1 => self.display = false,
+            2 => self.key_latch = val & 0xF,
+            _ => (),
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display = true;
+        }
+        // nothing drives the bus
+        0xFF
+    }
+
+    fn flag(&self, n: u8) -> bool {
+        match n {
+            1 => self.display_flag,
+            3 => self.keys[self.key_latch as usize],
+            _ => false,
+        }
+    }
+}
+
+/// A COSMAC VIP running the original CHIP-8 interpreter on its CDP1802,
+/// with the CDP1861 video timing, for the programs that depend on the
+/// exact behavior and speed of the real hardware.
+///
+/// The monitor ROM and the interpreter images are supplied by the user:
+/// the interpreter relies on the interrupt routine of the monitor to
+/// refresh the display and count down the timers.
+pub struct CosmacVip {
+    cpu: Cdp1802,
+    hw: Hardware,
+    interpreter: Vec<u8>,
+    /// Machine cycles into the current frame.
+    cycle: u32,
+    /// The next display line to fetch in this frame.
+    next_line: usize,
+    interrupted: bool,
+    lines: [[u8; LINE_BYTES]; DISPLAY_LINES],
+    screen: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+}
+
+impl CosmacVip {
+    /// Machine cycles in a frame, at 1.76064 MHz and 60 frames per second.
+    pub const CYCLES_PER_FRAME: u32 = CYCLES_PER_LINE * LINES_PER_FRAME;
+
+    /// A VIP with `monitor` in ROM and `interpreter` loaded at 0x0000,
+    /// both at most 512 bytes long.
+    pub fn new(monitor: &[u8], interpreter: &[u8]) -> Result<Self, C8Error> {
+        for image in [monitor, interpreter] {
+            if image.len() > ROM_SIZE {
+                return Err(C8Error::RomTooLarge {
+                    size: image.len(),
+                    max: ROM_SIZE,
+                });
+            }
+        }
+
+        let mut rom = monitor.to_vec();
+        rom.resize(ROM_SIZE, 0);
+        let mut vip = Self {
+            cpu: Cdp1802::new(),
+            hw: Hardware {
+                ram: vec![0; RAM_SIZE],
+                rom,
+                rom_at_zero: true,
+                display: false,
+                key_latch: 0,
+                keys: [false; 16],
+                display_flag: false,
+            },
+            interpreter: interpreter.to_vec(),
+            cycle: 0,
+            next_line: 0,
+            interrupted: false,
+            lines: [[0; LINE_BYTES]; DISPLAY_LINES],
+            screen: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        };
+        vip.reset();
+        Ok(vip)
+    }
+
+    /// Run the machine until the end of the current frame.
+    pub fn run_frame(&mut self) {
+        while self.cycle < Self::CYCLES_PER_FRAME {
+            let line = self.cycle / CYCLES_PER_LINE;
+            self.hw.display_flag = matches!(line, 76..80 | 204..208);
+            self.cycle += self.next_cycles(line);
+        }
+        self.cycle -= Self::CYCLES_PER_FRAME;
+        self.next_line = 0;
+        self.interrupted = false;
+
+        // the interpreter repeats every row on 4 lines
+        for (row, pixels) in self.screen.chunks_mut(SCREEN_WIDTH).enumerate() {
+            let line = &self.lines[row * DISPLAY_LINES / SCREEN_HEIGHT];
+            for (x, px) in pixels.iter_mut().enumerate() {
+                let lit = self.hw.display && line[x / 8] & (0x80 >> (x % 8)) != 0;
+                *px = lit as u8;
+            }
+        }
+    }
+
+    /// Do what's next at `line`: fetch it, interrupt, or execute an
+    /// instruction. Return the machine cycles it took.
+    fn next_cycles(&mut self, line: u32) -> u32 {
+        if !self.hw.display {
+            return self.cpu.step(&mut self.hw);
+        }
+
+        let display_line = (line.saturating_sub(FIRST_LINE) as usize).min(DISPLAY_LINES);
+        if line >= FIRST_LINE && self.next_line < display_line {
+            // the display was turned on midway, the lines missed are blank
+            self.lines[self.next_line..display_line].fill([0; LINE_BYTES]);
+            self.next_line = display_line;
+        }
+        if line >= FIRST_LINE && self.next_line == display_line && display_line < DISPLAY_LINES {
+            for byte in 0..LINE_BYTES {
+                self.lines[display_line][byte] = self.cpu.dma_out(&mut self.hw);
+            }
+            self.next_line += 1;
+            return LINE_BYTES as u32;
+        }
+
+        if (INTERRUPT_LINE..FIRST_LINE).contains(&line) && !self.interrupted {
+            let cycles = self.cpu.interrupt();
+            if cycles > 0 {
+                self.interrupted = true;
+                return cycles;
+            }
+        }
+        self.cpu.step(&mut self.hw)
+    }
+
+    /// Whether the speaker sounds, the Q output drives it.
+    pub fn is_sounding(&self) -> bool {
+        self.cpu.q
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.hw.ram
+    }
+}
+
+impl Machine for CosmacVip {
+    fn load(&mut self, rom: &[u8]) -> Result<(), C8Error> {
+        let max = PROGRAM_END - START_ADDR;
+        if rom.len() > max {
+            return Err(C8Error::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+        self.hw.ram[START_ADDR..START_ADDR + rom.len()].copy_from_slice(rom);
+        Ok(())
+    }
+
+    /// Like turning the VIP on with the RUN switch: the monitor starts the
+    /// interpreter unless the C key is held.
+    fn reset(&mut self) {
+        self.cpu = Cdp1802::new();
+        self.hw.ram.fill(0);
+        self.hw.ram[..self.interpreter.len()].copy_from_slice(&self.interpreter);
+        self.hw.rom_at_zero = true;
+        self.hw.display = false;
+        self.hw.key_latch = 0;
+        self.hw.display_flag = false;
+        self.cycle = 0;
+        self.next_line = 0;
+        self.interrupted = false;
+        self.lines = [[0; LINE_BYTES]; DISPLAY_LINES];
+        self.screen = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+    }
+
+    fn press_key(&mut self, idx: usize, pressed: bool) -> Result<(), C8Error> {
+        let key = self.hw.keys.get_mut(idx).ok_or(C8Error::InvalidKey(idx))?;
+        *key = pressed;
+        Ok(())
+    }
+
+    fn get_screen(&self) -> Screen<'_> {
+        Screen::new(SCREEN_WIDTH, SCREEN_HEIGHT, &self.screen)
+    }
+
+    /// The interrupt routine counts the timer down in R8.1.
+    fn delay_timer(&self) -> u8 {
+        (self.cpu.r[8] >> 8) as u8
+    }
+
+    /// And the tone timer in R8.0.
+    fn sound_timer(&self) -> u8 {
+        self.cpu.r[8] as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Not the real monitor: it only starts the program in RAM with R3
+    /// as the program counter.
+    const MONITOR: [u8; 8] = [
+        0xC0, 0x80, 0x03, // 8000: LBR 8003, leaving the mirror at 0000
+        0xF8, 0x00, // 8003: LDI 00
+        0xB3, // 8005: PHI R3
+        0xA3, // 8006: PLO R3
+        0xD3, // 8007: SEP R3
+    ];
+
+    /// Turns the display on and points R0 to 0x900 on every interrupt.
+    fn interpreter() -> Vec<u8> {
+        let mut image = vec![0; 0x10B];
+        image[..0x10].copy_from_slice(&[
+            0xF8, 0x01, 0xB1, // 000: LDI 01, PHI R1
+            0xF8, 0x01, 0xA1, // 003: LDI 01, PLO R1, the routine at 0101
+            0xF8, 0x01, 0xB2, // 006: LDI 01, PHI R2
+            0xF8, 0xF0, 0xA2, // 009: LDI F0, PLO R2, the stack at 01F0
+            0xE2, // 00C: SEX R2
+            0x69, // 00D: INP 1, the display on
+            0x30, 0x0E, // 00E: BR 0E
+        ]);
+        image[0x100..].copy_from_slice(&[
+            0x70, // 100: RET
+            0x22, 0x78, // 101: DEC R2, SAV
+            0xF8, 0x09, 0xB0, // 103: LDI 09, PHI R0
+            0xF8, 0x00, 0xA0, // 106: LDI 00, PLO R0
+            0x30, 0x00, // 109: BR 00, to return
+        ]);
+        image
+    }
+
+    #[test]
+    fn display() {
+        let mut vip = CosmacVip::new(&MONITOR, &interpreter()).unwrap();
+        // the first line of the first and last rows
+        let mut rom = vec![0; 0x900 + DISPLAY_LINES * LINE_BYTES - START_ADDR];
+        rom[0x900 - START_ADDR] = 0x80;
+        rom[0x900 + 31 * 32 + 7 - START_ADDR] = 0x01;
+        vip.load(&rom).unwrap();
+
+        vip.run_frame();
+        vip.run_frame();
+
+        let screen = vip.get_screen();
+        let lit: Vec<(usize, usize)> = (0..SCREEN_HEIGHT)
+            .flat_map(|y| (0..SCREEN_WIDTH).map(move |x| (x, y)))
+            .filter(|(x, y)| screen.pixel(*x, *y))
+            .collect();
+        assert_eq!(vec![(0, 0), (63, 31)], lit);
+        // the program went back to its loop after the interrupts
+        assert_eq!((3, 0x0E), (vip.cpu.p, vip.cpu.r[3]));
+    }
+
+    #[test]
+    fn keypad() {
+        let mut vip = CosmacVip::new(&MONITOR, &interpreter()).unwrap();
+        vip.press_key(5, true).unwrap();
+
+        vip.hw.output(2, 0x05);
+        assert!(vip.hw.flag(3));
+        vip.hw.output(2, 0x06);
+        assert!(!vip.hw.flag(3));
+        assert_eq!(Err(C8Error::InvalidKey(16)), vip.press_key(16, true));
+    }
+
+    #[test]
+    fn images_too_large() {
+        assert!(CosmacVip::new(&[0; 0x201], &interpreter()).is_err());
+        let mut vip = CosmacVip::new(&MONITOR, &interpreter()).unwrap();
+        assert_eq!(
+            Err(C8Error::RomTooLarge {
+                size: 0xCA1,
+                max: 0xCA0
+            }),
+            vip.load(&[0; 0xCA1])
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::ops::RangeInclusive;
+
+use crate::{C8Emulator, Instruction};
+
+/// Which memory accesses a watchpoint reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Writes that change the stored value.
+    Change,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+}
+
+/// A memory access matching a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub kind: WatchKind,
+    /// The address of the instruction that touched memory, or of the
+    /// program start when the ROM was loaded over the range.
+    pub pc: u16,
+    /// `None` when the ROM was loaded over the range.
+    pub instruction: Option<Instruction>,
+    /// The value before the access, the same as `new` for reads.
+    pub old: u8,
+    pub new: u8,
+}
+
+#[derive(Default)]
+pub(crate) struct Watches {
+    points: Vec<Watchpoint>,
+    hit: Option<WatchHit>,
+    /// The instruction being executed and its address.
+    current: Option<(u16, Instruction)>,
+}
+
+impl Watches {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub(crate) fn is_hit(&self) -> bool {
+        self.hit.is_some()
+    }
+
+    pub(crate) fn clear_hit(&mut self) {
+        self.hit = None;
+    }
+
+    pub(crate) fn executing(&mut self, addr: u16, instruction: Instruction) {
+        self.current = Some((addr, instruction));
+    }
+
+    /// Outside of any instruction, like loading a ROM.
+    pub(crate) fn idle(&mut self) {
+        self.current = None;
+    }
+
+    pub(crate) fn on_read(&mut self, pc: u16, addr: usize, val: u8) {
+        self.check(pc, addr, val, val, |kind| kind == WatchKind::Read);
+    }
+
+    pub(crate) fn on_write(&mut self, pc: u16, addr: usize, old: u8, new: u8) {
+        self.check(pc, addr, old, new, |kind| match kind {
+            WatchKind::Read => false,
+            WatchKind::Write => true,
+            WatchKind::Change => old != new,
+        });
+    }
+
+    fn check(
+        &mut self,
+        pc: u16,
+        addr: usize,
+        old: u8,
+        new: u8,
+        matches: impl Fn(WatchKind) -> bool,
+    ) {
+        // only the first access is reported until the hit is taken
+        if self.hit.is_some() {
+            return;
+        }
+
+        let point = self
+            .points
+            .iter()
+            .find(|point| point.range.contains(&(addr as u16)) && matches(point.kind));
+        if let Some(point) = point {
+            let (pc, instruction) = match self.current {
+                Some((addr, instruction)) => (addr, Some(instruction)),
+                None => (pc, None),
+            };
+            self.hit = Some(WatchHit {
+                addr: addr as u16,
+                kind: point.kind,
+                pc,
+                instruction,
+                old,
+                new,
+            });
+        }
+    }
+}
+
+impl C8Emulator {
+    /// Report the accesses of `kind` to the addresses in `range`.
+    ///
+    /// Execution stops after the instruction that hit a watchpoint
+    /// until the hit is taken with `take_watch_hit`.
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) {
+        self.watches.points.push(Watchpoint { range, kind });
+    }
+
+    /// Return false if there was no such watchpoint.
+    pub fn remove_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) -> bool {
+        let len = self.watches.points.len();
+        self.watches
+            .points
+            .retain(|point| point.range != range || point.kind != kind);
+        self.watches.points.len() != len
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watches.points.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watches.points
+    }
+
+    pub fn watch_hit(&self) -> Option<&WatchHit> {
+        self.watches.hit.as_ref()
+    }
+
+    /// Take the pending hit, letting the execution go on.
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watches.hit.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::START_ADDR;
+
+    /// Stores the BCD of 123 at 0x300, then reads it back.
+    const BCD: [u8; 10] = [
+        0xA3, 0x00, // 200: LD I, 0x300
+        0x60, 0x7B, // 202: LD V0, 123
+        0xF0, 0x33, // 204: LD B, V0
+        0xF2, 0x65, // 206: LD V2, [I]
+        0x12, 0x08, // 208: JP 208
+    ];
+
+    fn run(c8: &mut C8Emulator, cycles: usize) {
+        for _ in 0..cycles {
+            c8.cpu_cycle().unwrap();
+        }
+    }
+
+    #[test]
+    fn write_and_read_watchpoints() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&BCD).unwrap();
+        c8.add_watchpoint(0x301..=0x301, WatchKind::Write);
+
+        run(&mut c8, 10);
+        let hit = c8.take_watch_hit().unwrap();
+        assert_eq!(0x301, hit.addr);
+        assert_eq!(0x204, hit.pc);
+        assert_eq!(Some(Instruction::Bcd(0)), hit.instruction);
+        assert_eq!((0, 2), (hit.old, hit.new));
+        // the execution stopped after the instruction
+        assert_eq!(0x206, c8.pc());
+
+        c8.clear_watchpoints();
+        c8.add_watchpoint(0x300..=0x302, WatchKind::Read);
+        run(&mut c8, 1);
+        let hit = c8.take_watch_hit().unwrap();
+        assert_eq!((0x300, WatchKind::Read, 1), (hit.addr, hit.kind, hit.new));
+        assert_eq!([1, 2, 3], c8.v_regs()[..3]);
+    }
+
+    #[test]
+    fn change_watchpoints() {
+        let mut c8 = C8Emulator::new();
+        c8.load(&BCD).unwrap();
+        c8.add_watchpoint(0x300..=0x300, WatchKind::Change);
+        c8.write_ram(0x300, 1).unwrap();
+        assert_eq!(None, c8.take_watch_hit().unwrap().instruction);
+
+        // the hundreds digit is already 1, writing it changes nothing
+        run(&mut c8, 10);
+        assert_eq!(None, c8.watch_hit());
+        assert!(c8.remove_watchpoint(0x300..=0x300, WatchKind::Change));
+    }
+
+    #[test]
+    fn rom_load_hits() {
+        let mut c8 = C8Emulator::new();
+        c8.add_watchpoint(0x204..=0x205, WatchKind::Change);
+        c8.load(&BCD).unwrap();
+
+        let hit = *c8.watch_hit().unwrap();
+        assert_eq!((0x204, None), (hit.addr, hit.instruction));
+        assert_eq!(START_ADDR as u16, hit.pc);
+
+        // nothing runs until the hit is taken
+        run(&mut c8, 1);
+        assert_eq!(0x200, c8.pc());
+        c8.take_watch_hit();
+        run(&mut c8, 1);
+        assert_eq!(0x202, c8.pc());
+    }
+}
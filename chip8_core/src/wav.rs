@@ -0,0 +1,151 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+const HEADER_SIZE: u32 = 44;
+const BYTES_PER_SAMPLE: u16 = 2;
+/// The sizes in the RIFF header are 32 bits, which limits the file to
+/// 4 GiB, about 13 hours at 44.1 kHz.
+const MAX_SAMPLES: u32 = (u32::MAX - (HEADER_SIZE - 8)) / BYTES_PER_SAMPLE as u32;
+
+/// Writes mono samples as a 16-bit PCM WAV file.
+///
+/// The sizes in the header are only known at the end, they're written
+/// by `finish`, or when the writer is dropped ignoring the errors.
+pub struct WavWriter<W: Write + Seek> {
+    sink: Option<W>,
+    samples: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut sink: W, sample_rate: u32) -> io::Result<Self> {
+        let byte_rate = sample_rate
+            .checked_mul(BYTES_PER_SAMPLE as u32)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "sample rate too high"))?;
+
+        sink.write_all(b"RIFF")?;
+        sink.write_all(&0u32.to_le_bytes())?; // patched by finish
+        sink.write_all(b"WAVE")?;
+        sink.write_all(b"fmt ")?;
+        sink.write_all(&16u32.to_le_bytes())?;
+        sink.write_all(&1u16.to_le_bytes())?; // PCM
+        sink.write_all(&1u16.to_le_bytes())?; // mono
+        sink.write_all(&sample_rate.to_le_bytes())?;
+        sink.write_all(&byte_rate.to_le_bytes())?;
+        sink.write_all(&BYTES_PER_SAMPLE.to_le_bytes())?;
+        sink.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+        sink.write_all(b"data")?;
+        sink.write_all(&0u32.to_le_bytes())?; // patched by finish
+
+        Ok(Self {
+            sink: Some(sink),
+            samples: 0,
+        })
+    }
+
+    /// Append samples in -1.0..=1.0, out of range values are clipped.
+    /// Nothing is written when they would go past the 4 GiB a WAV file
+    /// can hold.
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let Some(sink) = self.sink.as_mut() else {
+            return Ok(());
+        };
+        let total = u32::try_from(samples.len())
+            .ok()
+            .and_then(|len| self.samples.checked_add(len))
+            .filter(|total| *total <= MAX_SAMPLES)
+            .ok_or_else(too_long)?;
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| ((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+            .collect();
+        sink.write_all(&bytes)?;
+        self.samples = total;
+        Ok(())
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// Write the sizes in the header and give back the sink.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut sink = self.sink.take().expect("the sink is only taken here");
+        Self::write_sizes(&mut sink, self.samples)?;
+        Ok(sink)
+    }
+
+    fn write_sizes(sink: &mut W, samples: u32) -> io::Result<()> {
+        let data_size = samples
+            .checked_mul(BYTES_PER_SAMPLE as u32)
+            .ok_or_else(too_long)?;
+        let riff_size = data_size
+            .checked_add(HEADER_SIZE - 8)
+            .ok_or_else(too_long)?;
+
+        sink.seek(SeekFrom::Start(4))?;
+        sink.write_all(&riff_size.to_le_bytes())?;
+        sink.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        sink.write_all(&data_size.to_le_bytes())?;
+        sink.seek(SeekFrom::End(0))?;
+        sink.flush()
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        if let Some(sink) = self.sink.as_mut() {
+            let _ = Self::write_sizes(sink, self.samples);
+        }
+    }
+}
+
+fn too_long() -> io::Error {
+    io::Error::other("the WAV file would be larger than 4 GiB")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn header_and_samples() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 8000).unwrap();
+        wav.write(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(HEADER_SIZE as usize + 8, bytes.len());
+        assert_eq!(b"RIFF", &bytes[..4]);
+        assert_eq!(
+            44 - 8 + 8,
+            u32::from_le_bytes(bytes[4..8].try_into().unwrap())
+        );
+        assert_eq!(8000, u32::from_le_bytes(bytes[24..28].try_into().unwrap()));
+        assert_eq!(b"data", &bytes[36..40]);
+        assert_eq!(8, u32::from_le_bytes(bytes[40..44].try_into().unwrap()));
+
+        let samples: Vec<i16> = bytes[44..]
+            .chunks(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(vec![0, i16::MAX, -i16::MAX, i16::MAX], samples);
+    }
+
+    #[test]
+    fn size_limit() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 8000).unwrap();
+        wav.samples = MAX_SAMPLES - 1;
+        assert!(wav.write(&[0.0, 0.0]).is_err());
+        assert_eq!(MAX_SAMPLES - 1, wav.samples());
+        wav.write(&[0.0]).unwrap();
+
+        let bytes = wav.finish().unwrap().into_inner();
+        assert_eq!(HEADER_SIZE as usize + 2, bytes.len());
+        assert_eq!(
+            u32::MAX - 1,
+            u32::from_le_bytes(bytes[4..8].try_into().unwrap())
+        );
+
+        assert!(WavWriter::new(Cursor::new(Vec::new()), u32::MAX).is_err());
+    }
+}
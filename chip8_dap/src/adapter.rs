@@ -0,0 +1,635 @@
+use std::{fs, path::Path};
+
+use chip8_core::{C8Emulator, Debugger, Program, Quirks, StopReason};
+
+use crate::json::Json;
+
+/// The only thread of the target.
+const THREAD_ID: i64 = 1;
+
+/// Cycles executed every frame while running.
+const CYCLES_PER_FRAME: usize = 10;
+
+/// Variables references of the scopes.
+const REGISTERS_REF: i64 = 1;
+const TIMERS_REF: i64 = 2;
+const MEMORY_REF: i64 = 3;
+
+/// Bytes shown on each row of the memory scope.
+const MEMORY_ROW: usize = 16;
+
+/// A debug session: turns the requests of the client into responses
+/// and events, queued until the transport takes them.
+#[derive(Default)]
+pub struct Session {
+    debugger: Option<Debugger>,
+    /// Symbol map of the program, when launched from source.
+    program: Option<Program>,
+    source_path: Option<String>,
+    line_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>,
+    stop_on_entry: bool,
+    seq: i64,
+    output: Vec<Json>,
+    terminated: bool,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the target runs and `run_frame` should be called at
+    /// 60 frames per second.
+    pub fn is_running(&self) -> bool {
+        self.debugger.as_ref().is_some_and(|dbg| !dbg.is_paused())
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+
+    /// The messages to send to the client.
+    pub fn take_output(&mut self) -> Vec<Json> {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn handle(&mut self, message: &Json) {
+        if message.get("type").as_str() != Some("request") {
+            return;
+        }
+        let command = message.get("command").as_str().unwrap_or_default();
+        let args = message.get("arguments");
+
+        let body = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(Json::object([("breakpoints", vec![].into())])),
+            "configurationDone" => self.configuration_done(),
+            "threads" => Ok(threads()),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(scopes()),
+            "variables" => self.variables(args),
+            "readMemory" => self.read_memory(args),
+            "continue" => self.command(|dbg| dbg.resume()),
+            "next" => self.command(|dbg| dbg.step_over()),
+            "stepIn" => self.command(|dbg| dbg.step()),
+            "stepOut" => self.command(|dbg| dbg.step_out()),
+            "pause" => self.pause(),
+            "disconnect" | "terminate" => {
+                self.terminated = true;
+                Ok(Json::Null)
+            }
+            _ => Err(format!("unsupported request `{command}`")),
+        };
+
+        let success = body.is_ok();
+        self.respond(message, command, body);
+        match command {
+            // the client sends the breakpoints and configurationDone next
+            "launch" if success => self.event("initialized", Json::Null),
+            "pause" if success => self.stopped("pause", None),
+            _ => (),
+        }
+    }
+
+    /// Run a frame of the target, reporting why it stopped if it did.
+    pub fn run_frame(&mut self) {
+        let Some(dbg) = self.debugger.as_mut() else {
+            return;
+        };
+        match dbg.run(CYCLES_PER_FRAME) {
+            Some(reason) => self.report(reason),
+            None => dbg.emulator_mut().frame_cycle(),
+        }
+    }
+
+    fn report(&mut self, reason: StopReason) {
+        match reason {
+            StopReason::Step | StopReason::AtReturn | StopReason::Returned => {
+                self.stopped("step", None)
+            }
+            StopReason::Breakpoint(_) => self.stopped("breakpoint", None),
+            StopReason::Watchpoint(hit) => self.stopped(
+                "data breakpoint",
+                Some(format!("memory at 0x{:03X} accessed", hit.addr)),
+            ),
+            StopReason::Fault(err) => self.stopped("exception", Some(err.to_string())),
+            StopReason::Halted => {
+                self.event("exited", Json::object([("exitCode", 0.into())]));
+                self.event("terminated", Json::Null);
+            }
+        }
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        let path = args
+            .get("program")
+            .as_str()
+            .ok_or("missing `program` launch argument")?;
+        let quirks = match args.get("quirks").as_str() {
+            Some(name) => Quirks::preset(name).ok_or(format!("unknown quirks preset `{name}`"))?,
+            None => Quirks::default(),
+        };
+        let xo_chip = args.get("xoChip").as_bool().unwrap_or(false);
+        self.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+
+        // sources are assembled, anything else is a ROM image
+        let is_source = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext == "asm" || ext == "s");
+        let rom = if is_source {
+            let source = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+            let program =
+                chip8_core::assemble_program(&source).map_err(|err| format!("{path}: {err}"))?;
+            let rom = program.rom.clone();
+            self.program = Some(program);
+            self.source_path = Some(path.to_string());
+            rom
+        } else {
+            fs::read(path).map_err(|err| format!("{path}: {err}"))?
+        };
+
+        let mut ch8 = C8Emulator::with_quirks(quirks);
+        ch8.set_xo_chip(xo_chip);
+        ch8.load(&rom).map_err(|err| err.to_string())?;
+        self.debugger = Some(Debugger::new(ch8));
+        Ok(Json::Null)
+    }
+
+    fn configuration_done(&mut self) -> Result<Json, String> {
+        if self.stop_on_entry {
+            self.stopped("entry", None);
+        } else {
+            self.debugger_mut()?.resume();
+        }
+        Ok(Json::Null)
+    }
+
+    /// Breakpoints on source lines, the lines that don't emit code can't
+    /// hold a breakpoint.
+    fn set_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let mut addrs = Vec::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in args.get("breakpoints").as_array() {
+            let line = breakpoint.get("line").as_i64().unwrap_or(0);
+            let addr = self
+                .program
+                .as_ref()
+                .and_then(|program| program.addr_of_line(line as usize));
+
+            breakpoints.push(match addr {
+                Some(addr) => {
+                    addrs.push(addr);
+                    Json::object([
+                        ("verified", true.into()),
+                        ("line", line.into()),
+                        ("instructionReference", format!("0x{addr:03X}").into()),
+                    ])
+                }
+                None => Json::object([
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", "no code on this line".into()),
+                ]),
+            });
+        }
+
+        self.line_breakpoints = addrs;
+        self.apply_breakpoints()?;
+        Ok(Json::object([("breakpoints", breakpoints.into())]))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let mut addrs = Vec::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in args.get("breakpoints").as_array() {
+            let reference = breakpoint.get("instructionReference").as_str();
+            let offset = breakpoint.get("offset").as_i64().unwrap_or(0);
+            let addr = reference
+                .and_then(parse_addr)
+                .and_then(|addr| (addr as i64).checked_add(offset))
+                .and_then(|addr| u16::try_from(addr).ok());
+
+            breakpoints.push(Json::object([("verified", addr.is_some().into())]));
+            addrs.extend(addr);
+        }
+
+        self.instruction_breakpoints = addrs;
+        self.apply_breakpoints()?;
+        Ok(Json::object([("breakpoints", breakpoints.into())]))
+    }
+
+    fn apply_breakpoints(&mut self) -> Result<(), String> {
+        let addrs: Vec<u16> = self
+            .line_breakpoints
+            .iter()
+            .chain(&self.instruction_breakpoints)
+            .copied()
+            .collect();
+        let dbg = self.debugger_mut()?;
+        dbg.clear_breakpoints();
+        for addr in addrs {
+            dbg.add_breakpoint(addr);
+        }
+        Ok(())
+    }
+
+    /// The frame at PC, then a frame for every call in the stack.
+    fn stack_trace(&mut self) -> Result<Json, String> {
+        let ch8 = self.debugger_ref()?.emulator();
+
+        let mut addrs = vec![ch8.pc()];
+        // the calls are two bytes before their return address
+        addrs.extend(ch8.stack().iter().rev().map(|ret| ret.wrapping_sub(2)));
+
+        let frames: Vec<Json> = addrs
+            .iter()
+            .enumerate()
+            .map(|(idx, addr)| self.frame(idx as i64, *addr))
+            .collect();
+        let total = frames.len() as i64;
+        Ok(Json::object([
+            ("stackFrames", frames.into()),
+            ("totalFrames", total.into()),
+        ]))
+    }
+
+    fn frame(&self, id: i64, addr: u16) -> Json {
+        let line = self
+            .program
+            .as_ref()
+            .and_then(|program| program.line_of_addr(addr));
+
+        let mut fields = vec![
+            ("id".to_string(), id.into()),
+            ("name".to_string(), format!("0x{addr:03X}").into()),
+            ("line".to_string(), (line.unwrap_or(0) as i64).into()),
+            ("column".to_string(), 0.into()),
+            (
+                "instructionPointerReference".to_string(),
+                format!("0x{addr:03X}").into(),
+            ),
+        ];
+        if let (Some(path), Some(_)) = (&self.source_path, line) {
+            fields.push((
+                "source".to_string(),
+                Json::object([("path", path.as_str().into())]),
+            ));
+        }
+        Json::Object(fields)
+    }
+
+    fn variables(&mut self, args: &Json) -> Result<Json, String> {
+        let ch8 = self.debugger_ref()?.emulator();
+
+        let variables: Vec<Json> = match args.get("variablesReference").as_i64() {
+            Some(REGISTERS_REF) => {
+                let mut vars: Vec<Json> = ch8
+                    .v_regs()
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, val)| variable(&format!("V{idx:X}"), format!("0x{val:02X}")))
+                    .collect();
+                let mut i_reg = variable("I", format!("0x{:03X}", ch8.i_reg()));
+                if let Json::Object(fields) = &mut i_reg {
+                    fields.push((
+                        "memoryReference".to_string(),
+                        format!("0x{:03X}", ch8.i_reg()).into(),
+                    ));
+                }
+                vars.push(i_reg);
+                vars.push(variable("PC", format!("0x{:03X}", ch8.pc())));
+                vars.push(variable("SP", ch8.stack().len().to_string()));
+                vars
+            }
+            Some(TIMERS_REF) => vec![
+                variable("DT", ch8.delay_timer().to_string()),
+                variable("ST", ch8.sound_timer().to_string()),
+            ],
+            Some(MEMORY_REF) => ch8
+                .ram()
+                .chunks(MEMORY_ROW)
+                .enumerate()
+                .map(|(row, bytes)| {
+                    let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02X}")).collect();
+                    variable(&format!("0x{:03X}", row * MEMORY_ROW), hex.join(" "))
+                })
+                .collect(),
+            _ => return Err("unknown variables reference".to_string()),
+        };
+        Ok(Json::object([("variables", variables.into())]))
+    }
+
+    fn read_memory(&mut self, args: &Json) -> Result<Json, String> {
+        let ram = self.debugger_ref()?.emulator().ram();
+
+        let reference = args.get("memoryReference").as_str().unwrap_or_default();
+        let base = parse_addr(reference).ok_or("invalid memory reference")?;
+        let offset = args.get("offset").as_i64().unwrap_or(0);
+        let count = args.get("count").as_i64().unwrap_or(0).max(0) as usize;
+
+        let start = (base as i64)
+            .saturating_add(offset)
+            .clamp(0, ram.len() as i64) as usize;
+        let end = start.saturating_add(count).min(ram.len());
+        let unreadable = (count - (end - start)) as i64;
+        Ok(Json::object([
+            ("address", format!("0x{start:03X}").into()),
+            ("data", base64(&ram[start..end]).into()),
+            ("unreadableBytes", unreadable.into()),
+        ]))
+    }
+
+    fn command(&mut self, start: impl FnOnce(&mut Debugger)) -> Result<Json, String> {
+        start(self.debugger_mut()?);
+        Ok(Json::Null)
+    }
+
+    fn pause(&mut self) -> Result<Json, String> {
+        self.debugger_mut()?.pause();
+        Ok(Json::Null)
+    }
+
+    fn debugger_ref(&self) -> Result<&Debugger, String> {
+        self.debugger
+            .as_ref()
+            .ok_or("no program launched".to_string())
+    }
+
+    fn debugger_mut(&mut self) -> Result<&mut Debugger, String> {
+        self.debugger
+            .as_mut()
+            .ok_or("no program launched".to_string())
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) {
+        let mut body = Json::object([
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ]);
+        if let (Json::Object(fields), Some(description)) = (&mut body, description) {
+            fields.push(("description".to_string(), description.clone().into()));
+            fields.push(("text".to_string(), description.into()));
+        }
+        self.event("stopped", body);
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.seq += 1;
+        let mut fields = vec![
+            ("seq".to_string(), self.seq.into()),
+            ("type".to_string(), "event".into()),
+            ("event".to_string(), event.into()),
+        ];
+        if body != Json::Null {
+            fields.push(("body".to_string(), body));
+        }
+        self.output.push(Json::Object(fields));
+    }
+
+    fn respond(&mut self, request: &Json, command: &str, body: Result<Json, String>) {
+        self.seq += 1;
+        let mut fields = vec![
+            ("seq".to_string(), self.seq.into()),
+            ("type".to_string(), "response".into()),
+            (
+                "request_seq".to_string(),
+                request.get("seq").as_i64().unwrap_or(0).into(),
+            ),
+            ("command".to_string(), command.into()),
+        ];
+        match body {
+            Ok(body) => {
+                fields.push(("success".to_string(), true.into()));
+                if body != Json::Null {
+                    fields.push(("body".to_string(), body));
+                }
+            }
+            Err(message) => {
+                fields.push(("success".to_string(), false.into()));
+                fields.push(("message".to_string(), message.into()));
+            }
+        }
+        self.output.push(Json::Object(fields));
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        ("supportsConfigurationDoneRequest", true.into()),
+        ("supportsInstructionBreakpoints", true.into()),
+        ("supportsReadMemoryRequest", true.into()),
+        ("supportsTerminateRequest", true.into()),
+    ])
+}
+
+fn threads() -> Json {
+    let thread = Json::object([("id", THREAD_ID.into()), ("name", "CHIP-8".into())]);
+    Json::object([("threads", vec![thread].into())])
+}
+
+fn scopes() -> Json {
+    let scope = |name: &str, reference: i64, expensive: bool| {
+        Json::object([
+            ("name", name.into()),
+            ("variablesReference", reference.into()),
+            ("expensive", expensive.into()),
+        ])
+    };
+    let scopes = vec![
+        scope("Registers", REGISTERS_REF, false),
+        scope("Timers", TIMERS_REF, false),
+        scope("Memory", MEMORY_REF, true),
+    ];
+    Json::object([("scopes", scopes.into())])
+}
+
+fn variable(name: &str, value: String) -> Json {
+    Json::object([
+        ("name", name.into()),
+        ("value", value.into()),
+        ("variablesReference", 0.into()),
+    ])
+}
+
+/// Addresses are `0x` hexadecimal, or decimal.
+fn parse_addr(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (idx, byte)| {
+            group | (*byte as u32) << (16 - 8 * idx)
+        });
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                text.push(ALPHABET[(group >> (18 - 6 * idx) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+start:
+    LD V0, 1
+    CALL sub
+    JP start
+sub:
+    ADD V1, 1
+    RET
+";
+
+    fn request(seq: i64, command: &str, arguments: Json) -> Json {
+        Json::object([
+            ("seq", seq.into()),
+            ("type", "request".into()),
+            ("command", command.into()),
+            ("arguments", arguments),
+        ])
+    }
+
+    /// The tests run in parallel, each needs its own source file.
+    fn launched(test: &str) -> Session {
+        let path =
+            std::env::temp_dir().join(format!("chip8_dap_{test}_{}.asm", std::process::id()));
+        fs::write(&path, SOURCE).unwrap();
+
+        let mut session = Session::new();
+        session.handle(&request(
+            1,
+            "launch",
+            Json::object([("program", path.to_str().unwrap().into())]),
+        ));
+        fs::remove_file(path).unwrap();
+        session
+    }
+
+    #[test]
+    fn launch_and_break_on_line() {
+        let mut session = launched("launch");
+        let output = session.take_output();
+        assert_eq!(Some(true), output[0].get("success").as_bool());
+        assert_eq!(Some("initialized"), output[1].get("event").as_str());
+
+        // line 6 is `ADD V1, 1`, line 5 only holds a label
+        let lines = vec![
+            Json::object([("line", 6.into())]),
+            Json::object([("line", 5.into())]),
+        ];
+        session.handle(&request(
+            2,
+            "setBreakpoints",
+            Json::object([("breakpoints", lines.into())]),
+        ));
+        let output = session.take_output();
+        let breakpoints = output[0].get("body").get("breakpoints").as_array();
+        assert_eq!(
+            Some("0x206"),
+            breakpoints[0].get("instructionReference").as_str()
+        );
+        assert_eq!(Some(false), breakpoints[1].get("verified").as_bool());
+
+        session.handle(&request(3, "configurationDone", Json::Null));
+        assert!(session.is_running());
+        session.run_frame();
+        let output = session.take_output();
+        let stopped = output.last().unwrap();
+        assert_eq!(
+            Some("breakpoint"),
+            stopped.get("body").get("reason").as_str()
+        );
+
+        // the call site is on line 3
+        session.handle(&request(4, "stackTrace", Json::Null));
+        let output = session.take_output();
+        let frames = output[0].get("body").get("stackFrames").as_array();
+        assert_eq!(2, frames.len());
+        assert_eq!(Some(6), frames[0].get("line").as_i64());
+        assert_eq!(Some(3), frames[1].get("line").as_i64());
+    }
+
+    #[test]
+    fn variables_and_memory() {
+        let mut session = launched("variables");
+        session.handle(&request(2, "stepIn", Json::Null));
+        session.run_frame();
+        session.take_output();
+
+        session.handle(&request(
+            3,
+            "variables",
+            Json::object([("variablesReference", REGISTERS_REF.into())]),
+        ));
+        let output = session.take_output();
+        let vars = output[0].get("body").get("variables").as_array();
+        assert_eq!(Some("0x01"), vars[0].get("value").as_str());
+        assert_eq!(Some("0x202"), vars[17].get("value").as_str());
+
+        session.handle(&request(
+            4,
+            "readMemory",
+            Json::object([("memoryReference", "0x200".into()), ("count", 4.into())]),
+        ));
+        let output = session.take_output();
+        // 60 01 22 06
+        assert_eq!(Some("YAEiBg=="), output[0].get("body").get("data").as_str());
+
+        // offsets that overflow read nothing
+        session.handle(&request(
+            5,
+            "readMemory",
+            Json::object([
+                ("memoryReference", "0x200".into()),
+                ("offset", i64::MAX.into()),
+                ("count", 4.into()),
+            ]),
+        ));
+        let output = session.take_output();
+        assert_eq!(Some(""), output[0].get("body").get("data").as_str());
+        assert_eq!(
+            Some(4),
+            output[0].get("body").get("unreadableBytes").as_i64()
+        );
+
+        session.handle(&request(
+            6,
+            "setInstructionBreakpoints",
+            Json::object([(
+                "breakpoints",
+                Json::Array(vec![Json::object([
+                    ("instructionReference", "0x200".into()),
+                    ("offset", i64::MAX.into()),
+                ])]),
+            )]),
+        ));
+        let output = session.take_output();
+        let breakpoints = output[0].get("body").get("breakpoints").as_array();
+        assert_eq!(Some(false), breakpoints[0].get("verified").as_bool());
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9v", base64(b"foo"));
+    }
+}
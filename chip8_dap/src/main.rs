@@ -0,0 +1,106 @@
+//! Debug Adapter Protocol server for CHIP-8 programs, over stdio.
+//!
+//! The `launch` request takes the path of the `program`, a ROM image or
+//! an assembly source (`.asm` or `.s`) whose lines can hold breakpoints,
+//! and optionally a `quirks` preset, `xoChip` and `stopOnEntry`.
+
+mod adapter;
+mod json;
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use adapter::Session;
+use json::Json;
+
+/// The target runs at 60 frames per second.
+const FRAME: Duration = Duration::from_micros(16_667);
+
+fn main() -> io::Result<()> {
+    // requests are read on their own thread, so a pause can arrive
+    // while the target runs
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = BufReader::new(io::stdin());
+        while let Ok(Some(message)) = read_message(&mut stdin) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session::new();
+    let mut stdout = io::stdout();
+    let mut last_frame = Instant::now();
+
+    loop {
+        let request = if session.is_running() {
+            match requests.recv_timeout(FRAME.saturating_sub(last_frame.elapsed())) {
+                Ok(request) => Some(request),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            match requests.recv() {
+                Ok(request) => Some(request),
+                Err(_) => break,
+            }
+        };
+
+        if let Some(request) = request {
+            session.handle(&request);
+        }
+        if session.is_running() && last_frame.elapsed() >= FRAME {
+            session.run_frame();
+            last_frame = Instant::now();
+        }
+
+        for message in session.take_output() {
+            write_message(&mut stdout, &message)?;
+        }
+        if session.is_terminated() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a message framed by its `Content-Length` header, `None` at the
+/// end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(val) = header.strip_prefix("Content-Length:") {
+            length = val.trim().parse().ok();
+        }
+    }
+
+    let length =
+        length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    let text = String::from_utf8_lossy(&body);
+    Json::parse(&text)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
@@ -0,0 +1,197 @@
+//! A GDB remote serial protocol server over TCP, the transport of the
+//! `GdbStub` of chip8_core: `target remote :<port>` from GDB connects
+//! to it.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread::sleep,
+    time::Duration,
+};
+
+use chip8_core::{C8Emulator, GdbAction, GdbStub};
+
+/// Cycles executed by `serve` every frame.
+const CYCLES_PER_FRAME: usize = 10;
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+/// A GDB remote serial protocol server for a single connection.
+///
+/// The frontend calls `poll` to answer the pending packets and
+/// `run_frame` to make the target run while GDB resumed it, so it can
+/// keep showing the screen in between.
+pub struct GdbServer {
+    stream: TcpStream,
+    stub: GdbStub,
+    input: Vec<u8>,
+}
+
+impl GdbServer {
+    /// Wait for GDB to connect on localhost, `target remote :<port>`.
+    pub fn accept(port: u16, emulator: C8Emulator) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (stream, _) = listener.accept()?;
+        Self::new(stream, emulator)
+    }
+
+    /// Serve GDB on a connection it already opened.
+    pub fn new(stream: TcpStream, emulator: C8Emulator) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+
+        Ok(Self {
+            stream,
+            stub: GdbStub::new(emulator),
+            input: Vec::new(),
+        })
+    }
+
+    pub fn emulator(&self) -> &C8Emulator {
+        self.stub.debugger().emulator()
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut C8Emulator {
+        self.stub.debugger_mut().emulator_mut()
+    }
+
+    /// Whether GDB resumed the target.
+    pub fn is_running(&self) -> bool {
+        !self.stub.debugger().is_paused()
+    }
+
+    /// Answer the packets received so far, return false once GDB
+    /// detached or closed the connection.
+    pub fn poll(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(len) => self.input.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        while let Some(&first) = self.input.first() {
+            match first {
+                b'$' => {
+                    let Some(end) = self.input.iter().position(|b| *b == b'#') else {
+                        break;
+                    };
+                    // wait for the two checksum digits
+                    if self.input.len() < end + 3 {
+                        break;
+                    }
+                    let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+                    let data = String::from_utf8_lossy(&packet[1..end]).into_owned();
+                    let sum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                    if sum != Some(checksum(&data)) {
+                        self.write_all(b"-")?;
+                        continue;
+                    }
+
+                    self.write_all(b"+")?;
+                    match self.stub.handle(&data) {
+                        GdbAction::Reply(reply) => self.send(&reply)?,
+                        GdbAction::Resume => (),
+                        GdbAction::Detach => {
+                            self.send("OK")?;
+                            return Ok(false);
+                        }
+                    }
+                }
+                // Ctrl-C from GDB
+                0x03 => {
+                    self.input.remove(0);
+                    let reply = self.stub.interrupt();
+                    self.send(&reply)?;
+                }
+                // acks, and noise between packets
+                _ => {
+                    self.input.remove(0);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Run `cycles` CPU cycles and end the frame while the target is
+    /// running, sending the stop reply if it stopped.
+    pub fn run_frame(&mut self, cycles: usize) -> io::Result<()> {
+        if !self.is_running() {
+            return Ok(());
+        }
+        match self.stub.run(cycles) {
+            Some(reply) => self.send(&reply),
+            None => {
+                self.emulator_mut().frame_cycle();
+                Ok(())
+            }
+        }
+    }
+
+    /// Serve until GDB detaches, running about 60 frames per second.
+    pub fn serve(mut self) -> io::Result<C8Emulator> {
+        while self.poll()? {
+            self.run_frame(CYCLES_PER_FRAME)?;
+            sleep(Duration::from_millis(16));
+        }
+        Ok(self.stub.into_emulator())
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${data}#{:02x}", checksum(data));
+        self.write_all(packet.as_bytes())
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        // the stream is non blocking, retry until everything is sent
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            match self.stream.write(bytes) {
+                Ok(len) => bytes = &bytes[len..],
+                Err(err) if err.kind() == ErrorKind::WouldBlock => sleep(Duration::from_millis(1)),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut gdb = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut c8 = C8Emulator::new();
+        c8.load(&[0x12, 0x00]).unwrap();
+        let mut server = GdbServer::new(stream, c8).unwrap();
+
+        // a bad checksum is refused, then a packet split in two reads
+        gdb.write_all(b"$?#00$m20").unwrap();
+        gdb.flush().unwrap();
+        sleep(Duration::from_millis(20));
+        assert!(server.poll().unwrap());
+        gdb.write_all(b"0,2#5d").unwrap();
+        sleep(Duration::from_millis(20));
+        assert!(server.poll().unwrap());
+
+        gdb.write_all(b"$D#44").unwrap();
+        sleep(Duration::from_millis(20));
+        assert!(!server.poll().unwrap());
+        drop(server);
+
+        let mut received = String::new();
+        gdb.read_to_string(&mut received).unwrap();
+        assert_eq!("-+$1200#c3+$OK#9a", received);
+    }
+}
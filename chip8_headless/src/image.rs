@@ -0,0 +1,172 @@
+use chip8_core::Screen;
+
+/// Gray levels of the palette indexes in the PNG output.
+const GRAYS: [u8; 4] = [0x00, 0xFF, 0xAA, 0x55];
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// The most a stored deflate block can hold.
+const STORED_BLOCK_SIZE: usize = 0xFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ascii,
+    Pbm,
+    Png,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ascii" | "txt" => Some(Format::Ascii),
+            "pbm" => Some(Format::Pbm),
+            "png" => Some(Format::Png),
+            _ => None,
+        }
+    }
+
+    /// The format of a file by its extension, ASCII when it's unknown.
+    pub fn of_path(path: &str) -> Self {
+        path.rsplit_once('.')
+            .and_then(|(_, ext)| Format::from_name(&ext.to_ascii_lowercase()))
+            .unwrap_or(Format::Ascii)
+    }
+
+    pub fn encode(self, screen: &Screen) -> Vec<u8> {
+        match self {
+            Format::Ascii => screen.to_text().into_bytes(),
+            Format::Pbm => screen.to_pbm().into_bytes(),
+            Format::Png => png(screen),
+        }
+    }
+}
+
+/// An 8-bit grayscale PNG, with the image data stored uncompressed.
+fn png(screen: &Screen) -> Vec<u8> {
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(screen.width() as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(screen.height() as u32).to_be_bytes());
+    // bit depth, grayscale, deflate, no filters, not interlaced
+    ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+    // every row starts with its filter type, none
+    let mut raw = Vec::with_capacity((screen.width() + 1) * screen.height());
+    for row in screen.rows() {
+        raw.push(0);
+        raw.extend(row.iter().map(|color| GRAYS[*color as usize & 3]));
+    }
+
+    let mut bytes = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut bytes, b"IHDR", &ihdr);
+    write_chunk(&mut bytes, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut bytes, b"IEND", &[]);
+    bytes
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = bytes.len();
+    bytes.extend_from_slice(kind);
+    bytes.extend_from_slice(data);
+    let crc = crc32(&bytes[start..]);
+    bytes.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// A zlib stream of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x78, 0x01];
+    let mut blocks = data.chunks(STORED_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        // an empty final block
+        bytes.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        bytes.push(last as u8);
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes.extend_from_slice(&(!len).to_le_bytes());
+        bytes.extend_from_slice(block);
+    }
+    bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    bytes
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), byte| {
+        let a = (a + *byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8_core::C8Emulator;
+
+    use super::*;
+
+    /// Draws the font digit 0 at the top left corner.
+    fn zero() -> C8Emulator {
+        let mut c8 = C8Emulator::new();
+        c8.load(&[0xF0, 0x29, 0xD0, 0x05]).unwrap();
+        c8.cpu_cycle().unwrap();
+        c8.cpu_cycle().unwrap();
+        c8
+    }
+
+    #[test]
+    fn text_formats() {
+        let c8 = zero();
+        let screen = c8.get_screen();
+
+        let ascii = String::from_utf8(Format::Ascii.encode(&screen)).unwrap();
+        let lines: Vec<&str> = ascii.lines().collect();
+        assert_eq!(32, lines.len());
+        assert_eq!("####....", &lines[0][..8]);
+        assert_eq!("#..#....", &lines[1][..8]);
+        assert!(lines[5].chars().all(|chr| chr == '.'));
+
+        let pbm = String::from_utf8(Format::Pbm.encode(&screen)).unwrap();
+        assert!(pbm.starts_with("P1\n64 32\n1 1 1 1 0 0"));
+    }
+
+    #[test]
+    fn png_chunks() {
+        let c8 = zero();
+        let png = Format::Png.encode(&c8.get_screen());
+
+        assert_eq!(PNG_SIGNATURE, png[..8]);
+        assert_eq!(b"IHDR", &png[12..16]);
+        assert_eq!(64, u32::from_be_bytes(png[16..20].try_into().unwrap()));
+        // the IEND chunk has a well known CRC
+        assert_eq!(
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82],
+            png[png.len() - 12..]
+        );
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+        assert_eq!(0x11E6_0398, adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn formats_by_path() {
+        assert_eq!(Format::Png, Format::of_path("out/screen.PNG"));
+        assert_eq!(Format::Pbm, Format::of_path("screen.pbm"));
+        assert_eq!(Format::Ascii, Format::of_path("screen"));
+    }
+}
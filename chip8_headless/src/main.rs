@@ -0,0 +1,300 @@
+//! Runs a CHIP-8 program without a terminal, for batch and regression
+//! jobs: the ROM runs for a number of frames, or until the program
+//! counter reaches an address or the program halts, then a report with
+//! a hash of the state is printed and the final screen can be saved.
+//!
+//! The exit status is 0 when the run stopped as asked, 1 when the
+//! program faulted, 2 on bad arguments or files, and 3 when the frames
+//! ran out before `--until-pc` or `--until-halt` was reached.
+//!
+//! `chip8_headless conformance <dir>` runs the test ROMs of a directory
+//! as listed in its `conformance.txt`, see `Manifest`, and exits with 1
+//! when a check expected to pass doesn't.
+
+mod image;
+
+use std::{
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    process::ExitCode,
+};
+
+use chip8_core::{
+    C8Emulator, C8Error, Debugger, InputScript, Manifest, Quirks, Seed, StopReason, WavWriter,
+    MANIFEST_NAME, TIMER_HZ,
+};
+use image::Format;
+
+const USAGE: &str = "\
+usage: chip8_headless <rom> [options]
+  --frames <n>         frames to run at most, 600 by default
+  --until-pc <addr>    stop when the PC reaches the address, in hexadecimal
+  --until-halt         stop when the program executes 00FD
+  --ips <n>            instructions per second, 600 by default
+  --quirks <preset>    vip, chip48, schip or xochip
+  --xo-chip            enable XO-CHIP
+  --seed <n>           seed of the random numbers, 1 by default
+  --input <script>     key presses to replay, see InputScript
+  --screen <path>      save the final screen, - for stdout
+  --format <format>    ascii, pbm or png, by default from the extension
+  --audio-out <path>   write the sound to a WAV file
+       chip8_headless conformance <dir> [--json]
+  runs the test ROMs listed in <dir>/conformance.txt under their quirks
+  presets and reports their results";
+
+const DEFAULT_FRAMES: u64 = 600;
+/// The same speed as the terminal frontend.
+const DEFAULT_IPS: u32 = 10 * TIMER_HZ;
+
+const EXIT_FAULT: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_TIMEOUT: u8 = 3;
+
+struct Options {
+    rom_path: String,
+    frames: u64,
+    until_pc: Option<u16>,
+    until_halt: bool,
+    ips: u32,
+    quirks: Quirks,
+    xo_chip: bool,
+    seed: u64,
+    input_path: Option<String>,
+    screen_path: Option<String>,
+    format: Option<Format>,
+    audio_path: Option<String>,
+}
+
+/// Why the run ended.
+enum Stop {
+    Frames,
+    Pc(u16),
+    Halted,
+    Fault(C8Error),
+}
+
+fn main() -> ExitCode {
+    if env::args().nth(1).as_deref() == Some("conformance") {
+        return conformance(env::args().skip(2));
+    }
+
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    match run(&options) {
+        Ok(status) => ExitCode::from(status),
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::from(EXIT_USAGE)
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: String::new(),
+        frames: DEFAULT_FRAMES,
+        until_pc: None,
+        until_halt: false,
+        ips: DEFAULT_IPS,
+        quirks: Quirks::default(),
+        xo_chip: false,
+        seed: 1,
+        input_path: None,
+        screen_path: None,
+        format: None,
+        audio_path: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--frames" => {
+                let val = value()?;
+                options.frames = val.parse().map_err(|_| format!("Invalid frames {val}"))?;
+            }
+            "--until-pc" => {
+                let val = value()?;
+                let addr = u16::from_str_radix(val.trim_start_matches("0x"), 16)
+                    .map_err(|_| format!("Invalid address {val}"))?;
+                options.until_pc = Some(addr);
+            }
+            "--until-halt" => options.until_halt = true,
+            "--ips" => {
+                let val = value()?;
+                options.ips = val
+                    .parse()
+                    .map_err(|_| format!("Invalid instructions per second {val}"))?;
+            }
+            "--quirks" => {
+                let name = value()?;
+                options.quirks =
+                    Quirks::preset(&name).ok_or(format!("Unknown quirks preset {name}"))?;
+            }
+            "--xo-chip" => {
+                options.xo_chip = true;
+                options.quirks = Quirks::xo_chip();
+            }
+            "--seed" => {
+                let val = value()?;
+                options.seed = val.parse().map_err(|_| format!("Invalid seed {val}"))?;
+            }
+            "--input" => options.input_path = Some(value()?),
+            "--screen" => options.screen_path = Some(value()?),
+            "--format" => {
+                let name = value()?;
+                options.format =
+                    Some(Format::from_name(&name).ok_or(format!("Unknown format {name}"))?);
+            }
+            "--audio-out" => options.audio_path = Some(value()?),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {arg}")),
+            _ => options.rom_path = arg,
+        }
+    }
+
+    if options.rom_path.is_empty() {
+        return Err("Didn't get a rom path".to_string());
+    }
+    Ok(options)
+}
+
+fn run(options: &Options) -> Result<u8, Box<dyn Error>> {
+    let rom = fs::read(&options.rom_path)?;
+    let script = match &options.input_path {
+        Some(path) => InputScript::parse(&fs::read_to_string(path)?)?,
+        None => InputScript::default(),
+    };
+
+    let mut c8 = C8Emulator::with_quirks(options.quirks).with_seed(Seed::Fixed(options.seed));
+    c8.set_xo_chip(options.xo_chip);
+    c8.load(&rom)?;
+    let mut wav = match &options.audio_path {
+        Some(path) => {
+            c8.capture_audio(true);
+            let sink = BufWriter::new(File::create(path)?);
+            Some(WavWriter::new(sink, c8.sample_rate())?)
+        }
+        None => None,
+    };
+
+    // the debugger stops right at the address, before executing it
+    let mut debugger = Debugger::new(c8);
+    if let Some(addr) = options.until_pc {
+        debugger.add_breakpoint(addr);
+    }
+    debugger.resume();
+
+    let mut frame = 0;
+    let mut rest = 0;
+    let stop = loop {
+        if frame == options.frames {
+            break Stop::Frames;
+        }
+        script.apply(frame, debugger.emulator_mut())?;
+
+        // rates that aren't a multiple of 60 Hz spread the remainder
+        let cycles = (options.ips + rest) / TIMER_HZ;
+        rest = (options.ips + rest) % TIMER_HZ;
+        match debugger.run(cycles as usize) {
+            Some(StopReason::Breakpoint(addr)) => break Stop::Pc(addr),
+            Some(StopReason::Halted) => break Stop::Halted,
+            Some(StopReason::Fault(err)) => break Stop::Fault(err),
+            _ => (),
+        }
+
+        let c8 = debugger.emulator_mut();
+        c8.frame_cycle();
+        if let Some(wav) = wav.as_mut() {
+            wav.write(&c8.take_audio())?;
+        }
+        frame += 1;
+    };
+
+    if let Some(wav) = wav {
+        wav.finish()?;
+    }
+
+    let c8 = debugger.emulator();
+    if let Some(path) = &options.screen_path {
+        let format = options.format.unwrap_or_else(|| Format::of_path(path));
+        let bytes = format.encode(&c8.get_screen());
+        if path == "-" {
+            io::stdout().write_all(&bytes)?;
+        } else {
+            fs::write(path, bytes)?;
+        }
+    }
+
+    let (reason, status) = match stop {
+        Stop::Frames if options.until_pc.is_some() || options.until_halt => {
+            ("timeout".to_string(), EXIT_TIMEOUT)
+        }
+        Stop::Frames => ("frames".to_string(), 0),
+        Stop::Pc(addr) => (format!("pc {addr:#06X}"), 0),
+        Stop::Halted => ("halted".to_string(), 0),
+        Stop::Fault(err) => (format!("fault: {err}"), EXIT_FAULT),
+    };
+    // the report goes to stderr when stdout has the screen
+    let mut report: Box<dyn Write> = match options.screen_path.as_deref() {
+        Some("-") => Box::new(io::stderr()),
+        _ => Box::new(io::stdout()),
+    };
+    writeln!(report, "stop: {reason}")?;
+    writeln!(report, "frames: {frame}")?;
+    writeln!(report, "pc: {:#06X}", c8.pc())?;
+    writeln!(report, "hash: {:016x}", c8.state_hash())?;
+
+    Ok(status)
+}
+
+fn conformance(args: impl Iterator<Item = String>) -> ExitCode {
+    let mut dir = None;
+    let mut json = false;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            _ if arg.starts_with("--") || dir.is_some() => {
+                eprintln!("Unexpected argument {arg}\n{USAGE}");
+                return ExitCode::from(EXIT_USAGE);
+            }
+            _ => dir = Some(arg),
+        }
+    }
+    let Some(dir) = dir else {
+        eprintln!("Didn't get a directory\n{USAGE}");
+        return ExitCode::from(EXIT_USAGE);
+    };
+
+    let path = Path::new(&dir).join(MANIFEST_NAME);
+    let manifest = match fs::read_to_string(&path) {
+        Ok(source) => Manifest::parse(&source).map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    let manifest = match manifest {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            eprintln!("error: {}: {err}", path.display());
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let report = manifest.run(Path::new(&dir));
+    if json {
+        print!("{}", report.to_json());
+    } else {
+        print!("{}", report.to_text());
+    }
+    if report.is_success() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_FAULT)
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    io::{self, Stdout, Write},
+    mem,
+    ops::RangeInclusive,
+    thread::sleep,
+    time::Instant,
+};
+
+use chip8_core::{C8Emulator, Debugger, Instruction, Scheduler, StopReason, WatchKind};
+use termion::{color, cursor, event::Key, raw::RawTerminal, screen::AlternateScreen};
+
+use crate::{map_ch8_key, press_ch8_key, refresh_screen, FRAME};
+
+/// Instructions shown before the program counter in the disassembly.
+const DISASM_BEFORE: u16 = 4;
+/// Instructions shown from the program counter on.
+const DISASM_AFTER: u16 = 8;
+
+const HELP: &str = "s step, n next, o out, r until return, c continue, b/d <addr> break/delete, \
+                    w/uw <addr>[-<end>] [r|w|c] watch/unwatch, q quit";
+
+/// Run the emulator under the debugger, in a split view with the screen,
+/// the registers and the disassembly around PC on the right, and a
+/// command prompt below. The emulator starts paused, F9 pauses it again.
+pub(crate) fn run(
+    stdout: &mut AlternateScreen<RawTerminal<Stdout>>,
+    keys: &mut impl Iterator<Item = io::Result<Key>>,
+    ch8: C8Emulator,
+    mut scheduler: Scheduler,
+) -> io::Result<()> {
+    let mut dbg = Debugger::new(ch8);
+    let mut last_update = Instant::now();
+    let mut prompt = String::new();
+    let mut message = format!("Paused: {HELP}");
+    let mut last_key: Option<u8> = None;
+
+    loop {
+        if let Some(key) = keys.next() {
+            match key? {
+                Key::Esc => break,
+                Key::F(9) => {
+                    dbg.pause();
+                    message = "Paused".to_string();
+                }
+                // while paused the keyboard goes to the prompt
+                Key::Char('\n') if dbg.is_paused() => {
+                    let command = mem::take(&mut prompt);
+                    match execute(&mut dbg, command.trim()) {
+                        Some(reply) => message = reply,
+                        None => break,
+                    }
+                }
+                Key::Backspace if dbg.is_paused() => {
+                    prompt.pop();
+                }
+                Key::Char(chr) if dbg.is_paused() => prompt.push(chr),
+                Key::Char(chr) => {
+                    press_ch8_key(dbg.emulator_mut(), &mut last_key, map_ch8_key(chr))
+                }
+                _ => (),
+            }
+        }
+
+        // the time spent paused isn't caught up on
+        let now = Instant::now();
+        if !dbg.is_paused() {
+            if let Err(reason) = scheduler.advance_debugger(&mut dbg, now - last_update) {
+                message = describe(reason);
+            }
+        }
+        last_update = now;
+
+        refresh_screen(stdout, dbg.emulator())?;
+        write_panel(stdout, &dbg)?;
+        write_prompt(stdout, &dbg, &message, &prompt)?;
+        sleep(FRAME.saturating_sub(now.elapsed()));
+    }
+
+    Ok(())
+}
+
+/// Start a command typed at the prompt and return the message to show,
+/// `None` to quit.
+fn execute(dbg: &mut Debugger, command: &str) -> Option<String> {
+    let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+
+    let reply = match name {
+        "s" | "step" => {
+            dbg.step();
+            "Stepping".to_string()
+        }
+        "n" | "next" => {
+            dbg.step_over();
+            "Stepping over".to_string()
+        }
+        "o" | "out" => {
+            dbg.step_out();
+            "Running until the subroutine returns".to_string()
+        }
+        "r" | "ret" => {
+            dbg.run_until_return();
+            "Running until the next return".to_string()
+        }
+        "c" | "continue" => {
+            dbg.resume();
+            "Running, F9 to pause".to_string()
+        }
+        "b" | "break" | "d" | "delete" => match parse_addr(arg) {
+            Some(addr) if name.starts_with('b') => {
+                dbg.add_breakpoint(addr);
+                format!("Breakpoint at 0x{addr:03X}")
+            }
+            Some(addr) if dbg.remove_breakpoint(addr) => {
+                format!("Deleted the breakpoint at 0x{addr:03X}")
+            }
+            Some(addr) => format!("No breakpoint at 0x{addr:03X}"),
+            None => format!("Invalid address `{arg}`"),
+        },
+        "w" | "watch" | "uw" | "unwatch" => match parse_watch(arg) {
+            Some((range, kind)) if name.starts_with('w') => {
+                let reply = format!(
+                    "Watching {kind:?} at {:03X}-{:03X}",
+                    range.start(),
+                    range.end()
+                );
+                dbg.emulator_mut().add_watchpoint(range, kind);
+                reply
+            }
+            Some((range, kind)) if dbg.emulator_mut().remove_watchpoint(range.clone(), kind) => {
+                format!(
+                    "Deleted the watchpoint at {:03X}-{:03X}",
+                    range.start(),
+                    range.end()
+                )
+            }
+            Some(_) => "No such watchpoint".to_string(),
+            None => format!("Invalid watchpoint `{arg}`"),
+        },
+        "q" | "quit" => return None,
+        "" | "h" | "help" => HELP.to_string(),
+        _ => format!("Unknown command `{name}`: {HELP}"),
+    };
+    Some(reply)
+}
+
+/// Addresses are hexadecimal, with or without the 0x prefix.
+fn parse_addr(arg: &str) -> Option<u16> {
+    let arg = arg.trim();
+    let hex = arg.strip_prefix("0x").unwrap_or(arg);
+    u16::from_str_radix(hex, 16).ok()
+}
+
+/// `<addr>[-<end>] [r|w|c]`, watching writes unless asked otherwise.
+fn parse_watch(arg: &str) -> Option<(RangeInclusive<u16>, WatchKind)> {
+    let (range, kind) = arg.trim().split_once(' ').unwrap_or((arg.trim(), "w"));
+    let kind = match kind.trim() {
+        "r" => WatchKind::Read,
+        "w" => WatchKind::Write,
+        "c" => WatchKind::Change,
+        _ => return None,
+    };
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    Some((parse_addr(start)?..=parse_addr(end)?, kind))
+}
+
+fn describe(reason: StopReason) -> String {
+    match reason {
+        StopReason::Step => "Paused after the step".to_string(),
+        StopReason::Breakpoint(addr) => format!("Breakpoint at 0x{addr:03X}"),
+        StopReason::AtReturn => "Paused on the return".to_string(),
+        StopReason::Returned => "Returned from the subroutine".to_string(),
+        StopReason::Watchpoint(hit) => {
+            let instruction = match hit.instruction {
+                Some(instruction) => instruction.to_string(),
+                None => "loading the ROM".to_string(),
+            };
+            format!(
+                "Watchpoint: {:?} at {:03X} ({:02X} -> {:02X}) by {instruction} at {:03X}",
+                hit.kind, hit.addr, hit.old, hit.new, hit.pc
+            )
+        }
+        StopReason::Fault(err) => format!("FAULT: {err}"),
+        StopReason::Halted => "The program exited".to_string(),
+    }
+}
+
+/// Print the registers, the stack and the disassembly right of the screen.
+fn write_panel(
+    stdout: &mut AlternateScreen<RawTerminal<Stdout>>,
+    dbg: &Debugger,
+) -> io::Result<()> {
+    let ch8 = dbg.emulator();
+    let col = ch8.get_screen().width() as u16 + 3;
+
+    let mut lines = vec![
+        format!("PC {:04X}  I {:04X}", ch8.pc(), ch8.i_reg()),
+        format!(
+            "DT {:02X}    ST {:02X}",
+            ch8.delay_timer(),
+            ch8.sound_timer()
+        ),
+    ];
+    for (row, vals) in ch8.v_regs().chunks(4).enumerate() {
+        let regs: Vec<String> = vals
+            .iter()
+            .enumerate()
+            .map(|(idx, val)| format!("V{:X} {val:02X}", row * 4 + idx))
+            .collect();
+        lines.push(regs.join(" "));
+    }
+    let stack: Vec<String> = ch8
+        .stack()
+        .iter()
+        .map(|addr| format!("{addr:03X}"))
+        .collect();
+    lines.push(format!("Stack {}", stack.join(" ")));
+    lines.push(String::new());
+
+    let ram = ch8.ram();
+    let start = ch8.pc().saturating_sub(2 * DISASM_BEFORE);
+    for addr in (start..ch8.pc().saturating_add(2 * DISASM_AFTER)).step_by(2) {
+        let Some(bytes) = ram.get(addr as usize..addr as usize + 2) else {
+            break;
+        };
+        let op_code = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let text = match Instruction::decode(op_code) {
+            Ok(instruction) => instruction.to_string(),
+            Err(_) => format!("DW 0x{op_code:04X}"),
+        };
+        let marker = if addr == ch8.pc() { '>' } else { ' ' };
+        let breakpoint = if dbg.breakpoints().any(|bp| bp == addr) {
+            '*'
+        } else {
+            ' '
+        };
+        lines.push(format!(
+            "{marker}{breakpoint}{addr:03X}  {op_code:04X}  {text}"
+        ));
+    }
+
+    write!(stdout, "{}", color::Fg(color::Reset))?;
+    for (row, line) in lines.iter().enumerate() {
+        write!(stdout, "{}{line}", cursor::Goto(col, row as u16 + 1))?;
+    }
+    stdout.flush()
+}
+
+fn write_prompt(
+    stdout: &mut AlternateScreen<RawTerminal<Stdout>>,
+    dbg: &Debugger,
+    message: &str,
+    prompt: &str,
+) -> io::Result<()> {
+    let height = dbg.emulator().get_screen().height() as u16;
+
+    write!(
+        stdout,
+        "{}{}{message}{}",
+        cursor::Goto(1, height + 2),
+        color::Fg(color::Reset),
+        cursor::Goto(1, height + 3),
+    )?;
+    if dbg.is_paused() {
+        write!(stdout, "> {prompt}")?;
+    }
+    stdout.flush()
+}
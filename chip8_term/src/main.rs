@@ -1,11 +1,18 @@
+mod debug;
+
 use std::{
-    env, fs,
-    io::{self, Stdout, Write},
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Stdout, Write},
     thread::sleep,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use chip8_core::{C8Emulator, SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip8_core::{
+    C8Emulator, C8Error, CosmacVip, Machine, Movie, MovieStatus, Quirks, Scheduler, Seed, Snapshot,
+    Syntax, TraceFormat, Tracer, WavWriter, TIMER_HZ,
+};
+use chip8_gdb::GdbServer;
 use termion::{
     color, cursor,
     input::TermRead,
@@ -14,81 +21,431 @@ use termion::{
 };
 
 const TICK_PER_FRAME: u8 = 10;
+/// The same speed as running `TICK_PER_FRAME` cycles every frame.
+const DEFAULT_IPS: u32 = TICK_PER_FRAME as u32 * TIMER_HZ;
+const FRAME: Duration = Duration::from_micros(16_667);
 
-fn main() -> io::Result<()> {
-    let mut ch8 = C8Emulator::new();
+/// 30 seconds of history at 60 frames per second.
+const REWIND_FRAMES: usize = 60 * 30;
+/// The terminal only sends key presses, so rewinding goes on while the
+/// auto-repeat of Backspace keeps arriving within this time.
+const REWIND_HOLD: Duration = Duration::from_millis(150);
 
+fn main() -> io::Result<()> {
     let mut args = env::args();
 
     args.next(); // just ignore the first item, it is the program name.
 
-    let file_path = match args.next() {
+    let mut args = args.peekable();
+    if args.peek().map(String::as_str) == Some("disasm") {
+        args.next();
+        return disasm(args);
+    }
+    if args.peek().map(String::as_str) == Some("asm") {
+        args.next();
+        return asm(args);
+    }
+
+    let mut file_path = None;
+    let mut quirks = Quirks::default();
+    let mut xo_chip = false;
+    let mut debug = false;
+    let mut gdb_port = None;
+    let mut trace_path = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut ips = DEFAULT_IPS;
+    let mut vip_timing = false;
+    let mut vip_interpreter = None;
+    let mut vip_monitor = None;
+    let mut audio_path = None;
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut seed = Seed::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().expect("Didn't get a quirks preset");
+                quirks =
+                    Quirks::preset(&name).unwrap_or_else(|| panic!("Unknown quirks preset {name}"));
+            }
+            "--xo-chip" => {
+                xo_chip = true;
+                quirks = Quirks::xo_chip();
+            }
+            "--debug" => debug = true,
+            "--gdb" => {
+                let port = args.next().expect("Didn't get a port");
+                gdb_port = Some(port.parse().expect("Invalid port"));
+            }
+            "--trace" => trace_path = Some(args.next().expect("Didn't get a trace path")),
+            "--trace-binary" => trace_format = TraceFormat::Binary,
+            "--audio-out" => audio_path = Some(args.next().expect("Didn't get a WAV path")),
+            "--record" => record_path = Some(args.next().expect("Didn't get a movie path")),
+            "--play" => play_path = Some(args.next().expect("Didn't get a movie path")),
+            "--seed" => {
+                let val = args.next().expect("Didn't get a seed");
+                seed = Seed::Fixed(val.parse().expect("Invalid seed"));
+            }
+            "--random-seed" => seed = Seed::Entropy,
+            "--vip-timing" => vip_timing = true,
+            "--vip" => vip_interpreter = Some(args.next().expect("Didn't get an interpreter path")),
+            "--vip-monitor" => vip_monitor = Some(args.next().expect("Didn't get a monitor path")),
+            "--ips" => {
+                let val = args.next().expect("Didn't get the instructions per second");
+                ips = val.parse().expect("Invalid instructions per second");
+            }
+            _ => file_path = Some(arg),
+        }
+    }
+
+    let file_path = match file_path {
         Some(arg) => arg,
         None => panic!("Didn't get a file path"),
     };
 
-    let rom = fs::read(file_path).expect("Error reading rom");
+    if let Some(interpreter_path) = vip_interpreter {
+        // the original interpreter runs on the emulated hardware instead
+        let monitor_path = vip_monitor.expect("The VIP needs its monitor ROM, use --vip-monitor");
+        let monitor = fs::read(monitor_path).expect("Error reading the monitor ROM");
+        let interpreter = fs::read(interpreter_path).expect("Error reading the interpreter");
+        let rom = fs::read(&file_path).expect("Error reading rom");
+
+        let mut vip = CosmacVip::new(&monitor, &interpreter)
+            .and_then(|mut vip| vip.load(&rom).map(|_| vip))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let mut stdout = io::stdout().into_raw_mode()?.into_alternate_screen()?;
+        let mut keys = termion::async_stdin().keys();
+        return run_vip(&mut stdout, &mut keys, &mut vip);
+    }
+
+    let movie = match play_path {
+        Some(path) => {
+            let bytes = fs::read(path).expect("Error reading the movie");
+            let movie = Movie::from_bytes(&bytes)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            // the movie only plays with the settings it was recorded with
+            quirks = movie.quirks();
+            xo_chip = movie.is_xo_chip();
+            Some(movie)
+        }
+        None => None,
+    };
+
+    let mut ch8 = C8Emulator::with_quirks(quirks).with_seed(seed);
+    ch8.set_xo_chip(xo_chip);
+
+    let rom = fs::read(&file_path).expect("Error reading rom");
+
+    ch8.load(&rom)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    ch8.enable_rewind(REWIND_FRAMES);
+    if let Some(movie) = movie {
+        ch8.play_movie(movie, &rom)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    } else if record_path.is_some() {
+        ch8.record_movie(&rom);
+    }
+    if let Some(path) = trace_path {
+        // the buffer is flushed when the emulator is dropped
+        let sink = BufWriter::new(File::create(path)?);
+        ch8.set_tracer(Tracer::new(sink, trace_format));
+    }
+    let mut wav = match audio_path {
+        Some(path) => {
+            // the sizes in the header are written when it's dropped
+            ch8.capture_audio(true);
+            let sink = BufWriter::new(File::create(path)?);
+            Some(WavWriter::new(sink, ch8.sample_rate())?)
+        }
+        None => None,
+    };
+
+    if let Some(port) = gdb_port {
+        println!("Waiting for GDB on 127.0.0.1:{port}");
+        let server = GdbServer::accept(port, ch8)?;
+
+        let mut stdout = io::stdout().into_raw_mode()?.into_alternate_screen()?;
+        let mut keys = termion::async_stdin().keys();
+        return run_gdb(&mut stdout, &mut keys, server);
+    }
 
     // Set up terminal
     let mut stdout = io::stdout().into_raw_mode()?.into_alternate_screen()?;
 
-    ch8.load(&rom);
-
     let mut keys = termion::async_stdin().keys();
+
+    let mut scheduler = if vip_timing {
+        Scheduler::vip()
+    } else {
+        Scheduler::new(ips)
+    };
+    if debug {
+        return debug::run(&mut stdout, &mut keys, ch8, scheduler);
+    }
+
     let mut last_key: Option<u8> = None;
+    let mut status = String::new();
+    let mut rewind_pressed: Option<Instant> = None;
+    let mut last_update = Instant::now();
 
     loop {
         if let Some(key) = keys.next() {
             let pressed_key = match key.unwrap() {
                 termion::event::Key::Char(chr) => map_ch8_key(chr),
                 termion::event::Key::Esc => break,
+                // going back in time would break the movie
+                termion::event::Key::Backspace | termion::event::Key::F(5..=8)
+                    if ch8.movie_status().is_some() =>
+                {
+                    None
+                }
+                termion::event::Key::Backspace => {
+                    rewind_pressed = Some(Instant::now());
+                    None
+                }
+                termion::event::Key::F(slot @ 1..=4) => {
+                    status = match save_slot(&file_path, slot, &ch8) {
+                        Ok(()) => format!("Saved slot {slot}"),
+                        Err(err) => format!("Can't save slot {slot}: {err}"),
+                    };
+                    None
+                }
+                termion::event::Key::F(key @ 5..=8) => {
+                    let slot = key - 4;
+                    status = match load_slot(&file_path, slot, &mut ch8) {
+                        Ok(()) => format!("Loaded slot {slot}"),
+                        Err(err) => format!("Can't load slot {slot}: {err}"),
+                    };
+                    None
+                }
                 _ => None,
             };
 
-            if let Some(key) = last_key {
-                ch8.press_key(key as usize, false);
-            };
+            press_ch8_key(&mut ch8, &mut last_key, pressed_key);
+        }
 
-            if let Some(key) = pressed_key {
-                ch8.press_key(key as usize, true);
-                last_key = Some(key);
+        if rewind_pressed.is_some_and(|pressed| pressed.elapsed() < REWIND_HOLD) {
+            // go back one frame for every frame that would have run
+            status = match ch8.rewind(1) {
+                Ok(_) => format!("Rewinding ({} frames left)", ch8.rewind_len()),
+                Err(err) => format!("Can't rewind: {err}"),
             };
+
+            refresh_screen(&mut stdout, &ch8)?;
+            write_status(&mut stdout, &ch8, &status)?;
+            sleep(FRAME);
+            last_update = Instant::now();
+            continue;
         }
 
-        for _ in 0..TICK_PER_FRAME {
-            ch8.cpu_cycle();
-            sleep(Duration::from_millis(1));
+        // the scheduler keeps the speed whatever the rendering takes
+        let now = Instant::now();
+        let advanced = scheduler.advance(&mut ch8, now - last_update);
+        if let Some(wav) = wav.as_mut() {
+            wav.write(&ch8.take_audio())?;
+        }
+        if let Some(movie_status) = ch8.movie_status() {
+            status = describe_movie(movie_status);
         }
-        ch8.frame_cycle();
+        if let Err(err) = advanced {
+            save_movie(record_path.as_deref(), &mut ch8)?;
+            refresh_screen(&mut stdout, &ch8)?;
+            return show_fault(&mut stdout, &mut keys, &ch8, err);
+        }
+        last_update = now;
 
         refresh_screen(&mut stdout, &ch8)?;
+        write_status(&mut stdout, &ch8, &status)?;
+        sleep(FRAME.saturating_sub(now.elapsed()));
+    }
+
+    save_movie(record_path.as_deref(), &mut ch8)
+}
+
+/// Write the movie being recorded, if any.
+fn save_movie(path: Option<&str>, ch8: &mut C8Emulator) -> io::Result<()> {
+    match (path, ch8.take_movie()) {
+        (Some(path), Some(movie)) => {
+            let bytes = movie
+                .to_bytes()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            fs::write(path, bytes)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn describe_movie(movie_status: MovieStatus) -> String {
+    match movie_status {
+        MovieStatus::Recording { frame } => format!("Recording frame {frame}"),
+        MovieStatus::Playing { frame, frames } => format!("Playing frame {frame}/{frames}"),
+        MovieStatus::Finished { frames } => format!("Movie finished after {frames} frames"),
+        MovieStatus::Desynced { frame } => format!("Movie desynced at frame {frame}"),
+    }
+}
+
+/// Show the screen while GDB drives the emulator, until it detaches
+/// or Esc is pressed.
+fn run_gdb(
+    stdout: &mut AlternateScreen<RawTerminal<Stdout>>,
+    keys: &mut impl Iterator<Item = io::Result<termion::event::Key>>,
+    mut server: GdbServer,
+) -> io::Result<()> {
+    let mut last_key: Option<u8> = None;
+
+    while server.poll()? {
+        if let Some(key) = keys.next() {
+            match key? {
+                termion::event::Key::Esc => break,
+                termion::event::Key::Char(chr) => {
+                    press_ch8_key(server.emulator_mut(), &mut last_key, map_ch8_key(chr))
+                }
+                _ => (),
+            }
+        }
+
+        server.run_frame(TICK_PER_FRAME as usize)?;
+        refresh_screen(stdout, server.emulator())?;
+        let status = if server.is_running() {
+            "GDB: running"
+        } else {
+            "GDB: stopped"
+        };
+        write_status(stdout, server.emulator(), status)?;
+        sleep(Duration::from_millis(16));
     }
 
     Ok(())
 }
 
+/// Run the ROM on the emulated COSMAC VIP, a frame at a time.
+fn run_vip(
+    stdout: &mut AlternateScreen<RawTerminal<Stdout>>,
+    keys: &mut impl Iterator<Item = io::Result<termion::event::Key>>,
+    vip: &mut CosmacVip,
+) -> io::Result<()> {
+    let mut last_key: Option<u8> = None;
+
+    loop {
+        if let Some(key) = keys.next() {
+            match key? {
+                termion::event::Key::Esc => break,
+                termion::event::Key::Char(chr) => {
+                    press_ch8_key(vip, &mut last_key, map_ch8_key(chr))
+                }
+                _ => (),
+            }
+        }
+
+        let start = Instant::now();
+        vip.run_frame();
+        refresh_screen(stdout, vip)?;
+        let status = if vip.is_sounding() {
+            "COSMAC VIP: beep"
+        } else {
+            "COSMAC VIP"
+        };
+        write_status(stdout, vip, status)?;
+        sleep(FRAME.saturating_sub(start.elapsed()));
+    }
+
+    Ok(())
+}
+
+/// Save states live next to the ROM, one file per slot.
+fn slot_path(rom_path: &str, slot: u8) -> String {
+    format!("{rom_path}.state{slot}")
+}
+
+fn save_slot(rom_path: &str, slot: u8, ch8: &C8Emulator) -> io::Result<()> {
+    let bytes = ch8
+        .snapshot()
+        .to_bytes()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    fs::write(slot_path(rom_path, slot), bytes)
+}
+
+fn load_slot(rom_path: &str, slot: u8, ch8: &mut C8Emulator) -> io::Result<()> {
+    let bytes = fs::read(slot_path(rom_path, slot))?;
+    let snapshot = Snapshot::from_bytes(&bytes)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    ch8.restore(&snapshot);
+    Ok(())
+}
+
+/// Print a message below the screen.
+fn write_status(
+    stdout: &mut AlternateScreen<RawTerminal<Stdout>>,
+    ch8: &impl Machine,
+    status: &str,
+) -> io::Result<()> {
+    let height = ch8.get_screen().height() as u16;
+
+    write!(
+        stdout,
+        "{}{}{}",
+        cursor::Goto(1, height + 2),
+        color::Fg(color::Reset),
+        status
+    )?;
+    stdout.flush()
+}
+
+/// Print the fault below the last rendered frame and wait for Esc,
+/// so the user can look at the screen the ROM left behind.
+fn show_fault(
+    stdout: &mut AlternateScreen<RawTerminal<Stdout>>,
+    keys: &mut impl Iterator<Item = io::Result<termion::event::Key>>,
+    ch8: &impl Machine,
+    err: C8Error,
+) -> io::Result<()> {
+    let height = ch8.get_screen().height() as u16;
+
+    write!(
+        stdout,
+        "{}{}FAULT: {}{}{}Press Esc to quit.",
+        cursor::Goto(1, height + 2),
+        color::Fg(color::Red),
+        err,
+        cursor::Goto(1, height + 3),
+        color::Fg(color::Reset),
+    )?;
+    stdout.flush()?;
+
+    loop {
+        match keys.next() {
+            Some(key) => {
+                if let termion::event::Key::Esc = key? {
+                    return Ok(());
+                }
+            }
+            None => sleep(Duration::from_millis(10)),
+        }
+    }
+}
+
 fn refresh_screen(
     stdout: &mut AlternateScreen<RawTerminal<Stdout>>,
-    ch8: &C8Emulator,
+    ch8: &impl Machine,
 ) -> io::Result<()> {
     let screen = ch8.get_screen();
 
     write!(stdout, "{}", termion::clear::All)?;
     stdout.flush()?;
 
-    for y in 0..SCREEN_HEIGHT {
-        let line: String = screen[(y * SCREEN_WIDTH)..((y + 1) * SCREEN_WIDTH)]
-            .iter()
-            .map(|x| if *x { '*' } else { ' ' })
-            .collect();
+    for (y, row) in screen.rows().enumerate() {
+        write!(stdout, "{}", cursor::Goto(1, y as u16 + 1))?;
 
-        write!(
-            stdout,
-            "{}{}{}",
-            cursor::Goto(1, y as u16 + 1),
-            color::Fg(color::Green),
-            line
-        )?;
+        for px in row {
+            // palette for the four colors of the XO-CHIP planes
+            match px {
+                0 => write!(stdout, " ")?,
+                1 => write!(stdout, "{}*", color::Fg(color::Green))?,
+                2 => write!(stdout, "{}*", color::Fg(color::Red))?,
+                _ => write!(stdout, "{}*", color::Fg(color::Yellow))?,
+            }
+        }
     }
 
     stdout.flush()?;
@@ -96,6 +453,50 @@ fn refresh_screen(
     Ok(())
 }
 
+/// `disasm <rom> [--octo]`: print the listing of a ROM, in Cowgod
+/// syntax unless asked otherwise.
+fn disasm(args: impl Iterator<Item = String>) -> io::Result<()> {
+    let mut file_path = None;
+    let mut syntax = Syntax::Cowgod;
+    for arg in args {
+        match arg.as_str() {
+            "--octo" => syntax = Syntax::Octo,
+            _ => file_path = Some(arg),
+        }
+    }
+
+    let file_path = file_path.expect("Didn't get a file path");
+    let rom = fs::read(file_path).expect("Error reading rom");
+
+    write!(io::stdout(), "{}", chip8_core::disassemble(&rom, syntax))
+}
+
+/// `asm <source> <rom>`: assemble a source file into a ROM.
+fn asm(mut args: impl Iterator<Item = String>) -> io::Result<()> {
+    let source_path = args.next().expect("Didn't get a source path");
+    let rom_path = args.next().expect("Didn't get a rom path");
+
+    let source = fs::read_to_string(&source_path)?;
+    let rom = chip8_core::assemble(&source).map_err(|err| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{source_path}: {err}"))
+    })?;
+    fs::write(rom_path, rom)
+}
+
+/// The terminal doesn't report key releases, so a key stays pressed
+/// until the next one arrives.
+fn press_ch8_key(ch8: &mut impl Machine, last_key: &mut Option<u8>, pressed_key: Option<u8>) {
+    // keys come from map_ch8_key, so they are always valid
+    if let Some(key) = *last_key {
+        ch8.press_key(key as usize, false).unwrap();
+    };
+
+    if let Some(key) = pressed_key {
+        ch8.press_key(key as usize, true).unwrap();
+        *last_key = Some(key);
+    };
+}
+
 fn map_ch8_key(chr: char) -> Option<u8> {
     match chr {
         '1' => Some(0x1),